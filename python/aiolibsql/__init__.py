@@ -0,0 +1,3 @@
+from ._aiolibsql import *  # noqa: F401,F403
+from ._aiolibsql import VERSION as __version__  # noqa: F401
+from .dbapi import *  # noqa: F401,F403
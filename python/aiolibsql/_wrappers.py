@@ -0,0 +1,269 @@
+"""
+Cursor/Statement/Connection wrappers shared by `aiolibsql.sync` and `aiolibsql.anyio`.
+
+Each method hands a factory for the native awaitable to `self._call`, which a
+subclass sets to its call strategy: `sync` runs it to completion and returns
+the result, `anyio` returns an awaitable for it. Factories are only called
+inside `_call`, so the native call starts on the event loop that awaits it.
+"""
+
+
+async def _then(awaitable, fn):
+    return fn(await awaitable)
+
+
+class Cursor:
+    def __init__(self, connection, cursor):
+        self.connection = connection
+        self._cursor = cursor
+
+    @property
+    def description(self):
+        return self._cursor.description
+
+    @property
+    def rowcount(self):
+        return self._cursor.rowcount
+
+    @property
+    def column_metadata(self):
+        return self._cursor.column_metadata
+
+    @property
+    def last_sql(self):
+        return self._cursor.last_sql
+
+    @property
+    def expanded_sql(self):
+        return self._cursor.expanded_sql
+
+    @property
+    def query_id(self):
+        return self._cursor.query_id
+
+    @property
+    def lastrowid(self):
+        return self._cursor.lastrowid
+
+    @property
+    def replication_index(self):
+        return self._cursor.replication_index
+
+    @property
+    def arraysize(self):
+        return self._cursor.arraysize
+
+    @arraysize.setter
+    def arraysize(self, value):
+        self._cursor.arraysize = value
+
+    def execute(self, sql, parameters=None, *, buffered=False, primary=False,
+                min_replication_index=None):
+        return self._call(lambda: _then(self._cursor.execute(
+            sql, parameters, buffered=buffered, primary=primary,
+            min_replication_index=min_replication_index), lambda _: self))
+
+    def executemany(self, sql, parameters, *, progress=None, progress_every=100):
+        return self._call(lambda: _then(self._cursor.executemany(
+            sql, parameters, progress=progress, progress_every=progress_every), lambda _: self))
+
+    def executescript(self, script, *, commit=True):
+        return self._call(lambda: _then(
+            self._cursor.executescript(script, commit=commit), lambda _: self))
+
+    def fetchone(self):
+        return self._call(lambda: self._cursor.fetchone())
+
+    def fetchmany(self, size=None):
+        return self._call(lambda: self._cursor.fetchmany(size))
+
+    def fetchall(self):
+        return self._call(lambda: self._cursor.fetchall())
+
+    def fetch_into(self, model, *, coerce=False):
+        return self._call(lambda: self._cursor.fetch_into(model, coerce=coerce))
+
+    def fetchall_into(self, model, *, coerce=False):
+        return self._call(lambda: self._cursor.fetchall_into(model, coerce=coerce))
+
+    def close(self):
+        return self._call(lambda: self._cursor.close())
+
+
+class Statement:
+    def __init__(self, stmt):
+        self._stmt = stmt
+
+    @property
+    def sql(self):
+        return self._stmt.sql
+
+    @property
+    def columns(self):
+        return self._stmt.columns
+
+    @property
+    def parameter_count(self):
+        return self._stmt.parameter_count
+
+    @property
+    def parameter_names(self):
+        return self._stmt.parameter_names
+
+    @property
+    def column_metadata(self):
+        return self._stmt.column_metadata
+
+    def execute(self, parameters=None):
+        return self._call(lambda: self._stmt.execute(parameters))
+
+    def query(self, parameters=None):
+        return self._call(lambda: self._stmt.query(parameters))
+
+    def reset(self):
+        self._stmt.reset()
+
+    def finalize(self):
+        self._stmt.finalize()
+
+
+class Connection:
+    _cursor_class = Cursor
+    _statement_class = Statement
+
+    def __init__(self, conn):
+        self._conn = conn
+
+    @property
+    def isolation_level(self):
+        return self._conn.isolation_level
+
+    @isolation_level.setter
+    def isolation_level(self, value):
+        self._conn.isolation_level = value
+
+    @property
+    def in_transaction(self):
+        return self._conn.in_transaction
+
+    @property
+    def queue_length(self):
+        return self._conn.queue_length
+
+    @property
+    def autocommit(self):
+        return self._conn.autocommit
+
+    @autocommit.setter
+    def autocommit(self, value):
+        self._conn.autocommit = value
+
+    @property
+    def total_changes(self):
+        return self._conn.total_changes
+
+    @property
+    def user_version(self):
+        return self._call(lambda: self._conn.user_version)
+
+    def set_user_version(self, version):
+        return self._call(lambda: self._conn.set_user_version(version))
+
+    def cursor(self, factory=None):
+        return (factory or self._cursor_class)(self, self._conn.cursor())
+
+    def open_cursors(self):
+        return self._conn.open_cursors()
+
+    def queries(self):
+        return self._conn.queries()
+
+    def cancel(self, query_id):
+        return self._conn.cancel(query_id)
+
+    def metrics(self):
+        return self._conn.metrics()
+
+    def execute(self, sql, parameters=None, *, buffered=False, primary=False,
+                min_replication_index=None):
+        return self.cursor().execute(
+            sql, parameters, buffered=buffered, primary=primary,
+            min_replication_index=min_replication_index)
+
+    def executemany(self, sql, parameters, *, progress=None, progress_every=100):
+        return self.cursor().executemany(
+            sql, parameters, progress=progress, progress_every=progress_every)
+
+    def executescript(self, script, *, commit=True):
+        return self.cursor().executescript(script, commit=commit)
+
+    def prepare(self, sql):
+        return self._call(lambda: _then(self._conn.prepare(sql), self._statement_class))
+
+    def query_cached(self, sql, parameters=None):
+        return self._call(lambda: self._conn.query_cached(sql, parameters))
+
+    def clear_result_cache(self):
+        self._conn.clear_result_cache()
+
+    def commit(self):
+        return self._call(lambda: self._conn.commit())
+
+    def rollback(self):
+        return self._call(lambda: self._conn.rollback())
+
+    def ping(self):
+        return self._call(lambda: self._conn.ping())
+
+    def optimize(self):
+        return self._call(lambda: self._conn.optimize())
+
+    def vacuum(self, *, into=None):
+        return self._call(lambda: self._conn.vacuum(into=into))
+
+    def integrity_check(self, max_errors=100):
+        return self._call(lambda: self._conn.integrity_check(max_errors))
+
+    def quick_check(self, max_errors=100):
+        return self._call(lambda: self._conn.quick_check(max_errors))
+
+    def set_wal_autocheckpoint(self, pages):
+        return self._call(lambda: self._conn.set_wal_autocheckpoint(pages))
+
+    def backup(self, target):
+        return self._call(lambda: self._conn.backup(target._conn))
+
+    def db_stats(self):
+        return self._call(lambda: self._conn.db_stats())
+
+    def insert_many(self, table, rows, *, columns=None, on_conflict=None, batch_size=500,
+                    progress=None):
+        return self._call(lambda: self._conn.insert_many(
+            table, rows, columns=columns, on_conflict=on_conflict, batch_size=batch_size,
+            progress=progress))
+
+    def reset(self):
+        return self._call(lambda: self._conn.reset())
+
+    def sync(self):
+        return self._call(lambda: self._conn.sync())
+
+    def replication_info(self):
+        return self._call(lambda: self._conn.replication_info())
+
+    def close(self):
+        return self._call(lambda: self._conn.close())
+
+    def _exit(self, exc_type):
+        # Commits or rolls back like `sqlite3`, then closes only if the connection
+        # was opened with `close_on_exit=True`.
+        async def exit():
+            if exc_type is None:
+                await self._conn.commit()
+            else:
+                await self._conn.rollback()
+            if self._conn.close_on_exit:
+                await self._conn.close()
+            return False
+
+        return self._call(exit)
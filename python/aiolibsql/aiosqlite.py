@@ -0,0 +1,300 @@
+"""
+aiolibsql.aiosqlite — drop-in replacement for the aiosqlite API
+
+Usage:
+    from aiolibsql import aiosqlite
+
+    async with aiosqlite.connect("data.db") as db:
+        db.row_factory = aiosqlite.Row
+        async with db.execute("SELECT * FROM users") as cursor:
+            async for row in cursor:
+                print(row["name"])
+
+Differences from aiosqlite:
+    - `row_factory = sqlite3.Row` is accepted and replaced by `aiosqlite.Row`,
+      since sqlite3.Row can only wrap real sqlite3 cursors.
+    - `row_factory = "namedtuple"` returns rows as namedtuples of the column names.
+    - `text_factory` only supports `str`.
+    - Parameters must be a sequence; a dict raises ProgrammingError.
+"""
+
+import sqlite3
+from collections import namedtuple
+from collections.abc import Mapping
+
+import aiolibsql
+from aiolibsql import (  # noqa: F401
+    DatabaseError,
+    Error,
+    IntegrityError,
+    OperationalError,
+    ProgrammingError,
+    Warning,
+)
+
+sqlite_version = aiolibsql.sqlite_version
+sqlite_version_info = aiolibsql.sqlite_version_info
+paramstyle = aiolibsql.paramstyle
+
+
+class Row(tuple):
+    """Row factory compatible with sqlite3.Row: index by position or column name."""
+
+    def __new__(cls, cursor, values):
+        row = super().__new__(cls, values)
+        row._keys = tuple(d[0] for d in cursor.description or ())
+        return row
+
+    def keys(self):
+        return list(self._keys)
+
+    def __getitem__(self, key):
+        if isinstance(key, str):
+            lowered = key.lower()
+            for i, name in enumerate(self._keys):
+                if name.lower() == lowered:
+                    return tuple.__getitem__(self, i)
+            raise IndexError("No item with that key")
+        return tuple.__getitem__(self, key)
+
+    def __repr__(self):
+        return f"<Row {dict(zip(self._keys, self))!r}>"
+
+
+def _namedtuple_row(cursor, values):
+    # The namedtuple class is built once per statement and reset by the execute methods.
+    if cursor._row_type is None:
+        fields = [d[0] for d in cursor.description or ()]
+        cursor._row_type = namedtuple("Row", fields, rename=True)
+    return cursor._row_type._make(values)
+
+
+def _resolve_row_factory(factory):
+    if factory == "namedtuple":
+        return _namedtuple_row
+    return Row if factory is sqlite3.Row else factory
+
+
+class _ContextManager:
+    """Awaitable that also works as `async with`, closing the result on exit."""
+
+    def __init__(self, coro):
+        self._coro = coro
+        self._obj = None
+
+    def __await__(self):
+        return self._coro.__await__()
+
+    async def __aenter__(self):
+        self._obj = await self._coro
+        return self._obj
+
+    async def __aexit__(self, exc_type, exc, tb):
+        await self._obj.close()
+        self._obj = None
+
+
+class Cursor:
+    def __init__(self, conn, cursor):
+        self._conn = conn
+        self._cursor = cursor
+        self._row_type = None
+        self.row_factory = conn.row_factory
+
+    @property
+    def connection(self):
+        return self._conn
+
+    @property
+    def description(self):
+        return self._cursor.description
+
+    @property
+    def rowcount(self):
+        return self._cursor.rowcount
+
+    @property
+    def lastrowid(self):
+        return self._cursor.lastrowid
+
+    @property
+    def arraysize(self):
+        return self._cursor.arraysize
+
+    @arraysize.setter
+    def arraysize(self, value):
+        self._cursor.arraysize = value
+
+    def _make_row(self, values):
+        factory = _resolve_row_factory(self.row_factory)
+        return values if factory is None else factory(self, values)
+
+    async def execute(self, sql, parameters=None):
+        await self._cursor.execute(sql, _params(parameters))
+        self._row_type = None
+        return self
+
+    async def executemany(self, sql, parameters):
+        await self._cursor.executemany(sql, [_params(p) for p in parameters])
+        self._row_type = None
+        return self
+
+    async def executescript(self, sql_script):
+        await self._cursor.executescript(sql_script)
+        self._row_type = None
+        return self
+
+    async def fetchone(self):
+        row = await self._cursor.fetchone()
+        return None if row is None else self._make_row(row)
+
+    async def fetchmany(self, size=None):
+        rows = await self._cursor.fetchmany(size)
+        return [self._make_row(r) for r in rows]
+
+    async def fetchall(self):
+        rows = await self._cursor.fetchall()
+        return [self._make_row(r) for r in rows]
+
+    async def close(self):
+        await self._cursor.close()
+
+    def __aiter__(self):
+        return self
+
+    async def __anext__(self):
+        row = await self.fetchone()
+        if row is None:
+            raise StopAsyncIteration
+        return row
+
+    async def __aenter__(self):
+        return self
+
+    async def __aexit__(self, exc_type, exc, tb):
+        await self.close()
+
+
+def _params(parameters):
+    if parameters is None:
+        return None
+    if isinstance(parameters, Mapping):
+        # tuple() of a dict would bind its keys; aiolibsql has no named binding.
+        raise ProgrammingError("named parameters are not supported; pass a sequence")
+    return parameters if isinstance(parameters, (list, tuple)) else tuple(parameters)
+
+
+class Connection:
+    def __init__(self, database, kwargs):
+        self._database = database
+        self._kwargs = kwargs
+        self._conn = None
+        self.row_factory = None
+        self.text_factory = str
+
+    async def _connect(self):
+        if self._conn is None:
+            self._conn = await aiolibsql.connect(self._database, **self._kwargs)
+        return self
+
+    def __await__(self):
+        return self._connect().__await__()
+
+    async def __aenter__(self):
+        return await self._connect()
+
+    async def __aexit__(self, exc_type, exc, tb):
+        await self.close()
+
+    @property
+    def in_transaction(self):
+        return self._conn.in_transaction
+
+    @property
+    def isolation_level(self):
+        return self._conn.isolation_level
+
+    @isolation_level.setter
+    def isolation_level(self, value):
+        self._conn.isolation_level = value
+
+    @property
+    def total_changes(self):
+        return self._conn.total_changes
+
+    async def _cursor(self):
+        return Cursor(self, self._conn.cursor())
+
+    def cursor(self):
+        return _ContextManager(self._cursor())
+
+    async def _execute(self, method, *args):
+        cursor = Cursor(self, self._conn.cursor())
+        await getattr(cursor, method)(*args)
+        return cursor
+
+    def execute(self, sql, parameters=None):
+        return _ContextManager(self._execute("execute", sql, parameters))
+
+    def executemany(self, sql, parameters):
+        return _ContextManager(self._execute("executemany", sql, parameters))
+
+    def executescript(self, sql_script):
+        return _ContextManager(self._execute("executescript", sql_script))
+
+    async def execute_insert(self, sql, parameters=None):
+        cursor = await self._execute("execute", sql, parameters)
+        rowid = cursor.lastrowid
+        await cursor.close()
+        return (rowid,)
+
+    async def execute_fetchall(self, sql, parameters=None):
+        cursor = await self._execute("execute", sql, parameters)
+        rows = await cursor.fetchall()
+        await cursor.close()
+        return rows
+
+    async def commit(self):
+        await self._conn.commit()
+
+    async def rollback(self):
+        await self._conn.rollback()
+
+    async def close(self):
+        if self._conn is not None:
+            await self._conn.close()
+            self._conn = None
+
+    async def iterdump(self):
+        """Yield the database as SQL text, like sqlite3.Connection.iterdump()."""
+        yield "BEGIN TRANSACTION;"
+        cursor = await self._conn.execute(
+            "SELECT name, type, sql FROM sqlite_master "
+            "WHERE sql NOT NULL AND type = 'table' AND name != 'sqlite_sequence' "
+            "ORDER BY name"
+        )
+        tables = await cursor.fetchall()
+        for name, _, sql in tables:
+            if name.startswith("sqlite_"):
+                continue
+            yield f"{sql};"
+            quoted = name.replace('"', '""')
+            cursor = await self._conn.execute(f'PRAGMA table_info("{quoted}")')
+            columns = [row[1].replace('"', '""') for row in await cursor.fetchall()]
+            select = ", ".join(f'quote("{c}")' for c in columns)
+            cursor = await self._conn.execute(f'SELECT {select} FROM "{quoted}"')
+            async for row in cursor:
+                yield f'INSERT INTO "{quoted}" VALUES({",".join(row)});'
+        cursor = await self._conn.execute(
+            "SELECT name, sql FROM sqlite_master "
+            "WHERE sql NOT NULL AND type IN ('index', 'trigger', 'view') "
+            "ORDER BY name"
+        )
+        for _, sql in await cursor.fetchall():
+            yield f"{sql};"
+        yield "COMMIT;"
+
+
+def connect(database, *, iter_chunk_size=64, loop=None, **kwargs):
+    """Open a connection; await the result or use it with `async with`."""
+    return Connection(database, kwargs)
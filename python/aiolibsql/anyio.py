@@ -0,0 +1,103 @@
+"""
+aiolibsql.anyio — Connection/Cursor wrappers that work under any async library
+
+Usage (trio, or anyio with either backend):
+    from aiolibsql import anyio as aiolibsql_anyio
+
+    async with await aiolibsql_anyio.connect("data.db") as conn:
+        cursor = await conn.execute("SELECT * FROM t")
+        async for row in cursor:
+            print(row)
+
+The native driver hands results back through asyncio. Under asyncio (including
+anyio's asyncio backend) calls are awaited directly; under trio they run on the
+private event loop thread used by `aiolibsql.sync` and the trio task waits for
+the result without blocking the trio loop.
+"""
+
+import asyncio
+import contextlib
+
+import aiolibsql
+
+from . import _wrappers
+from .sync import _get_loop
+
+
+def _in_asyncio():
+    try:
+        import sniffio
+    except ImportError:
+        return True
+    return sniffio.current_async_library() == "asyncio"
+
+
+async def _call(factory):
+    if _in_asyncio():
+        return await factory()
+
+    import trio
+
+    async def call():
+        return await factory()
+
+    future = asyncio.run_coroutine_threadsafe(call(), _get_loop())
+    token = trio.lowlevel.current_trio_token()
+    done = trio.Event()
+    future.add_done_callback(lambda _: token.run_sync_soon(done.set))
+    try:
+        await done.wait()
+    except BaseException:
+        future.cancel()
+        raise
+    return future.result()
+
+
+class Cursor(_wrappers.Cursor):
+    _call = staticmethod(_call)
+
+    def __aiter__(self):
+        return self
+
+    async def __anext__(self):
+        row = await self.fetchone()
+        if row is None:
+            raise StopAsyncIteration
+        return row
+
+
+class Statement(_wrappers.Statement):
+    _call = staticmethod(_call)
+
+
+class Connection(_wrappers.Connection):
+    _call = staticmethod(_call)
+    _cursor_class = Cursor
+    _statement_class = Statement
+
+    @contextlib.asynccontextmanager
+    async def transaction(self, behavior=None, *, concurrent=False):
+        tx = self._conn.transaction(behavior, concurrent=concurrent)
+        await _call(lambda: tx.__aenter__())
+        try:
+            yield self
+        except BaseException as e:
+            await _call(lambda: tx.__aexit__(type(e), e, e.__traceback__))
+            raise
+        await _call(lambda: tx.__aexit__(None, None, None))
+
+    async def __aenter__(self):
+        return self
+
+    async def __aexit__(self, exc_type, exc, tb):
+        return await self._exit(exc_type)
+
+
+async def connect(database, **kwargs):
+    """`aiolibsql.connect()` for any async library; takes the same arguments."""
+    return Connection(await _call(lambda: aiolibsql.connect(database, **kwargs)))
+
+
+async def close_all(timeout=None):
+    """`aiolibsql.close_all()` for any async library."""
+    await _call(lambda: aiolibsql.close_all(timeout))
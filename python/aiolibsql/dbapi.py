@@ -0,0 +1,95 @@
+"""
+aiolibsql.dbapi — PEP 249 type objects and constructors
+
+The type objects compare equal to the declared column types reported as
+``type_code`` in ``cursor.description``, following SQLite's type affinity
+rules:
+
+    if cursor.description[0][1] == aiolibsql.NUMBER:
+        ...
+
+Expression columns have no declared type (``type_code`` is ``None``) and
+compare equal to nothing.
+"""
+
+import datetime
+import time
+
+__all__ = [
+    "STRING",
+    "BINARY",
+    "NUMBER",
+    "DATETIME",
+    "ROWID",
+    "Date",
+    "Time",
+    "Timestamp",
+    "DateFromTicks",
+    "TimeFromTicks",
+    "TimestampFromTicks",
+    "Binary",
+]
+
+
+def _affinity(decl_type):
+    name = decl_type.upper()
+    if "INT" in name:
+        return "INTEGER"
+    if "CHAR" in name or "CLOB" in name or "TEXT" in name:
+        return "TEXT"
+    if "BLOB" in name or not name.strip():
+        return "BLOB"
+    if "REAL" in name or "FLOA" in name or "DOUB" in name:
+        return "REAL"
+    return "NUMERIC"
+
+
+def _is_datetime(decl_type):
+    return decl_type.split("(")[0].strip().upper() in ("DATE", "TIME", "TIMESTAMP", "DATETIME")
+
+
+class DBAPITypeObject:
+    def __init__(self, name, matches):
+        self.name = name
+        self._matches = matches
+
+    def __eq__(self, other):
+        if isinstance(other, DBAPITypeObject):
+            return self is other
+        if isinstance(other, str):
+            return self._matches(other)
+        return NotImplemented
+
+    def __hash__(self):
+        return hash(self.name)
+
+    def __repr__(self):
+        return f"<aiolibsql.{self.name}>"
+
+
+STRING = DBAPITypeObject("STRING", lambda t: _affinity(t) == "TEXT")
+BINARY = DBAPITypeObject("BINARY", lambda t: _affinity(t) == "BLOB")
+NUMBER = DBAPITypeObject(
+    "NUMBER",
+    lambda t: _affinity(t) in ("INTEGER", "REAL", "NUMERIC") and not _is_datetime(t),
+)
+DATETIME = DBAPITypeObject("DATETIME", _is_datetime)
+# Only INTEGER columns can alias the rowid (INTEGER PRIMARY KEY).
+ROWID = DBAPITypeObject("ROWID", lambda t: t.strip().upper() == "INTEGER")
+
+Date = datetime.date
+Time = datetime.time
+Timestamp = datetime.datetime
+Binary = bytes
+
+
+def DateFromTicks(ticks):
+    return Date(*time.localtime(ticks)[:3])
+
+
+def TimeFromTicks(ticks):
+    return Time(*time.localtime(ticks)[3:6])
+
+
+def TimestampFromTicks(ticks):
+    return Timestamp(*time.localtime(ticks)[:6])
@@ -0,0 +1,134 @@
+"""
+aiolibsql.migrations — versioned schema migrations
+
+Usage:
+    from aiolibsql import migrations
+
+    # every *.sql file in the directory, ordered by version prefix
+    await migrations.apply(conn, "migrations/")
+
+    # or an explicit list of .sql paths and (version, sql_or_callable) pairs
+    async def backfill(conn):
+        await conn.execute("UPDATE users SET active = 1")
+
+    await migrations.apply(conn, [
+        "migrations/0001_users.sql",
+        ("0002", "ALTER TABLE users ADD COLUMN active INTEGER"),
+        ("0003", backfill),
+    ])
+
+Versions are recorded in a tracking table (`_aiolibsql_migrations` by default)
+and each migration runs in its own BEGIN IMMEDIATE transaction together with
+its bookkeeping row, so a failed migration leaves no trace and processes
+migrating the same database at once apply each version only once. Migration scripts must not issue
+their own BEGIN/COMMIT.
+"""
+
+import inspect
+import os
+
+import aiolibsql
+
+DEFAULT_TABLE = "_aiolibsql_migrations"
+
+
+class MigrationError(aiolibsql.Error):
+    """Raised when a migration fails; `version` names the failing migration."""
+
+    def __init__(self, version, cause):
+        super().__init__(f"migration {version} failed: {cause}")
+        self.version = version
+        self.__cause__ = cause
+
+
+def _version_from_path(path):
+    stem = os.path.splitext(os.path.basename(path))[0]
+    return stem.split("_", 1)[0]
+
+
+def _sort_key(version):
+    return (0, int(version), version) if version.isdigit() else (1, 0, version)
+
+
+def _load(source):
+    if isinstance(source, (str, os.PathLike)) and os.path.isdir(source):
+        names = sorted(n for n in os.listdir(source) if n.endswith(".sql"))
+        source = [os.path.join(source, n) for n in names]
+
+    migrations = []
+    for item in source:
+        if isinstance(item, (str, os.PathLike)):
+            path = os.fspath(item)
+            with open(path, encoding="utf-8") as f:
+                migrations.append((_version_from_path(path), os.path.basename(path), f.read()))
+        else:
+            version, body = item
+            name = getattr(body, "__name__", str(version))
+            migrations.append((str(version), name, body))
+
+    seen = set()
+    for version, _, _ in migrations:
+        if version in seen:
+            raise ValueError(f"duplicate migration version: {version}")
+        seen.add(version)
+    migrations.sort(key=lambda m: _sort_key(m[0]))
+    return migrations
+
+
+def _quote(table):
+    return '"' + table.replace('"', '""') + '"'
+
+
+async def _ensure_table(conn, table):
+    await conn.execute(
+        f"CREATE TABLE IF NOT EXISTS {_quote(table)} ("
+        "version TEXT PRIMARY KEY, "
+        "name TEXT NOT NULL, "
+        "applied_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP)"
+    )
+
+
+async def applied(conn, *, table=DEFAULT_TABLE):
+    """Return the versions already recorded in the tracking table, in order."""
+    await _ensure_table(conn, table)
+    cursor = await conn.execute(f"SELECT version FROM {_quote(table)}")
+    versions = [row[0] for row in await cursor.fetchall()]
+    return sorted(versions, key=_sort_key)
+
+
+async def apply(conn, source, *, table=DEFAULT_TABLE):
+    """Apply every migration from `source` that has not run yet.
+
+    Returns the list of versions applied by this call.
+    """
+    migrations = _load(source)
+    done = set(await applied(conn, table=table))
+    newly_applied = []
+    for version, name, body in migrations:
+        if version in done:
+            continue
+        await conn.execute("BEGIN IMMEDIATE")
+        try:
+            # Another connection may have applied it since `done` was read; the write
+            # lock taken by BEGIN IMMEDIATE makes this check final.
+            cursor = await conn.execute(
+                f"SELECT 1 FROM {_quote(table)} WHERE version = ?", (version,)
+            )
+            if await cursor.fetchone() is not None:
+                await conn.execute("ROLLBACK")
+                continue
+            if callable(body):
+                result = body(conn)
+                if inspect.isawaitable(result):
+                    await result
+            else:
+                await conn.executescript(body, commit=False)
+            await conn.execute(
+                f"INSERT INTO {_quote(table)} (version, name) VALUES (?, ?)", (version, name)
+            )
+        except Exception as e:
+            await conn.execute("ROLLBACK")
+            raise MigrationError(version, e) from e
+        await conn.execute("COMMIT")
+        newly_applied.append(version)
+    return newly_applied
@@ -0,0 +1,98 @@
+"""
+aiolibsql.sync — blocking Connection/Cursor API
+
+Usage:
+    from aiolibsql import sync
+
+    with sync.connect("data.db") as conn:
+        conn.execute("INSERT INTO t VALUES (?)", (1,))
+        rows = conn.execute("SELECT * FROM t").fetchall()
+
+Calls run the regular async driver on a private event loop thread, so this
+module works in scripts, REPLs and inside threads that already run their own
+event loop (e.g. Django management commands or Jupyter).
+"""
+
+import asyncio
+import contextlib
+import threading
+
+import aiolibsql
+
+from . import _wrappers
+
+_loop = None
+_lock = threading.Lock()
+
+
+def _get_loop():
+    global _loop
+    with _lock:
+        if _loop is None:
+            _loop = asyncio.new_event_loop()
+            thread = threading.Thread(
+                target=_loop.run_forever, name="aiolibsql-sync", daemon=True
+            )
+            thread.start()
+        return _loop
+
+
+def _run(factory):
+    async def call():
+        return await factory()
+
+    return asyncio.run_coroutine_threadsafe(call(), _get_loop()).result()
+
+
+class Cursor(_wrappers.Cursor):
+    _call = staticmethod(_run)
+
+    def __iter__(self):
+        return self
+
+    def __next__(self):
+        row = self.fetchone()
+        if row is None:
+            raise StopIteration
+        return row
+
+
+class Statement(_wrappers.Statement):
+    _call = staticmethod(_run)
+
+
+class Connection(_wrappers.Connection):
+    _call = staticmethod(_run)
+    _cursor_class = Cursor
+    _statement_class = Statement
+
+    @_wrappers.Connection.user_version.setter
+    def user_version(self, value):
+        self.set_user_version(value)
+
+    @contextlib.contextmanager
+    def transaction(self, behavior=None, *, concurrent=False):
+        tx = self._conn.transaction(behavior, concurrent=concurrent)
+        _run(lambda: tx.__aenter__())
+        try:
+            yield self
+        except BaseException as e:
+            _run(lambda: tx.__aexit__(type(e), e, e.__traceback__))
+            raise
+        _run(lambda: tx.__aexit__(None, None, None))
+
+    def __enter__(self):
+        return self
+
+    def __exit__(self, exc_type, exc, tb):
+        return self._exit(exc_type)
+
+
+def connect(database, **kwargs):
+    """Blocking equivalent of `aiolibsql.connect()`; takes the same arguments."""
+    return Connection(_run(lambda: aiolibsql.connect(database, **kwargs)))
+
+
+def close_all(timeout=None):
+    """Blocking equivalent of `aiolibsql.close_all()`."""
+    _run(lambda: aiolibsql.close_all(timeout))
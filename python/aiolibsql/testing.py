@@ -0,0 +1,138 @@
+"""
+aiolibsql.testing — helpers for testing code that uses aiolibsql
+
+Usage:
+    # conftest.py
+    pytest_plugins = ["aiolibsql.testing"]
+
+    @pytest.fixture
+    def aiolibsql_schema():
+        return ["schema.sql", "fixtures/seed.sql"]
+
+    # test_users.py
+    async def test_insert(aiolibsql_db):
+        await aiolibsql_db.execute("INSERT INTO users (name) VALUES ('alice')")
+
+Outside pytest the same building blocks are plain async context managers:
+
+    async with testing.temp_database(schema="schema.sql") as conn:
+        async with testing.rollback_after(conn):
+            ...
+
+The fixtures need pytest-asyncio; importing the module works without it.
+"""
+
+import contextlib
+import os
+import shutil
+import tempfile
+
+import aiolibsql
+
+
+def _schema_files(source):
+    if isinstance(source, (str, os.PathLike)):
+        source = [source]
+    paths = []
+    for item in source:
+        path = os.fspath(item)
+        if os.path.isdir(path):
+            paths.extend(os.path.join(path, n) for n in sorted(os.listdir(path)) if n.endswith(".sql"))
+        else:
+            paths.append(path)
+    return paths
+
+
+async def load_schema(conn, source):
+    """Run SQL files against `conn` in order.
+
+    `source` is a path, a directory (every `*.sql` file, in name order) or a
+    list of either.
+    """
+    for path in _schema_files(source):
+        with open(path, encoding="utf-8") as f:
+            await conn.executescript(f.read())
+
+
+@contextlib.asynccontextmanager
+async def memory_database(*, schema=None, **kwargs):
+    """A fresh in-memory database, closed on exit."""
+    conn = await aiolibsql.connect(":memory:", **kwargs)
+    try:
+        if schema is not None:
+            await load_schema(conn, schema)
+        yield conn
+    finally:
+        await conn.close()
+
+
+@contextlib.asynccontextmanager
+async def temp_database(directory=None, *, schema=None, **kwargs):
+    """A database file in a new temporary directory, deleted with its WAL on exit."""
+    owned = directory is None
+    if owned:
+        directory = tempfile.mkdtemp(prefix="aiolibsql-")
+    conn = await aiolibsql.connect(os.path.join(os.fspath(directory), "test.db"), **kwargs)
+    try:
+        if schema is not None:
+            await load_schema(conn, schema)
+        yield conn
+    finally:
+        await conn.close()
+        if owned:
+            shutil.rmtree(directory, ignore_errors=True)
+
+
+@contextlib.asynccontextmanager
+async def rollback_after(conn):
+    """Run the block inside a transaction that is always rolled back.
+
+    Code under test must not commit; a `commit()` inside the block ends the
+    isolation early.
+    """
+    await conn.execute("BEGIN")
+    try:
+        yield conn
+    finally:
+        if conn.in_transaction:
+            await conn.rollback()
+
+
+try:
+    import pytest
+    import pytest_asyncio
+except ImportError:
+    pass
+else:
+
+    @pytest.fixture
+    def aiolibsql_schema():
+        """Schema files loaded into every test database; override to return paths."""
+        return None
+
+    @pytest.fixture
+    def aiolibsql_connect_kwargs():
+        """Extra `aiolibsql.connect()` arguments for the test databases; override as needed."""
+        return {}
+
+    @pytest_asyncio.fixture
+    async def aiolibsql_db(aiolibsql_schema, aiolibsql_connect_kwargs):
+        """An in-memory database with the schema loaded, new for every test."""
+        async with memory_database(schema=aiolibsql_schema, **aiolibsql_connect_kwargs) as conn:
+            yield conn
+
+    @pytest_asyncio.fixture
+    async def aiolibsql_file_db(tmp_path, aiolibsql_schema, aiolibsql_connect_kwargs):
+        """A database file under `tmp_path` with the schema loaded, new for every test."""
+        async with temp_database(tmp_path, schema=aiolibsql_schema, **aiolibsql_connect_kwargs) as conn:
+            yield conn
+
+    @pytest_asyncio.fixture
+    async def aiolibsql_isolated(aiolibsql_db):
+        """`aiolibsql_db` wrapped in a transaction that is rolled back after the test.
+
+        Override `aiolibsql_db` with a wider-scoped fixture to load the schema once and
+        still start every test from the same data.
+        """
+        async with rollback_after(aiolibsql_db) as conn:
+            yield conn
@@ -2,45 +2,142 @@ use ::libsql as libsql_core;
 use parking_lot::Mutex as SyncMutex;
 use pyo3::{
     create_exception,
-    exceptions::PyValueError,
+    exceptions::{
+        PyOverflowError,
+        PyTypeError,
+        PyValueError,
+    },
     prelude::*,
     types::{
         PyAny,
         PyBytes,
+        PyDate,
+        PyDateAccess,
+        PyDateTime,
+        PyDict,
         PyFloat,
         PyInt,
         PyList,
         PyModule,
         PyString,
+        PyTime,
+        PyTimeAccess,
         PyTuple,
+        PyWeakrefMethods,
+        PyWeakrefReference,
     },
 };
 use pyo3_async_runtimes::tokio::future_into_py;
 use std::{
+    collections::{
+        HashMap,
+        VecDeque,
+    },
+    fmt::Write as _,
     sync::{
         atomic::{
             AtomicBool,
             AtomicI64,
+            AtomicU64,
             AtomicUsize,
             Ordering,
         },
         Arc,
+        OnceLock,
+        Weak,
+    },
+    thread::ThreadId,
+    time::{
+        Duration,
+        Instant,
+        SystemTime,
+        UNIX_EPOCH,
     },
-    time::Duration,
 };
 use tokio::sync::{
+    mpsc,
     Mutex as AsyncMutex,
+    OwnedMutexGuard,
     Semaphore,
 };
+use tracing::{
+    field::{
+        Field,
+        Visit,
+    },
+    Event,
+    Level,
+    Subscriber,
+};
+use tracing_subscriber::{
+    filter::{
+        LevelFilter,
+        Targets,
+    },
+    layer::{
+        Context,
+        Layer,
+        SubscriberExt,
+    },
+    reload,
+    util::SubscriberInitExt,
+    Registry,
+};
 
 const LEGACY_TRANSACTION_CONTROL: i32 = -1;
 const VERSION: &str = "0.2.8";
+const PARSE_DECLTYPES: i32 = 1;
+const PARSE_COLNAMES: i32 = 2;
+const UNIX_EPOCH_JULIAN_DAY: f64 = 2440587.5;
+
+macro_rules! sqlite_constants {
+    ($($name:ident),* $(,)?) => {
+        &[$((stringify!($name), libsql_core::ffi::$name)),*]
+    };
+}
+
+/// Result codes and authorizer constants, exported under the same names as `sqlite3`.
+const SQLITE_CONSTANTS: &[(&str, i32)] = sqlite_constants!(
+    SQLITE_OK, SQLITE_ERROR, SQLITE_INTERNAL, SQLITE_PERM, SQLITE_ABORT, SQLITE_BUSY,
+    SQLITE_LOCKED, SQLITE_NOMEM, SQLITE_READONLY, SQLITE_INTERRUPT, SQLITE_IOERR,
+    SQLITE_CORRUPT, SQLITE_NOTFOUND, SQLITE_FULL, SQLITE_CANTOPEN, SQLITE_PROTOCOL,
+    SQLITE_EMPTY, SQLITE_SCHEMA, SQLITE_TOOBIG, SQLITE_CONSTRAINT, SQLITE_MISMATCH,
+    SQLITE_MISUSE, SQLITE_NOLFS, SQLITE_AUTH, SQLITE_FORMAT, SQLITE_RANGE, SQLITE_NOTADB,
+    SQLITE_NOTICE, SQLITE_WARNING, SQLITE_ROW, SQLITE_DONE,
+    SQLITE_DENY, SQLITE_IGNORE,
+    SQLITE_CREATE_INDEX, SQLITE_CREATE_TABLE, SQLITE_CREATE_TEMP_INDEX,
+    SQLITE_CREATE_TEMP_TABLE, SQLITE_CREATE_TEMP_TRIGGER, SQLITE_CREATE_TEMP_VIEW,
+    SQLITE_CREATE_TRIGGER, SQLITE_CREATE_VIEW, SQLITE_DELETE, SQLITE_DROP_INDEX,
+    SQLITE_DROP_TABLE, SQLITE_DROP_TEMP_INDEX, SQLITE_DROP_TEMP_TABLE,
+    SQLITE_DROP_TEMP_TRIGGER, SQLITE_DROP_TEMP_VIEW, SQLITE_DROP_TRIGGER, SQLITE_DROP_VIEW,
+    SQLITE_INSERT, SQLITE_PRAGMA, SQLITE_READ, SQLITE_SELECT, SQLITE_TRANSACTION,
+    SQLITE_UPDATE, SQLITE_ATTACH, SQLITE_DETACH, SQLITE_ALTER_TABLE, SQLITE_REINDEX,
+    SQLITE_ANALYZE, SQLITE_CREATE_VTABLE, SQLITE_DROP_VTABLE, SQLITE_FUNCTION,
+    SQLITE_SAVEPOINT, SQLITE_RECURSIVE,
+);
+
+type RowsCell = AsyncMutex<Option<ResultSet>>;
+type RowsSlot = Arc<RowsCell>;
+/// The SQL of a cursor's last statement and the values bound to it.
+type LastSqlCell = SyncMutex<Option<(String, Vec<libsql_core::Value>)>>;
+type LastSql = Arc<LastSqlCell>;
+
+/// What a connection keeps of each cursor it created, without keeping the cursor alive.
+struct CursorEntry {
+    rows: Weak<RowsCell>,
+    last_sql: Weak<LastSqlCell>,
+}
 
 create_exception!(
     aiolibsql,
-    DatabaseError,
+    Error,
     pyo3::exceptions::PyException
 );
+create_exception!(
+    aiolibsql,
+    DatabaseError,
+    Error
+);
 create_exception!(
     aiolibsql,
     OperationalError,
@@ -56,19 +153,165 @@ create_exception!(
     TimeoutError,
     DatabaseError
 );
+create_exception!(
+    aiolibsql,
+    ConflictError,
+    TimeoutError
+);
+create_exception!(
+    aiolibsql,
+    ProgrammingError,
+    DatabaseError
+);
+create_exception!(
+    aiolibsql,
+    DataError,
+    DatabaseError
+);
+create_exception!(
+    aiolibsql,
+    InterfaceError,
+    Error
+);
+create_exception!(
+    aiolibsql,
+    Warning,
+    pyo3::exceptions::PyUserWarning
+);
+
+fn warn(py: Python<'_>, message: &str) -> PyResult<()> {
+    let message = std::ffi::CString::new(message)?;
+    PyErr::warn(py, &py.get_type::<Warning>(), &message, 1)
+}
+
+/// The process that opened a connection and, with `check_same_thread=True`, its thread.
+#[derive(Clone, Copy)]
+struct Owner {
+    pid: u32,
+    thread: Option<ThreadId>,
+}
+
+impl Owner {
+    fn current(check_same_thread: bool) -> Self {
+        Self {
+            pid: std::process::id(),
+            thread: check_same_thread.then(|| std::thread::current().id()),
+        }
+    }
+
+    /// Refuses use from a forked child, whose copy of the connection shares the parent's file
+    /// locks and whose runtime threads did not survive the fork, and from other threads when
+    /// `check_same_thread` is set, as in `sqlite3`.
+    fn check(&self) -> PyResult<()> {
+        if self.pid != std::process::id() {
+            return Err(InterfaceError::new_err(format!(
+                "connection was opened in process {} and cannot be used after os.fork(); \
+                 open a new connection in the child process",
+                self.pid
+            )));
+        }
+        match self.thread {
+            Some(thread) if thread != std::thread::current().id() => Err(ProgrammingError::new_err(
+                "aiolibsql objects created in a thread can only be used in that same thread \
+                 (check_same_thread=True)",
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Process in which the driver's Tokio runtime was started.
+static RUNTIME_PID: OnceLock<u32> = OnceLock::new();
+
+/// Fails in a process forked after the runtime was started: its worker threads only exist in
+/// the parent, so work handed to it would never run.
+fn check_runtime_pid() -> PyResult<()> {
+    let pid = *RUNTIME_PID.get_or_init(std::process::id);
+    if pid != std::process::id() {
+        return Err(InterfaceError::new_err(format!(
+            "aiolibsql was already used in process {} before os.fork() and cannot run in the \
+             forked child; open connections only after forking",
+            pid
+        )));
+    }
+    Ok(())
+}
 
 fn to_py_err<E: std::fmt::Display>(error: E) -> PyErr {
     let msg = error.to_string();
+    error_class(&msg)(msg)
+}
+
+/// Whether a lowercased error message reports a `BEGIN CONCURRENT` transaction that lost to
+/// another writer: `SQLITE_BUSY_SNAPSHOT` by name or by its extended code 517 in a remote
+/// failure (`code:extended:message`), or the text SQLite gives the conflict.
+fn is_commit_conflict(lower: &str) -> bool {
+    lower.contains("sqlite_busy_snapshot")
+        || lower.contains(":517:")
+        || lower.contains("cannot commit concurrent transaction")
+}
+
+/// The exception class for a database error message.
+fn error_class(msg: &str) -> fn(String) -> PyErr {
     let lower = msg.to_lowercase();
     if lower.contains("constraint") || lower.contains("unique") || lower.contains("foreign key") {
-        IntegrityError::new_err(msg)
+        IntegrityError::new_err
+    } else if lower.contains("syntax")
+        || lower.contains("no such table")
+        || lower.contains("no such column")
+        || lower.contains("unrecognized")
+        || lower.contains("interrupted")
+        || lower.contains("unable to open")
+    {
+        OperationalError::new_err
+    } else if is_commit_conflict(&lower) {
+        ConflictError::new_err
     } else if lower.contains("timeout") || lower.contains("busy") || lower.contains("locked") {
-        TimeoutError::new_err(msg)
-    } else if lower.contains("syntax") || lower.contains("no such table") || lower.contains("unrecognized") {
-        OperationalError::new_err(msg)
+        TimeoutError::new_err
     } else {
-        DatabaseError::new_err(msg)
+        DatabaseError::new_err
+    }
+}
+
+/// Characters of SQL quoted in error messages.
+const ERROR_SQL_LIMIT: usize = 200;
+
+/// Like `to_py_err`, for an error raised while preparing or running `sql`: the message also
+/// names the line and column of the token a syntax error points at, and quotes the statement
+/// (whitespace collapsed, truncated to `ERROR_SQL_LIMIT` characters) so it is useful on its own
+/// in a log.
+fn sql_err<E: std::fmt::Display>(error: E, sql: &str) -> PyErr {
+    let msg = error.to_string();
+    let class = error_class(&msg);
+    let mut text = msg.clone();
+    if let Some((line, column)) = error_position(&msg, sql) {
+        text.push_str(&format!(" (line {}, column {})", line, column));
     }
+    let flat = sql.split_whitespace().collect::<Vec<_>>().join(" ");
+    let quoted = match flat.char_indices().nth(ERROR_SQL_LIMIT) {
+        Some((end, _)) => format!("{}...", &flat[..end]),
+        None => flat,
+    };
+    text.push_str(&format!("\nSQL: {}", quoted));
+    class(text)
+}
+
+/// The 1-based line and column of the token named in a `near "...": syntax error` message.
+fn error_position(msg: &str, sql: &str) -> Option<(usize, usize)> {
+    let start = msg.find("near \"")? + 6;
+    let token = &msg[start..start + msg[start..].find("\":")?];
+    let offset = if token.is_empty() { sql.trim_end().len() } else { sql.find(token)? };
+    let before = &sql[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+    Some((line, column))
+}
+
+/// A duration argument given in seconds. Negative, NaN and infinite values raise `ValueError`
+/// instead of reaching `Duration::from_secs_f64`, which panics on them.
+fn seconds_arg(name: &str, secs: f64) -> PyResult<Duration> {
+    Duration::try_from_secs_f64(secs)
+        .map_err(|_| PyValueError::new_err(format!("{} must be a non-negative number of seconds", name)))
 }
 
 fn is_remote_path(path: &str) -> bool {
@@ -77,624 +320,5984 @@ fn is_remote_path(path: &str) -> bool {
         || path.starts_with("https://")
 }
 
-fn extract_parameter(_py: Python, item: &Bound<'_, PyAny>) -> PyResult<libsql_core::Value> {
-    if item.is_none() {
-        Ok(libsql_core::Value::Null)
-    } else if item.is_instance_of::<PyInt>() {
-        Ok(libsql_core::Value::Integer(item.extract::<i64>()?))
-    } else if item.is_instance_of::<PyString>() {
-        Ok(libsql_core::Value::Text(item.extract::<String>()?))
-    } else if item.is_instance_of::<PyFloat>() {
-        Ok(libsql_core::Value::Real(item.extract::<f64>()?))
-    } else if item.is_instance_of::<PyBytes>() {
-        Ok(libsql_core::Value::Blob(item.extract::<Vec<u8>>()?))
-    } else if item.is_instance_of::<pyo3::types::PyByteArray>() {
-        Ok(libsql_core::Value::Blob(item.extract::<Vec<u8>>()?))
-    } else if item.is_instance_of::<pyo3::types::PyBool>() {
-        let v: bool = item.extract()?;
-        Ok(libsql_core::Value::Integer(if v { 1 } else { 0 }))
-    } else {
-        Ok(libsql_core::Value::Null)
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = |b: u8| (b as char).to_digit(16);
+        match bytes[i] {
+            b'%' => {
+                match (bytes.get(i + 1).and_then(|b| hex(*b)), bytes.get(i + 2).and_then(|b| hex(*b))) {
+                    (Some(hi), Some(lo)) => {
+                        out.push((hi * 16 + lo) as u8);
+                        i += 3;
+                    }
+                    _ => {
+                        out.push(b'%');
+                        i += 1;
+                    }
+                }
+            }
+            b'+' => {
+                out.push(b' ');
+                i += 1;
+            }
+            b => {
+                out.push(b);
+                i += 1;
+            }
+        }
     }
+    String::from_utf8_lossy(&out).into_owned()
 }
 
-enum ListOrTuple {
-    List(Py<PyList>),
-    Tuple(Py<PyTuple>),
+fn parse_bool_option(key: &str, value: &str) -> PyResult<bool> {
+    match value.to_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" => Ok(false),
+        _ => Err(PyValueError::new_err(format!("invalid value for {}: {}", key, value))),
+    }
 }
 
-impl<'py> FromPyObject<'py> for ListOrTuple {
-    fn extract_bound(ob: &Bound<'py, PyAny>) -> PyResult<Self> {
-        if ob.is_instance_of::<PyList>() {
-            Ok(ListOrTuple::List(ob.downcast::<PyList>()?.clone().unbind()))
-        } else if ob.is_instance_of::<PyTuple>() {
-            Ok(ListOrTuple::Tuple(ob.downcast::<PyTuple>()?.clone().unbind()))
-        } else {
-            Err(PyValueError::new_err("expected list or tuple"))
+/// Rewrites a `ws://` / `wss://` URL to the same host over HTTP, which is the only transport
+/// libsql's client has. The warning names the URL without its query, which may hold an
+/// `authToken`.
+fn http_for_websocket(py: Python<'_>, url: String) -> PyResult<String> {
+    let http = match url.split_once("://") {
+        Some(("wss", rest)) => format!("https://{}", rest),
+        Some(("ws", rest)) => format!("http://{}", rest),
+        _ => return Ok(url),
+    };
+    let shown = http.split_once('?').map_or(http.as_str(), |(base, _)| base);
+    warn(
+        py,
+        &format!("WebSocket transport is not available, connecting over HTTP: {}", shown),
+    )?;
+    Ok(http)
+}
+
+fn parse_database_url(url: &str) -> PyResult<(String, Option<String>)> {
+    let (base, query) = match url.split_once('?') {
+        Some((base, query)) if is_remote_path(base) => (base, query),
+        _ => return Ok((url.to_string(), None)),
+    };
+    let mut auth_token = None;
+    let mut tls = None;
+    for pair in query.split('&').filter(|p| !p.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let value = percent_decode(value);
+        match key {
+            "authToken" | "auth_token" => auth_token = Some(value),
+            "tls" | "secure" => tls = Some(parse_bool_option(key, &value)?),
+            _ => return Err(PyValueError::new_err(format!("unsupported URL parameter: {}", key))),
         }
     }
+    let base = match (tls, base.split_once("://")) {
+        (Some(true), Some(("libsql" | "http", rest))) => format!("https://{}", rest),
+        (Some(false), Some(("libsql" | "https", rest))) => format!("http://{}", rest),
+        _ => base.to_string(),
+    };
+    Ok((base, auth_token))
 }
 
-fn extract_parameters(
-    py: Python,
-    parameters: Option<ListOrTuple>,
-) -> PyResult<libsql_core::params::Params> {
-    match parameters {
-        Some(p) => {
-            let mut params = Vec::new();
-            let (len, binder) = match &p {
-                ListOrTuple::List(l) => {
-                    let b = l.bind(py);
-                    (b.len(), b.as_any())
-                }
-                ListOrTuple::Tuple(t) => {
-                    let b = t.bind(py);
-                    (b.len(), b.as_any())
-                }
-            };
-            for i in 0..len {
-                let item = if let Ok(l) = binder.downcast::<PyList>() {
-                    l.get_item(i)?
-                } else {
-                    binder.downcast::<PyTuple>().unwrap().get_item(i)?
-                };
-                params.push(extract_parameter(py, &item)?);
-            }
-            Ok(libsql_core::params::Params::Positional(params))
+#[derive(Clone, Copy, Default, PartialEq)]
+enum DateTimeFormat {
+    #[default]
+    Iso,
+    Unix,
+    Julian,
+}
+
+impl DateTimeFormat {
+    fn parse(name: &str) -> PyResult<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "iso" => Ok(DateTimeFormat::Iso),
+            "unix" => Ok(DateTimeFormat::Unix),
+            "julian" => Ok(DateTimeFormat::Julian),
+            _ => Err(PyValueError::new_err(format!("unknown datetime format: {}", name))),
         }
-        None => Ok(libsql_core::params::Params::None),
     }
 }
 
-fn convert_value(py: Python<'_>, value: libsql_core::Value) -> PyResult<PyObject> {
-    match value {
-        libsql_core::Value::Null => Ok(py.None()),
-        libsql_core::Value::Integer(v) => Ok(v.into_pyobject(py)?.unbind().into_any()),
-        libsql_core::Value::Real(v) => Ok(v.into_pyobject(py)?.unbind().into_any()),
-        libsql_core::Value::Text(v) => Ok(v.into_pyobject(py)?.unbind().into_any()),
-        libsql_core::Value::Blob(v) => Ok(PyBytes::new(py, &v).into_pyobject(py)?.unbind().into_any()),
+/// Zone of the `timezone` option, resolved once when the connection opens and shared by
+/// its cursors.
+#[derive(Clone, Default)]
+enum TimeZone {
+    #[default]
+    Naive,
+    Zone(Arc<PyObject>),
+}
+
+impl TimeZone {
+    fn parse(py: Python<'_>, name: Option<&str>) -> PyResult<Self> {
+        let Some(name) = name else {
+            return Ok(TimeZone::Naive);
+        };
+        let tz = if name.eq_ignore_ascii_case("utc") {
+            utc(py)
+        } else {
+            py.import("zoneinfo")?
+                .getattr("ZoneInfo")?
+                .call1((name,))
+                .map_err(|_| PyValueError::new_err(format!("unknown timezone: {}", name)))?
+        };
+        Ok(TimeZone::Zone(Arc::new(tz.unbind())))
+    }
+
+    fn tzinfo<'py>(&self, py: Python<'py>) -> Option<Bound<'py, PyAny>> {
+        match self {
+            TimeZone::Naive => None,
+            TimeZone::Zone(tz) => Some(tz.bind(py).clone()),
+        }
     }
 }
 
-async fn begin_transaction(conn: &libsql_core::Connection) -> PyResult<()> {
-    conn.execute("BEGIN", ()).await.map_err(to_py_err)?;
-    Ok(())
+fn utc(py: Python<'_>) -> Bound<'_, PyAny> {
+    pyo3::types::timezone_utc(py).into_any()
 }
 
-fn determine_autocommit(autocommit: i32, isolation_level: &Option<String>) -> bool {
-    match autocommit {
-        LEGACY_TRANSACTION_CONTROL => isolation_level.is_none(),
-        _ => autocommit != 0,
-    }
+fn with_tzinfo<'py>(dt: &Bound<'py, PyAny>, tz: &Bound<'py, PyAny>) -> PyResult<Bound<'py, PyAny>> {
+    let kwargs = PyDict::new(dt.py());
+    kwargs.set_item("tzinfo", tz)?;
+    dt.call_method("replace", (), Some(&kwargs))
 }
 
-fn stmt_is_dml(sql: &str) -> bool {
-    let s = sql.trim_start().to_uppercase();
-    s.starts_with("INSERT")
-        || s.starts_with("UPDATE")
-        || s.starts_with("DELETE")
-        || s.starts_with("REPLACE")
-        || s.starts_with("CREATE")
-        || s.starts_with("DROP")
-        || s.starts_with("ALTER")
-        || s.starts_with("ATTACH")
-        || s.starts_with("DETACH")
-        || s.starts_with("VACUUM")
+/// With `timezone` set, `dt` as a naive UTC datetime, the form timestamps are stored in; a
+/// naive `dt` is taken to be in that zone.
+fn datetime_to_utc<'py>(dt: &Bound<'py, PyDateTime>, zone: &TimeZone) -> PyResult<Bound<'py, PyAny>> {
+    let py = dt.py();
+    let Some(tz) = zone.tzinfo(py) else {
+        return Ok(dt.clone().into_any());
+    };
+    let aware = if dt.call_method0("utcoffset")?.is_none() {
+        with_tzinfo(dt.as_any(), &tz)?
+    } else {
+        dt.clone().into_any()
+    };
+    with_tzinfo(&aware.call_method1("astimezone", (utc(py),))?, &py.None().into_bound(py))
 }
 
-fn stmt_is_read(sql: &str) -> bool {
-    !stmt_is_dml(sql)
+/// With `timezone` set, a fetched timestamp as an aware datetime in that zone; a naive `dt`
+/// is taken to be UTC.
+fn datetime_from_utc(dt: &Bound<'_, PyAny>, zone: &TimeZone) -> PyResult<PyObject> {
+    let py = dt.py();
+    let Some(tz) = zone.tzinfo(py) else {
+        return Ok(dt.clone().unbind());
+    };
+    let aware = if dt.call_method0("utcoffset")?.is_none() {
+        with_tzinfo(dt, &utc(py))?
+    } else {
+        dt.clone()
+    };
+    Ok(aware.call_method1("astimezone", (tz,))?.unbind())
 }
 
-struct TxGuard {
-    conn: libsql_core::Connection,
-    started: bool,
-    done: bool,
+#[derive(Clone, Copy, Default, PartialEq)]
+enum InitialSync {
+    #[default]
+    Bootstrap,
+    Full,
+    Skip,
 }
 
-impl TxGuard {
-    async fn new(
-        conn: libsql_core::Connection,
-        is_tx: bool,
-    ) -> PyResult<Self> {
-        let started = if is_tx && conn.is_autocommit() {
-            begin_transaction(&conn).await?;
-            true
-        } else {
-            false
-        };
-        Ok(Self {
-            conn,
-            started,
-            done: false,
-        })
+impl InitialSync {
+    fn parse(name: &str) -> PyResult<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "bootstrap" => Ok(InitialSync::Bootstrap),
+            "full" => Ok(InitialSync::Full),
+            "skip" => Ok(InitialSync::Skip),
+            _ => Err(PyValueError::new_err(format!("unknown initial_sync mode: {}", name))),
+        }
     }
+}
 
-    async fn commit(mut self) -> PyResult<()> {
-        if self.started && !self.done {
-            self.conn.execute("COMMIT", ()).await.map_err(to_py_err)?;
-            self.done = true;
+#[derive(Clone, Copy, Default, PartialEq)]
+enum DecimalFormat {
+    #[default]
+    Text,
+    Cents,
+}
+
+impl DecimalFormat {
+    fn parse(name: &str) -> PyResult<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "text" => Ok(DecimalFormat::Text),
+            "cents" => Ok(DecimalFormat::Cents),
+            _ => Err(PyValueError::new_err(format!("unknown decimal format: {}", name))),
         }
-        Ok(())
     }
+}
 
-    async fn rollback(mut self) -> PyResult<()> {
-        if self.started && !self.done {
-            let _ = self.conn.execute("ROLLBACK", ()).await;
-            self.done = true;
+#[derive(Clone, Copy, Default, PartialEq)]
+enum UuidFormat {
+    #[default]
+    Text,
+    Blob,
+}
+
+impl UuidFormat {
+    fn parse(name: &str) -> PyResult<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "text" => Ok(UuidFormat::Text),
+            "blob" => Ok(UuidFormat::Blob),
+            _ => Err(PyValueError::new_err(format!("unknown uuid format: {}", name))),
         }
-        Ok(())
     }
-    
-    fn conn(&self) -> &libsql_core::Connection {
-        &self.conn
+}
+
+#[derive(Clone, Copy, Default, PartialEq)]
+enum IntOverflow {
+    #[default]
+    Raise,
+    Text,
+    Blob,
+}
+
+impl IntOverflow {
+    fn parse(name: &str) -> PyResult<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "raise" => Ok(IntOverflow::Raise),
+            "text" => Ok(IntOverflow::Text),
+            "blob" => Ok(IntOverflow::Blob),
+            _ => Err(PyValueError::new_err(format!("unknown int overflow policy: {}", name))),
+        }
     }
 }
 
-#[pyfunction]
-#[pyo3(
-    signature = (
-        database,
-        timeout=5.0,
-        isolation_level="DEFERRED".to_string(),
-        _check_same_thread=true,
-        _uri=false,
-        sync_url=None,
-        sync_interval=None,
-        offline=false,
-        auth_token=None,
-        encryption_key=None,
-        autocommit=LEGACY_TRANSACTION_CONTROL
-    )
-)]
-fn connect<'py>(
-    py: Python<'py>,
-    database: String,
-    timeout: f64,
-    isolation_level: Option<String>,
-    _check_same_thread: bool,
-    _uri: bool,
-    sync_url: Option<String>,
-    sync_interval: Option<f64>,
-    offline: bool,
-    auth_token: Option<String>,
-    encryption_key: Option<String>,
-    autocommit: i32,
-) -> PyResult<Bound<'py, PyAny>> {
-    let auth_token = auth_token.unwrap_or_default();
-    future_into_py(py, async move {
-        let ver = "libsql-python-rpc-0.2.8";
-        let encryption_config = match encryption_key {
-            Some(key) => {
-                let cipher = libsql_core::Cipher::default();
-                Some(libsql_core::EncryptionConfig::new(cipher, key.into()))
-            }
-            None => None,
-        };
-        let db = if is_remote_path(&database) {
-            libsql_core::Database::open_remote_internal(
-                database,
-                auth_token.clone(),
-                ver.to_string(),
-            )
-            .map_err(to_py_err)?
-        } else {
-            match sync_url {
-                Some(sync_url) => {
-                    let sync_interval = sync_interval.map(Duration::from_secs_f64);
-                    let mut builder = libsql_core::Builder::new_synced_database(
-                        database,
-                        sync_url,
-                        auth_token.clone(),
-                    );
-                    if encryption_config.is_some() {
-                        return Err(PyValueError::new_err("no encryption sync"));
-                    }
-                    if let Some(sync_interval) = sync_interval {
-                        builder = builder.sync_interval(sync_interval);
-                    }
-                    builder = builder.remote_writes(!offline);
-                    builder.build().await.map_err(to_py_err)?
-                }
-                None => {
-                    let mut builder = libsql_core::Builder::new_local(database);
-                    if let Some(config) = encryption_config {
-                        builder = builder.encryption_config(config);
-                    }
-                    builder.build().await.map_err(to_py_err)?
-                }
-            }
-        };
-        let conn = db.connect().map_err(to_py_err)?;
-        conn.busy_timeout(Duration::from_secs_f64(timeout)).map_err(to_py_err)?;
-        let autocommit_val = if autocommit == LEGACY_TRANSACTION_CONTROL {
-            if isolation_level.is_none() { 1 } else { 0 }
-        } else {
-            autocommit
-        };
-        Ok(Connection {
-            db: Arc::new(db),
-            conn: Arc::new(SyncMutex::new(Some(conn))),
-            isolation_level,
-            autocommit: autocommit_val,
-        })
-    })
+/// What happens to fetched TEXT that is not valid UTF-8.
+#[derive(Clone, Copy, Default, PartialEq)]
+enum TextErrors {
+    #[default]
+    Strict,
+    Bytes,
+    Replace,
+}
+
+impl TextErrors {
+    fn parse(name: &str) -> PyResult<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "strict" => Ok(TextErrors::Strict),
+            "bytes" => Ok(TextErrors::Bytes),
+            "replace" => Ok(TextErrors::Replace),
+            "surrogateescape" => Err(PyValueError::new_err(
+                "text_errors=\"surrogateescape\" is not supported; use \"bytes\" and \
+                 decode with errors=\"surrogateescape\"",
+            )),
+            _ => Err(PyValueError::new_err(format!("unknown text_errors policy: {}", name))),
+        }
+    }
+}
+
+/// What happens to `float('nan')`, `inf` and `-inf` on bind, and to non-finite REALs on fetch.
+#[derive(Clone, Copy, Default, PartialEq)]
+enum NonFinite {
+    #[default]
+    Keep,
+    Null,
+    Text,
+    Raise,
+}
+
+impl NonFinite {
+    fn parse(name: &str) -> PyResult<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "keep" => Ok(NonFinite::Keep),
+            "null" => Ok(NonFinite::Null),
+            "text" => Ok(NonFinite::Text),
+            "raise" => Ok(NonFinite::Raise),
+            _ => Err(PyValueError::new_err(format!("unknown nonfinite policy: {}", name))),
+        }
+    }
+}
+
+fn sql_keyword(what: &str, value: &str, allowed: &[&str]) -> PyResult<String> {
+    let upper = value.to_ascii_uppercase();
+    if allowed.contains(&upper.as_str()) {
+        Ok(upper)
+    } else {
+        Err(PyValueError::new_err(format!("unknown {}: {}", what, value)))
+    }
+}
+
+#[derive(Default)]
+struct Pragmas {
+    journal_mode: Option<String>,
+    synchronous: Option<String>,
+    foreign_keys: Option<bool>,
+    cache_size: Option<i64>,
+    mmap_size: Option<i64>,
+    wal_autocheckpoint: Option<i64>,
+    soft_heap_limit: Option<i64>,
+    hard_heap_limit: Option<i64>,
+}
+
+impl Pragmas {
+    fn to_sql(&self) -> Option<String> {
+        let mut sql = String::new();
+        if let Some(mode) = &self.journal_mode {
+            sql.push_str(&format!("PRAGMA journal_mode={};", mode));
+        }
+        if let Some(level) = &self.synchronous {
+            sql.push_str(&format!("PRAGMA synchronous={};", level));
+        }
+        if let Some(on) = self.foreign_keys {
+            sql.push_str(&format!("PRAGMA foreign_keys={};", if on { "ON" } else { "OFF" }));
+        }
+        if let Some(pages) = self.cache_size {
+            sql.push_str(&format!("PRAGMA cache_size={};", pages));
+        }
+        if let Some(bytes) = self.mmap_size {
+            sql.push_str(&format!("PRAGMA mmap_size={};", bytes));
+        }
+        if let Some(pages) = self.wal_autocheckpoint {
+            sql.push_str(&format!("PRAGMA wal_autocheckpoint={};", pages));
+        }
+        if let Some(bytes) = self.soft_heap_limit {
+            sql.push_str(&format!("PRAGMA soft_heap_limit={};", bytes));
+        }
+        if let Some(bytes) = self.hard_heap_limit {
+            sql.push_str(&format!("PRAGMA hard_heap_limit={};", bytes));
+        }
+        (!sql.is_empty()).then_some(sql)
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq)]
+struct HttpConfig {
+    keepalive: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    http2: bool,
+}
+
+type HttpsConnector = hyper_rustls::HttpsConnector<hyper::client::HttpConnector>;
+
+impl HttpConfig {
+    fn connector(&self) -> PyResult<HttpsConnector> {
+        let mut http = hyper::client::HttpConnector::new();
+        http.enforce_http(false);
+        http.set_nodelay(true);
+        http.set_keepalive(self.keepalive);
+        http.set_connect_timeout(self.connect_timeout);
+        let builder = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_native_roots()
+            .map_err(|e| PyValueError::new_err(format!("cannot load TLS roots: {}", e)))?
+            .https_or_http();
+        Ok(if self.http2 {
+            builder.enable_http1().enable_http2().wrap_connector(http)
+        } else {
+            builder.enable_http1().wrap_connector(http)
+        })
+    }
+}
+
+const CLIENT_VERSION: &str = "libsql-python-rpc-0.2.8";
+
+async fn open_remote(url: &str, auth_token: &str, http: HttpConfig) -> PyResult<libsql_core::Database> {
+    if http == HttpConfig::default() {
+        libsql_core::Database::open_remote_internal(url, auth_token, CLIENT_VERSION.to_string())
+            .map_err(to_py_err)
+    } else {
+        libsql_core::Builder::new_remote(url.to_string(), auth_token.to_string())
+            .connector(http.connector()?)
+            .version(CLIENT_VERSION.to_string())
+            .build()
+            .await
+            .map_err(to_py_err)
+    }
+}
+
+/// `(url, auth_token)` pairs of a connection opened with `fallback_urls`, primary first.
+type Endpoints = Vec<(String, String)>;
+
+/// Whether a statement failed because the server could not be reached at all, rather than
+/// because the server rejected it.
+fn is_unreachable(msg: &str) -> bool {
+    let msg = msg.to_lowercase();
+    [
+        "http error",
+        "error trying to connect",
+        "connection refused",
+        "connection reset",
+        "dns error",
+        "tcp connect error",
+        "timed out",
+    ]
+    .iter()
+    .any(|m| msg.contains(m))
+}
+
+/// Opens the first endpoint, starting at `start` and wrapping around, that answers a
+/// `SELECT 1`, and returns it with its index. Fails with the last endpoint's error.
+async fn open_reachable(
+    endpoints: &Endpoints,
+    start: usize,
+    http: HttpConfig,
+) -> PyResult<(libsql_core::Database, libsql_core::Connection, usize)> {
+    let mut last_err = None;
+    for i in 0..endpoints.len() {
+        let idx = (start + i) % endpoints.len();
+        let (url, auth_token) = &endpoints[idx];
+        let probe = async {
+            let db = open_remote(url, auth_token, http).await?;
+            let conn = db.connect().map_err(to_py_err)?;
+            conn.query("SELECT 1", ()).await.map_err(to_py_err)?;
+            Ok::<_, PyErr>((db, conn))
+        };
+        match probe.await {
+            Ok((db, conn)) => return Ok((db, conn, idx)),
+            Err(e) => last_err = Some(e),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| PyValueError::new_err("no endpoints to connect to")))
+}
+
+#[derive(Default)]
+struct FailoverState {
+    current: usize,
+    failures: u32,
+    /// Database of the endpoint switched to last; the connection borrows its client.
+    db: Option<libsql_core::Database>,
+}
+
+/// Moves a remote connection to the next reachable endpoint once `threshold` statements in a
+/// row could not reach the current one.
+struct Failover {
+    endpoints: Endpoints,
+    http: HttpConfig,
+    threshold: u32,
+    conn: Arc<SyncMutex<Option<libsql_core::Connection>>>,
+    exec_lock: Arc<AsyncMutex<()>>,
+    state: SyncMutex<FailoverState>,
+}
+
+impl Failover {
+    async fn observe(&self, err: Option<&PyErr>, metrics: &ConnectionMetrics) {
+        let start = {
+            let mut state = self.state.lock();
+            match err {
+                None => {
+                    state.failures = 0;
+                    return;
+                }
+                Some(e) if is_unreachable(&e.to_string()) => {
+                    state.failures += 1;
+                    if state.failures < self.threshold {
+                        return;
+                    }
+                    state.failures = 0;
+                    state.current + 1
+                }
+                Some(_) => return,
+            }
+        };
+        // Switching mid-transaction would silently drop the statements already run in it.
+        let _exec = self.exec_lock.lock().await;
+        if !self.conn.lock().as_ref().is_some_and(|c| c.is_autocommit()) {
+            return;
+        }
+        let Ok((db, conn, idx)) = open_reachable(&self.endpoints, start, self.http).await else {
+            return;
+        };
+        let mut guard = self.conn.lock();
+        if guard.is_none() {
+            return;
+        }
+        *guard = Some(conn);
+        let mut state = self.state.lock();
+        state.current = idx;
+        state.db = Some(db);
+        metrics.failovers.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Clone, Default)]
+struct TypeConfig {
+    detect_types: i32,
+    datetime_format: DateTimeFormat,
+    decimal_format: DecimalFormat,
+    uuid_format: UuidFormat,
+    int_overflow: IntOverflow,
+    nonfinite: NonFinite,
+    timezone: TimeZone,
+    text_errors: TextErrors,
+    strict_parameters: bool,
+}
+
+/// Where a result column comes from, as reported by `sqlite3_column_decltype` and the
+/// `sqlite3_column_*_name` functions. The origin fields are `None` for expressions.
+#[derive(Clone)]
+struct ColumnMeta {
+    name: String,
+    decl_type: Option<String>,
+    table: Option<String>,
+    column: Option<String>,
+    database: Option<String>,
+}
+
+impl ColumnMeta {
+    fn for_statement(stmt: &libsql_core::Statement) -> Vec<Self> {
+        stmt.columns()
+            .iter()
+            .map(|c| ColumnMeta {
+                name: c.name().to_string(),
+                decl_type: c.decl_type().map(str::to_string),
+                table: c.table_name().map(str::to_string),
+                column: c.origin_name().map(str::to_string),
+                database: c.database_name().map(str::to_string),
+            })
+            .collect()
+    }
+
+    fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let d = PyDict::new(py);
+        d.set_item("name", &self.name)?;
+        d.set_item("decltype", &self.decl_type)?;
+        d.set_item("table", &self.table)?;
+        d.set_item("column", &self.column)?;
+        d.set_item("database", &self.database)?;
+        Ok(d)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ColumnKind {
+    Plain,
+    Date,
+    Time,
+    Timestamp,
+    Decimal,
+    Uuid,
+    Real,
+}
+
+impl ColumnKind {
+    fn from_decl_type(decl_type: Option<&str>) -> Self {
+        let name = decl_type
+            .and_then(|t| t.split(|c: char| c.is_whitespace() || c == '(').next())
+            .unwrap_or("")
+            .to_ascii_uppercase();
+        match name.as_str() {
+            "DATE" => ColumnKind::Date,
+            "TIME" => ColumnKind::Time,
+            "TIMESTAMP" | "DATETIME" => ColumnKind::Timestamp,
+            "NUMERIC" | "DECIMAL" => ColumnKind::Decimal,
+            "UUID" => ColumnKind::Uuid,
+            "REAL" | "FLOAT" | "DOUBLE" => ColumnKind::Real,
+            _ => ColumnKind::Plain,
+        }
+    }
+
+    fn for_statement(stmt: &libsql_core::Statement, types: &TypeConfig) -> Vec<Self> {
+        if types.detect_types & (PARSE_DECLTYPES | PARSE_COLNAMES) == 0 {
+            // `nonfinite="text"` reads its spellings back in REAL columns even without detect_types.
+            if types.nonfinite != NonFinite::Text {
+                return Vec::new();
+            }
+            return stmt
+                .columns()
+                .iter()
+                .map(|c| match ColumnKind::from_decl_type(c.decl_type()) {
+                    ColumnKind::Real => ColumnKind::Real,
+                    _ => ColumnKind::Plain,
+                })
+                .collect();
+        }
+        stmt.columns()
+            .iter()
+            .map(|c| {
+                let from_name = (types.detect_types & PARSE_COLNAMES != 0)
+                    .then(|| colname_type(c.name()))
+                    .flatten()
+                    .map(|t| ColumnKind::from_decl_type(Some(t)));
+                match from_name {
+                    Some(kind) if kind != ColumnKind::Plain => kind,
+                    _ if types.detect_types & PARSE_DECLTYPES != 0 => {
+                        ColumnKind::from_decl_type(c.decl_type())
+                    }
+                    _ => ColumnKind::Plain,
+                }
+            })
+            .collect()
+    }
+}
+
+/// The type in a column alias such as `"d [date]"`, used with `PARSE_COLNAMES`.
+fn colname_type(name: &str) -> Option<&str> {
+    let start = name.find('[')? + 1;
+    let end = name[start..].find(']')? + start;
+    Some(&name[start..end])
+}
+
+/// Column name as shown in `description`; with `PARSE_COLNAMES` the `[type]` suffix is dropped.
+fn description_name(name: &str, types: &TypeConfig) -> String {
+    match name.find('[') {
+        Some(i) if types.detect_types & PARSE_COLNAMES != 0 => name[..i].trim_end().to_string(),
+        _ => name.to_string(),
+    }
+}
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn date_unix_seconds(date: &Bound<'_, PyAny>) -> PyResult<f64> {
+    let date = date.downcast::<PyDate>()?;
+    let days = days_from_civil(
+        date.get_year() as i64,
+        date.get_month() as i64,
+        date.get_day() as i64,
+    );
+    Ok(days as f64 * 86400.0)
+}
+
+fn datetime_unix_seconds(dt: &Bound<'_, PyDateTime>) -> PyResult<f64> {
+    let mut secs = date_unix_seconds(dt.as_any())?
+        + dt.get_hour() as f64 * 3600.0
+        + dt.get_minute() as f64 * 60.0
+        + dt.get_second() as f64
+        + dt.get_microsecond() as f64 / 1e6;
+    let offset = dt.call_method0("utcoffset")?;
+    if !offset.is_none() {
+        secs -= offset.call_method0("total_seconds")?.extract::<f64>()?;
+    }
+    Ok(secs)
+}
+
+fn epoch_value(secs: f64, format: DateTimeFormat) -> libsql_core::Value {
+    match format {
+        DateTimeFormat::Julian => {
+            libsql_core::Value::Real(secs / 86400.0 + UNIX_EPOCH_JULIAN_DAY)
+        }
+        _ if secs.fract() == 0.0 => libsql_core::Value::Integer(secs as i64),
+        _ => libsql_core::Value::Real(secs),
+    }
+}
+
+fn adapt_datetime(
+    item: &Bound<'_, PyAny>,
+    format: DateTimeFormat,
+    zone: &TimeZone,
+) -> PyResult<Option<libsql_core::Value>> {
+    if let Ok(dt) = item.downcast::<PyDateTime>() {
+        let stored = datetime_to_utc(dt, zone)?;
+        let dt = stored.downcast::<PyDateTime>()?;
+        Ok(Some(match format {
+            DateTimeFormat::Iso => {
+                libsql_core::Value::Text(dt.call_method1("isoformat", (" ",))?.extract()?)
+            }
+            _ => epoch_value(datetime_unix_seconds(dt)?, format),
+        }))
+    } else if item.is_instance_of::<PyDate>() {
+        Ok(Some(match format {
+            DateTimeFormat::Iso => {
+                libsql_core::Value::Text(item.call_method0("isoformat")?.extract()?)
+            }
+            _ => epoch_value(date_unix_seconds(item)?, format),
+        }))
+    } else if item.is_instance_of::<PyTime>() {
+        Ok(Some(libsql_core::Value::Text(item.call_method0("isoformat")?.extract()?)))
+    } else {
+        Ok(None)
+    }
+}
+
+fn adapt_decimal(
+    py: Python,
+    item: &Bound<'_, PyAny>,
+    format: DecimalFormat,
+) -> PyResult<Option<libsql_core::Value>> {
+    let decimal = py.import("decimal")?.getattr("Decimal")?;
+    if !item.is_instance(&decimal)? {
+        return Ok(None);
+    }
+    match format {
+        DecimalFormat::Text => Ok(Some(libsql_core::Value::Text(item.str()?.extract()?))),
+        DecimalFormat::Cents => {
+            let scaled = item.call_method1("scaleb", (2,))?;
+            if !scaled.call_method0("is_finite")?.is_truthy()?
+                || !scaled.eq(scaled.call_method0("to_integral_value")?)?
+            {
+                return Err(PyValueError::new_err(format!(
+                    "decimal not representable in cents: {}",
+                    item.str()?
+                )));
+            }
+            let cents = py.get_type::<PyInt>().call1((scaled,))?;
+            Ok(Some(libsql_core::Value::Integer(cents.extract()?)))
+        }
+    }
+}
+
+fn adapt_uuid(
+    py: Python,
+    item: &Bound<'_, PyAny>,
+    format: UuidFormat,
+) -> PyResult<Option<libsql_core::Value>> {
+    let uuid = py.import("uuid")?.getattr("UUID")?;
+    if !item.is_instance(&uuid)? {
+        return Ok(None);
+    }
+    match format {
+        UuidFormat::Text => Ok(Some(libsql_core::Value::Text(item.str()?.extract()?))),
+        UuidFormat::Blob => Ok(Some(libsql_core::Value::Blob(item.getattr("bytes")?.extract()?))),
+    }
+}
+
+fn adapt_big_int(
+    index: usize,
+    item: &Bound<'_, PyAny>,
+    policy: IntOverflow,
+) -> PyResult<libsql_core::Value> {
+    match policy {
+        IntOverflow::Raise => Err(PyOverflowError::new_err(format!(
+            "parameter {}: int {} does not fit in a 64-bit integer",
+            index + 1,
+            item.str()?
+        ))),
+        IntOverflow::Text => Ok(libsql_core::Value::Text(item.str()?.extract()?)),
+        IntOverflow::Blob => {
+            let bits: usize = item.call_method0("bit_length")?.extract()?;
+            let kwargs = PyDict::new(item.py());
+            kwargs.set_item("signed", true)?;
+            let bytes = item.call_method("to_bytes", (bits / 8 + 1, "big"), Some(&kwargs))?;
+            Ok(libsql_core::Value::Blob(bytes.extract()?))
+        }
+    }
+}
+
+fn adapt_float(index: usize, v: f64, policy: NonFinite) -> PyResult<libsql_core::Value> {
+    if v.is_finite() {
+        return Ok(libsql_core::Value::Real(v));
+    }
+    match policy {
+        NonFinite::Keep => Ok(libsql_core::Value::Real(v)),
+        NonFinite::Null => Ok(libsql_core::Value::Null),
+        NonFinite::Text => Ok(libsql_core::Value::Text(nonfinite_text(v).to_string())),
+        NonFinite::Raise => Err(DataError::new_err(format!(
+            "parameter {}: float {} is not finite",
+            index + 1,
+            nonfinite_text(v)
+        ))),
+    }
+}
+
+/// Spelling of a non-finite float with `nonfinite="text"`, as accepted by `float()`.
+fn nonfinite_text(v: f64) -> &'static str {
+    if v.is_nan() {
+        "NaN"
+    } else if v > 0.0 {
+        "Infinity"
+    } else {
+        "-Infinity"
+    }
+}
+
+fn extract_parameter(
+    py: Python,
+    index: usize,
+    item: &Bound<'_, PyAny>,
+    types: &TypeConfig,
+) -> PyResult<libsql_core::Value> {
+    if item.is_none() {
+        Ok(libsql_core::Value::Null)
+    } else if item.is_instance_of::<PyInt>() {
+        match item.extract::<i64>() {
+            Ok(v) => Ok(libsql_core::Value::Integer(v)),
+            Err(_) => adapt_big_int(index, item, types.int_overflow),
+        }
+    } else if item.is_instance_of::<PyString>() {
+        Ok(libsql_core::Value::Text(item.extract::<String>()?))
+    } else if item.is_instance_of::<PyFloat>() {
+        adapt_float(index, item.extract::<f64>()?, types.nonfinite)
+    } else if item.is_instance_of::<PyBytes>() {
+        Ok(libsql_core::Value::Blob(item.extract::<Vec<u8>>()?))
+    } else if item.is_instance_of::<pyo3::types::PyByteArray>() {
+        Ok(libsql_core::Value::Blob(item.extract::<Vec<u8>>()?))
+    } else if let Ok(view) = pyo3::types::PyMemoryView::from(item) {
+        // memoryview, array.array, numpy arrays and anything else exposing the buffer protocol;
+        // tobytes() copies the raw bytes in C order, also for non-contiguous views.
+        Ok(libsql_core::Value::Blob(view.call_method0("tobytes")?.extract::<Vec<u8>>()?))
+    } else if item.is_instance_of::<pyo3::types::PyBool>() {
+        let v: bool = item.extract()?;
+        Ok(libsql_core::Value::Integer(if v { 1 } else { 0 }))
+    } else if let Some(value) = adapt_datetime(item, types.datetime_format, &types.timezone)? {
+        Ok(value)
+    } else if let Some(value) = adapt_decimal(py, item, types.decimal_format)? {
+        Ok(value)
+    } else if let Some(value) = adapt_uuid(py, item, types.uuid_format)? {
+        Ok(value)
+    } else if types.strict_parameters {
+        Err(ProgrammingError::new_err(format!(
+            "error binding parameter {}: type '{}' is not supported",
+            index + 1,
+            item.get_type().name()?
+        )))
+    } else {
+        warn(
+            py,
+            &format!(
+                "parameter {}: type '{}' is not supported, binding NULL",
+                index + 1,
+                item.get_type().name()?
+            ),
+        )?;
+        Ok(libsql_core::Value::Null)
+    }
+}
+
+enum ListOrTuple {
+    List(Py<PyList>),
+    Tuple(Py<PyTuple>),
+}
+
+impl<'py> FromPyObject<'py> for ListOrTuple {
+    fn extract_bound(ob: &Bound<'py, PyAny>) -> PyResult<Self> {
+        if ob.is_instance_of::<PyList>() {
+            Ok(ListOrTuple::List(ob.downcast::<PyList>()?.clone().unbind()))
+        } else if ob.is_instance_of::<PyTuple>() {
+            Ok(ListOrTuple::Tuple(ob.downcast::<PyTuple>()?.clone().unbind()))
+        } else {
+            Err(PyValueError::new_err("expected list or tuple"))
+        }
+    }
+}
+
+/// Prefixes a conversion error with the parameter it came from, keeping its type and cause.
+fn parameter_error(py: Python, index: usize, err: PyErr) -> PyErr {
+    let msg = err.value(py).to_string();
+    if msg.starts_with("error binding parameter") {
+        return err;
+    }
+    let wrapped = PyErr::from_type(
+        err.get_type(py),
+        format!("error binding parameter {}: {}", index + 1, msg),
+    );
+    wrapped.set_cause(py, Some(err));
+    wrapped
+}
+
+fn extract_parameters(
+    py: Python,
+    parameters: Option<ListOrTuple>,
+    types: &TypeConfig,
+) -> PyResult<libsql_core::params::Params> {
+    match parameters {
+        Some(p) => {
+            let mut params = Vec::new();
+            let (len, binder) = match &p {
+                ListOrTuple::List(l) => {
+                    let b = l.bind(py);
+                    (b.len(), b.as_any())
+                }
+                ListOrTuple::Tuple(t) => {
+                    let b = t.bind(py);
+                    (b.len(), b.as_any())
+                }
+            };
+            for i in 0..len {
+                let item = if let Ok(l) = binder.downcast::<PyList>() {
+                    l.get_item(i)?
+                } else {
+                    binder.downcast::<PyTuple>().unwrap().get_item(i)?
+                };
+                params.push(extract_parameter(py, i, &item, types).map_err(|e| parameter_error(py, i, e))?);
+            }
+            Ok(libsql_core::params::Params::Positional(params))
+        }
+        None => Ok(libsql_core::params::Params::None),
+    }
+}
+
+/// Names of a statement's parameters in order, without the `:` / `@` / `$` prefix; `None`
+/// for positional `?` and `?NNN` parameters.
+fn statement_parameters(stmt: &libsql_core::Statement) -> Vec<Option<String>> {
+    (1..=stmt.parameter_count() as i32)
+        .map(|i| {
+            stmt.parameter_name(i)
+                .filter(|name| !name.starts_with('?'))
+                .map(|name| name[1..].to_string())
+        })
+        .collect()
+}
+
+/// `row.get_value(idx)` for a fetched row, with `text_errors` applied to TEXT that is not
+/// valid UTF-8. The vendored libsql returns such text as a blob of its bytes, while the
+/// column type still says TEXT.
+fn row_value(
+    row: &libsql_core::Row,
+    idx: i32,
+    text_errors: TextErrors,
+) -> PyResult<libsql_core::Value> {
+    match row.get_value(idx).map_err(to_py_err)? {
+        libsql_core::Value::Blob(bytes)
+            if matches!(row.column_type(idx), Ok(libsql_core::ValueType::Text)) =>
+        {
+            match text_errors {
+                TextErrors::Strict => Err(DataError::new_err(format!(
+                    "column {} ({}) holds TEXT that is not valid UTF-8; pass text_errors=\"bytes\" \
+                     or \"replace\" to connect() to read it",
+                    idx + 1,
+                    row.column_name(idx).unwrap_or("?")
+                ))),
+                TextErrors::Bytes => Ok(libsql_core::Value::Blob(bytes)),
+                TextErrors::Replace => {
+                    Ok(libsql_core::Value::Text(String::from_utf8_lossy(&bytes).into_owned()))
+                }
+            }
+        }
+        value => Ok(value),
+    }
+}
+
+async fn collect_rows(
+    rows: &mut libsql_core::Rows,
+    text_errors: TextErrors,
+) -> PyResult<Vec<Vec<libsql_core::Value>>> {
+    let cc = rows.column_count();
+    let mut data = Vec::new();
+    while let Some(r) = rows.next().await.map_err(to_py_err)? {
+        let mut row = Vec::with_capacity(cc as usize);
+        for i in 0..cc {
+            row.push(row_value(&r, i, text_errors)?);
+        }
+        data.push(row);
+    }
+    Ok(data)
+}
+
+#[derive(Clone, Copy, Default)]
+struct ResultLimits {
+    max_rows: Option<u64>,
+    max_bytes: Option<u64>,
+}
+
+fn value_size(value: &libsql_core::Value) -> u64 {
+    match value {
+        libsql_core::Value::Null => 0,
+        libsql_core::Value::Integer(_) | libsql_core::Value::Real(_) => 8,
+        libsql_core::Value::Text(s) => s.len() as u64,
+        libsql_core::Value::Blob(b) => b.len() as u64,
+    }
+}
+
+/// A cursor's pending rows, including any read ahead of the caller by prefetching.
+struct ResultSet {
+    rows: Option<libsql_core::Rows>,
+    ahead: VecDeque<Vec<libsql_core::Value>>,
+    error: Option<PyErr>,
+    limits: ResultLimits,
+    text_errors: TextErrors,
+    rows_read: u64,
+    bytes_read: u64,
+}
+
+impl ResultSet {
+    fn new(rows: libsql_core::Rows, limits: ResultLimits, text_errors: TextErrors) -> Self {
+        ResultSet {
+            rows: Some(rows),
+            ahead: VecDeque::new(),
+            error: None,
+            limits,
+            text_errors,
+            rows_read: 0,
+            bytes_read: 0,
+        }
+    }
+
+    /// An empty result set that `append` fills, for statements run once per parameter set.
+    fn collected(limits: ResultLimits, text_errors: TextErrors) -> Self {
+        ResultSet {
+            rows: None,
+            ahead: VecDeque::new(),
+            error: None,
+            limits,
+            text_errors,
+            rows_read: 0,
+            bytes_read: 0,
+        }
+    }
+
+    /// Reads all of `rows` after the rows already collected; limits apply to the total.
+    async fn append(&mut self, rows: libsql_core::Rows) -> PyResult<()> {
+        self.rows = Some(rows);
+        while let Some(row) = self.read_row().await? {
+            self.ahead.push_back(row);
+        }
+        Ok(())
+    }
+
+    async fn read_row(&mut self) -> PyResult<Option<Vec<libsql_core::Value>>> {
+        let Some(rows) = self.rows.as_mut() else {
+            return Ok(None);
+        };
+        let Some(r) = rows.next().await.map_err(to_py_err)? else {
+            self.rows = None;
+            return Ok(None);
+        };
+        let cc = rows.column_count();
+        let mut row = Vec::with_capacity(cc as usize);
+        for i in 0..cc {
+            row.push(row_value(&r, i, self.text_errors)?);
+        }
+        self.rows_read += 1;
+        self.bytes_read += row.iter().map(value_size).sum::<u64>();
+        let exceeded = match self.limits {
+            ResultLimits { max_rows: Some(max), .. } if self.rows_read > max => {
+                Some(format!("result set exceeds max_rows ({})", max))
+            }
+            ResultLimits { max_bytes: Some(max), .. } if self.bytes_read > max => {
+                Some(format!("result set exceeds max_result_bytes ({})", max))
+            }
+            _ => None,
+        };
+        if let Some(msg) = exceeded {
+            self.rows = None;
+            return Err(DataError::new_err(msg));
+        }
+        Ok(Some(row))
+    }
+
+    async fn next_row(&mut self) -> PyResult<Option<Vec<libsql_core::Value>>> {
+        if let Some(row) = self.ahead.pop_front() {
+            return Ok(Some(row));
+        }
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.read_row().await
+    }
+
+    /// Takes up to `limit` rows (all of them for `None`). Rows are buffered as they are read,
+    /// so if the caller is cancelled while waiting for more, they are kept for the next call.
+    /// Rows that have been taken are gone from the cursor even if the caller never sees them.
+    async fn take_rows(&mut self, limit: Option<usize>) -> PyResult<Vec<Vec<libsql_core::Value>>> {
+        self.read_ahead(limit).await;
+        let wanted = limit.unwrap_or(usize::MAX);
+        if self.ahead.len() < wanted
+            && let Some(e) = self.error.take()
+        {
+            return Err(e);
+        }
+        let n = wanted.min(self.ahead.len());
+        Ok(self.ahead.drain(..n).collect())
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.rows.is_none() && self.ahead.is_empty() && self.error.is_none()
+    }
+
+    /// Reads rows until `limit` are waiting (all of them for `None`). A read error is
+    /// kept and returned by `next_row` once the rows before it have been taken.
+    async fn read_ahead(&mut self, limit: Option<usize>) {
+        while self.error.is_none() && limit.is_none_or(|n| self.ahead.len() < n) {
+            match self.read_row().await {
+                Ok(Some(row)) => self.ahead.push_back(row),
+                Ok(None) => break,
+                Err(e) => self.error = Some(e),
+            }
+        }
+    }
+}
+
+/// Starts reading the next `count` rows in the background while Python handles the current ones.
+fn prefetch_rows(rows: &RowsSlot, count: usize) {
+    if count == 0 {
+        return;
+    }
+    let rows = rows.clone();
+    pyo3_async_runtimes::tokio::get_runtime().spawn(async move {
+        if let Some(rs) = rows.lock().await.as_mut() {
+            rs.read_ahead(Some(count)).await;
+        }
+    });
+}
+
+fn convert_value(py: Python<'_>, value: libsql_core::Value) -> PyResult<PyObject> {
+    match value {
+        libsql_core::Value::Null => Ok(py.None()),
+        libsql_core::Value::Integer(v) => Ok(v.into_pyobject(py)?.unbind().into_any()),
+        libsql_core::Value::Real(v) => Ok(v.into_pyobject(py)?.unbind().into_any()),
+        libsql_core::Value::Text(v) => Ok(v.into_pyobject(py)?.unbind().into_any()),
+        libsql_core::Value::Blob(v) => Ok(PyBytes::new(py, &v).into_pyobject(py)?.unbind().into_any()),
+    }
+}
+
+fn convert_decimal(
+    py: Python<'_>,
+    value: libsql_core::Value,
+    format: DecimalFormat,
+) -> PyResult<PyObject> {
+    let decimal = py.import("decimal")?.getattr("Decimal")?;
+    match value {
+        libsql_core::Value::Integer(v) if format == DecimalFormat::Cents => {
+            Ok(decimal.call1((v,))?.call_method1("scaleb", (-2,))?.unbind())
+        }
+        libsql_core::Value::Integer(v) => Ok(decimal.call1((v,))?.unbind()),
+        libsql_core::Value::Real(v) => Ok(decimal.call1((v.to_string(),))?.unbind()),
+        libsql_core::Value::Text(v) => Ok(decimal.call1((v,))?.unbind()),
+        _ => convert_value(py, value),
+    }
+}
+
+fn convert_uuid(py: Python<'_>, value: libsql_core::Value) -> PyResult<PyObject> {
+    let uuid = py.import("uuid")?.getattr("UUID")?;
+    match value {
+        libsql_core::Value::Text(v) => Ok(uuid.call1((v,))?.unbind()),
+        libsql_core::Value::Blob(v) if v.len() == 16 => {
+            let kwargs = PyDict::new(py);
+            kwargs.set_item("bytes", PyBytes::new(py, &v))?;
+            Ok(uuid.call((), Some(&kwargs))?.unbind())
+        }
+        _ => convert_value(py, value),
+    }
+}
+
+/// Rows converted to Python per GIL acquisition by `rows_to_list`.
+const CONVERT_CHUNK: usize = 1000;
+
+/// Converts fetched rows to a list of tuples, releasing the GIL and yielding between chunks
+/// so a large `fetchall()` does not stall the event loop for the whole conversion.
+async fn rows_to_list(
+    data: Vec<Vec<libsql_core::Value>>,
+    kinds: &[ColumnKind],
+    types: &TypeConfig,
+) -> PyResult<PyObject> {
+    let list = Python::with_gil(|py| PyList::empty(py).unbind());
+    let mut data = data.into_iter().peekable();
+    while data.peek().is_some() {
+        Python::with_gil(|py| {
+            let list = list.bind(py);
+            for row in data.by_ref().take(CONVERT_CHUNK) {
+                let mut py_row = Vec::with_capacity(row.len());
+                for (i, v) in row.into_iter().enumerate() {
+                    py_row.push(convert_column(py, v, kinds.get(i), types)?);
+                }
+                list.append(PyTuple::new(py, py_row)?)?;
+            }
+            Ok::<_, PyErr>(())
+        })?;
+        tokio::task::yield_now().await;
+    }
+    Ok(list.into_any())
+}
+
+/// Builds `model(**row)` for `fetch_into()` / `fetchall_into()`, with the column names as
+/// keyword arguments. With `coerce`, a value that is not already an instance of the field's
+/// annotated class (or `X` of `Optional[X]`) is passed through it first, or through its
+/// `fromisoformat` for text, so a `datetime` field accepts a stored ISO string.
+struct ModelMapper {
+    model: PyObject,
+    names: Vec<String>,
+    coerce: Vec<Option<PyObject>>,
+}
+
+impl ModelMapper {
+    fn new(py: Python<'_>, model: PyObject, columns: Option<&[String]>, coerce: bool) -> PyResult<Self> {
+        let names = columns
+            .ok_or_else(|| ProgrammingError::new_err("no result set to fetch into a model"))?
+            .to_vec();
+        let mut targets: Vec<Option<PyObject>> = names.iter().map(|_| None).collect();
+        if coerce {
+            let typing = py.import("typing")?;
+            let hints = typing.call_method1("get_type_hints", (&model,))?;
+            let none_type = py.None().bind(py).get_type();
+            let union = typing.getattr("Union")?;
+            // `X | None` has its own origin from Python 3.10 on.
+            let union_type = py.import("types")?.getattr("UnionType").ok();
+            for (target, name) in targets.iter_mut().zip(&names) {
+                let mut hint = hints.call_method1("get", (name,))?;
+                if hint.is_none() {
+                    continue;
+                }
+                let origin = typing.call_method1("get_origin", (&hint,))?;
+                if !origin.is_none() {
+                    if !origin.is(&union) && !union_type.as_ref().is_some_and(|u| origin.is(u)) {
+                        continue;
+                    }
+                    let inner: Vec<Bound<'_, PyAny>> = typing
+                        .call_method1("get_args", (&hint,))?
+                        .try_iter()?
+                        .filter(|a| a.as_ref().map_or(true, |a| !a.is(&none_type)))
+                        .collect::<PyResult<_>>()?;
+                    match inner.as_slice() {
+                        [only] => hint = only.clone(),
+                        _ => continue,
+                    }
+                }
+                if hint.is_instance_of::<pyo3::types::PyType>() {
+                    *target = Some(hint.unbind());
+                }
+            }
+        }
+        Ok(Self { model, names, coerce: targets })
+    }
+
+    fn build(&self, py: Python<'_>, row: &Bound<'_, PyAny>) -> PyResult<PyObject> {
+        let kwargs = PyDict::new(py);
+        for ((name, target), value) in self.names.iter().zip(&self.coerce).zip(row.try_iter()?) {
+            let mut value = value?;
+            if let Some(target) = target {
+                let target = target.bind(py);
+                if !value.is_none() && !value.is_instance(target)? {
+                    value = if value.is_instance_of::<PyString>() && target.hasattr("fromisoformat")? {
+                        target.call_method1("fromisoformat", (value,))?
+                    } else {
+                        target.call1((value,))?
+                    };
+                }
+            }
+            kwargs.set_item(name, value)?;
+        }
+        self.model.call(py, (), Some(&kwargs))
+    }
+}
+
+fn convert_column(
+    py: Python<'_>,
+    value: libsql_core::Value,
+    kind: Option<&ColumnKind>,
+    types: &TypeConfig,
+) -> PyResult<PyObject> {
+    let value = match value {
+        libsql_core::Value::Real(v) if !v.is_finite() => match types.nonfinite {
+            NonFinite::Null => return Ok(py.None()),
+            NonFinite::Raise => {
+                return Err(DataError::new_err(format!("fetched float {} is not finite", v)))
+            }
+            _ => value,
+        },
+        value => value,
+    };
+    let kind = match kind {
+        Some(k) if *k != ColumnKind::Plain => *k,
+        _ => return convert_value(py, value),
+    };
+    if kind == ColumnKind::Real {
+        return match &value {
+            libsql_core::Value::Text(text)
+                if types.nonfinite == NonFinite::Text
+                    && matches!(text.as_str(), "NaN" | "Infinity" | "-Infinity") =>
+            {
+                convert_value(py, libsql_core::Value::Real(text.parse().unwrap_or(f64::NAN)))
+            }
+            _ => convert_value(py, value),
+        };
+    }
+    if kind == ColumnKind::Decimal {
+        return convert_decimal(py, value, types.decimal_format);
+    }
+    if kind == ColumnKind::Uuid {
+        return convert_uuid(py, value);
+    }
+    let datetime = py.import("datetime")?;
+    let class = match kind {
+        ColumnKind::Date => "date",
+        ColumnKind::Time => "time",
+        _ => "datetime",
+    };
+    let secs = match (&value, types.datetime_format) {
+        (libsql_core::Value::Text(text), _) => {
+            let parsed = datetime.getattr(class)?.call_method1("fromisoformat", (text.as_str(),))?;
+            return if kind == ColumnKind::Timestamp {
+                datetime_from_utc(&parsed, &types.timezone)
+            } else {
+                Ok(parsed.unbind())
+            };
+        }
+        (_, DateTimeFormat::Iso) => return convert_value(py, value),
+        (libsql_core::Value::Integer(v), DateTimeFormat::Unix) => *v as f64,
+        (libsql_core::Value::Real(v), DateTimeFormat::Unix) => *v,
+        (libsql_core::Value::Integer(v), DateTimeFormat::Julian) => {
+            (*v as f64 - UNIX_EPOCH_JULIAN_DAY) * 86400.0
+        }
+        (libsql_core::Value::Real(v), DateTimeFormat::Julian) => {
+            (v - UNIX_EPOCH_JULIAN_DAY) * 86400.0
+        }
+        _ => return convert_value(py, value),
+    };
+    if kind == ColumnKind::Time {
+        return convert_value(py, value);
+    }
+    let epoch = datetime.getattr("datetime")?.call1((1970, 1, 1))?;
+    let delta = datetime.getattr("timedelta")?.call1((0, secs))?;
+    let dt = epoch.call_method1("__add__", (delta,))?;
+    if kind == ColumnKind::Date {
+        Ok(dt.call_method0("date")?.unbind())
+    } else {
+        datetime_from_utc(&dt, &types.timezone)
+    }
+}
+
+async fn begin_transaction(conn: &libsql_core::Connection, behavior: Option<&str>) -> PyResult<()> {
+    let sql = match behavior {
+        Some(b) if !b.is_empty() => format!("BEGIN {}", b),
+        _ => "BEGIN".to_string(),
+    };
+    conn.execute(&sql, ()).await.map_err(to_py_err)?;
+    Ok(())
+}
+
+/// Validates an `isolation_level` value as `sqlite3` does: `None` for autocommit, or `""`,
+/// `DEFERRED`, `IMMEDIATE` or `EXCLUSIVE` in any case.
+fn isolation_keyword(value: Option<String>) -> PyResult<Option<String>> {
+    value
+        .map(|v| sql_keyword("isolation_level", &v, &["", "DEFERRED", "IMMEDIATE", "EXCLUSIVE"]))
+        .transpose()
+}
+
+/// Whether a remote request was rejected because its Hrana stream no longer existed on the
+/// server. The server refuses the whole request before running any of it then, so the
+/// statement can be sent again on a fresh stream.
+fn is_stream_lost(err: &libsql_core::Error) -> bool {
+    if !matches!(err, libsql_core::Error::Hrana(_)) {
+        return false;
+    }
+    let msg = err.to_string().to_ascii_lowercase();
+    ["stream_expired", "stream has expired", "stream_not_found", "stream not found"]
+        .iter()
+        .any(|s| msg.contains(s))
+}
+
+/// Whether a remote request failed in transit. The server may have run it and only the
+/// response been lost, so it is never replayed.
+fn is_transport_error(err: &libsql_core::Error) -> bool {
+    matches!(err, libsql_core::Error::Hrana(_))
+        && err.to_string().to_ascii_lowercase().contains("http error")
+}
+
+async fn retry_lost_stream<T, F, Fut>(
+    conn: &libsql_core::Connection,
+    remote: bool,
+    f: F,
+) -> libsql_core::Result<T>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = libsql_core::Result<T>>,
+{
+    let in_tx = !conn.is_autocommit();
+    match f().await {
+        Err(e) if remote && is_stream_lost(&e) => {
+            conn.reset().await;
+            if in_tx { Err(e) } else { f().await }
+        }
+        Err(e) if remote && !in_tx && is_transport_error(&e) => {
+            // The baton the server sent back may have been lost with the response.
+            conn.reset().await;
+            Err(e)
+        }
+        res => res,
+    }
+}
+
+/// Upper bound for the doubling delay between busy retries.
+const BUSY_RETRY_MAX_DELAY: Duration = Duration::from_secs(2);
+
+/// How often and how patiently a statement failing with a busy or locked error is run again,
+/// from the `busy_retries` / `busy_retry_delay` arguments of `connect()`.
+#[derive(Clone, Copy, Default)]
+struct BusyRetry {
+    retries: u32,
+    delay: Duration,
+}
+
+impl BusyRetry {
+    /// Decides whether a failed attempt is tried again and, if so, waits before it: only busy
+    /// and locked errors are retried, and only when the connection was outside a transaction
+    /// before the attempt (`in_tx`, read before running it) and still is. SQLite may roll a
+    /// transaction back on its own when a statement in it fails, so checking afterwards alone
+    /// would replay that statement in autocommit mode and lose the writes before it. The delay
+    /// doubles after every attempt, up to `BUSY_RETRY_MAX_DELAY`.
+    async fn backoff(
+        &self,
+        err: &PyErr,
+        attempt: &mut u32,
+        in_tx: bool,
+        conn: &SyncMutex<Option<libsql_core::Connection>>,
+        metrics: &ConnectionMetrics,
+    ) -> bool {
+        if *attempt >= self.retries
+            || in_tx
+            || in_transaction(conn)
+            || !Python::with_gil(|py| err.is_instance_of::<TimeoutError>(py))
+        {
+            return false;
+        }
+        let delay = self.delay.saturating_mul(1 << (*attempt).min(16)).min(BUSY_RETRY_MAX_DELAY);
+        *attempt += 1;
+        metrics.busy_retries.fetch_add(1, Ordering::Relaxed);
+        tokio::time::sleep(delay).await;
+        true
+    }
+}
+
+/// Whether the connection is inside a transaction; `false` once it has been closed.
+fn in_transaction(conn: &SyncMutex<Option<libsql_core::Connection>>) -> bool {
+    conn.lock().as_ref().is_some_and(|c| !c.is_autocommit())
+}
+
+fn determine_autocommit(autocommit: i32, isolation_level: &Option<String>) -> bool {
+    match autocommit {
+        LEGACY_TRANSACTION_CONTROL => isolation_level.is_none(),
+        _ => autocommit != 0,
+    }
+}
+
+/// What kind of token an [`SqlToken`] is.
+#[derive(Clone, Copy, PartialEq)]
+enum SqlTokenKind {
+    /// A keyword, bare identifier or number.
+    Word,
+    /// A string literal or quoted identifier: `'...'`, `"..."`, `` `...` `` or `[...]`.
+    Quoted,
+    /// A placeholder: `?`, `?NNN`, `:name`, `@name` or `$name`.
+    Parameter,
+    /// Any other character, such as `(`, `;` or `=`.
+    Punct,
+}
+
+/// A token of an SQL string and its byte offset in it.
+#[derive(Clone, Copy)]
+struct SqlToken<'a> {
+    kind: SqlTokenKind,
+    text: &'a str,
+    start: usize,
+}
+
+impl SqlToken<'_> {
+    fn is_word(&self, word: &str) -> bool {
+        self.kind == SqlTokenKind::Word && self.text.eq_ignore_ascii_case(word)
+    }
+
+    fn is_punct(&self, punct: &str) -> bool {
+        self.kind == SqlTokenKind::Punct && self.text == punct
+    }
+
+    fn end(&self) -> usize {
+        self.start + self.text.len()
+    }
+}
+
+fn is_sql_word_byte(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_' || c == b'$' || c >= 0x80
+}
+
+/// Splits `sql` into tokens, skipping whitespace and comments. Every statement check and
+/// placeholder rewrite goes through this one scanner, so they agree on where literals,
+/// identifiers and comments start and end. An unterminated literal or comment runs to the
+/// end of the string.
+fn sql_tokens(sql: &str) -> impl Iterator<Item = SqlToken<'_>> {
+    let b = sql.as_bytes();
+    let mut i = 0;
+    std::iter::from_fn(move || {
+        i = skip_sql_trivia(b, i);
+        let start = i;
+        let kind = match *b.get(i)? {
+            q @ (b'\'' | b'"' | b'`') => {
+                i += 1;
+                while i < b.len() {
+                    if b[i] == q && b.get(i + 1) == Some(&q) {
+                        i += 2;
+                    } else if b[i] == q {
+                        i += 1;
+                        break;
+                    } else {
+                        i += 1;
+                    }
+                }
+                SqlTokenKind::Quoted
+            }
+            b'[' => {
+                i = b[i..].iter().position(|&c| c == b']').map_or(b.len(), |p| i + p + 1);
+                SqlTokenKind::Quoted
+            }
+            b'?' => {
+                i += 1;
+                while i < b.len() && b[i].is_ascii_digit() {
+                    i += 1;
+                }
+                SqlTokenKind::Parameter
+            }
+            b':' | b'@' | b'$'
+                if b.get(i + 1).is_some_and(|c| c.is_ascii_alphabetic() || *c == b'_') =>
+            {
+                i += 1;
+                while i < b.len() && is_sql_word_byte(b[i]) {
+                    i += 1;
+                }
+                SqlTokenKind::Parameter
+            }
+            c if c != b'$' && is_sql_word_byte(c) => {
+                while i < b.len() && is_sql_word_byte(b[i]) {
+                    i += 1;
+                }
+                SqlTokenKind::Word
+            }
+            _ => {
+                i += 1;
+                SqlTokenKind::Punct
+            }
+        };
+        Some(SqlToken { kind, text: &sql[start..i], start })
+    })
+}
+
+/// The keyword that decides what a statement does: its first word, or for `WITH ...` the
+/// first `SELECT` / `INSERT` / `UPDATE` / `DELETE` / `REPLACE` / `VALUES` outside the common
+/// table expressions. libsql does not expose `sqlite3_stmt_readonly`, so the statement is
+/// classified from its tokens instead.
+fn statement_verb(sql: &str) -> String {
+    let mut with = false;
+    let mut depth = 0usize;
+    for token in sql_tokens(sql) {
+        if token.is_punct("(") {
+            depth += 1;
+        } else if token.is_punct(")") {
+            depth = depth.saturating_sub(1);
+        } else if token.kind == SqlTokenKind::Word {
+            let word = token.text.to_ascii_uppercase();
+            if !with && word != "WITH" {
+                return word;
+            }
+            if with
+                && depth == 0
+                && ["SELECT", "INSERT", "UPDATE", "DELETE", "REPLACE", "VALUES"].contains(&word.as_str())
+            {
+                return word;
+            }
+            with = true;
+        }
+    }
+    if with { "WITH".to_string() } else { String::new() }
+}
+
+fn stmt_is_dml(sql: &str) -> bool {
+    matches!(
+        statement_verb(sql).as_str(),
+        "INSERT"
+            | "UPDATE"
+            | "DELETE"
+            | "REPLACE"
+            | "CREATE"
+            | "DROP"
+            | "ALTER"
+            | "ATTACH"
+            | "DETACH"
+            | "VACUUM"
+            | "REINDEX"
+            | "ANALYZE"
+    )
+}
+
+fn stmt_is_insert(sql: &str) -> bool {
+    matches!(statement_verb(sql).as_str(), "INSERT" | "REPLACE")
+}
+
+/// Pragmas that take a table or index name in parentheses and only report on it.
+const QUERY_PRAGMAS: &[&str] = &[
+    "foreign_key_check",
+    "foreign_key_list",
+    "index_info",
+    "index_list",
+    "index_xinfo",
+    "integrity_check",
+    "quick_check",
+    "table_info",
+    "table_list",
+    "table_xinfo",
+];
+
+/// Whether a `PRAGMA` sets something, as in `PRAGMA user_version = 3` or
+/// `PRAGMA main.journal_mode(WAL)`, rather than only reading it.
+fn pragma_assigns(sql: &str) -> bool {
+    let mut tokens = sql_tokens(sql).skip_while(|t| !t.is_word("PRAGMA")).skip(1);
+    let (mut name, mut next) = (tokens.next(), tokens.next());
+    if next.is_some_and(|t| t.is_punct(".")) {
+        (name, next) = (tokens.next(), tokens.next());
+    }
+    match (name, next) {
+        (_, Some(t)) if t.is_punct("=") => true,
+        (Some(name), Some(t)) if t.is_punct("(") => {
+            !QUERY_PRAGMAS.iter().any(|q| name.text.eq_ignore_ascii_case(q))
+        }
+        _ => false,
+    }
+}
+
+/// Whether a statement may change the database: everything [`stmt_is_dml`] covers, plus
+/// pragmas that set a value. Those stay out of `stmt_is_dml` because many of them, such as
+/// `journal_mode`, can't run inside the implicit transaction it opens.
+fn stmt_writes(sql: &str) -> bool {
+    stmt_is_dml(sql) || (statement_verb(sql) == "PRAGMA" && pragma_assigns(sql))
+}
+
+fn stmt_is_read(sql: &str) -> bool {
+    !stmt_writes(sql)
+}
+
+/// A `SELECT` / `VALUES` query; unlike [`stmt_is_read`] this leaves out `PRAGMA` and other
+/// statements that may change connection state.
+fn is_plain_read(sql: &str) -> bool {
+    matches!(statement_verb(sql).as_str(), "SELECT" | "VALUES")
+}
+
+fn skip_sql_trivia(sql: &[u8], mut i: usize) -> usize {
+    while i < sql.len() {
+        if sql[i].is_ascii_whitespace() {
+            i += 1;
+        } else if sql[i..].starts_with(b"--") {
+            while i < sql.len() && sql[i] != b'\n' {
+                i += 1;
+            }
+        } else if sql[i..].starts_with(b"/*") {
+            i = sql[i + 2..]
+                .windows(2)
+                .position(|w| w == b"*/")
+                .map_or(sql.len(), |p| i + 2 + p + 2);
+        } else {
+            break;
+        }
+    }
+    i
+}
+
+/// Byte offset just past the `;` that ends the first statement, or `None` if there is
+/// none. Semicolons inside the `BEGIN ... END` body of a `CREATE TRIGGER` don't end it.
+fn first_statement_end(sql: &str) -> Option<usize> {
+    let mut words: Vec<SqlToken<'_>> = Vec::new();
+    let mut in_trigger_body = false;
+    let mut case_depth = 0usize;
+    for token in sql_tokens(sql) {
+        if token.is_punct(";") && !in_trigger_body {
+            return Some(token.end());
+        }
+        if token.kind != SqlTokenKind::Word {
+            continue;
+        }
+        let is_trigger = words.first().is_some_and(|w| w.is_word("CREATE"))
+            && words.iter().skip(1).any(|w| w.is_word("TRIGGER"));
+        if is_trigger {
+            match token.text.to_ascii_uppercase().as_str() {
+                "BEGIN" if !in_trigger_body => in_trigger_body = true,
+                "CASE" if in_trigger_body => case_depth += 1,
+                "END" if in_trigger_body && case_depth > 0 => case_depth -= 1,
+                "END" if in_trigger_body => in_trigger_body = false,
+                _ => {}
+            }
+        }
+        if words.len() < 3 {
+            words.push(token);
+        }
+    }
+    None
+}
+
+/// The SQL after the first statement, without the semicolons and comments that end it.
+fn statement_tail(sql: &str) -> Option<&str> {
+    let end = first_statement_end(sql)?;
+    sql_tokens(&sql[end..]).find(|t| !t.is_punct(";")).map(|t| &sql[end + t.start..])
+}
+
+fn check_single_statement(sql: &str) -> PyResult<()> {
+    match statement_tail(sql) {
+        Some(tail) => {
+            let tail: String = tail.chars().take(40).collect();
+            Err(ProgrammingError::new_err(format!(
+                "you can only execute one statement at a time, use executescript() (found: {})",
+                tail
+            )))
+        }
+        None => Ok(()),
+    }
+}
+
+const MAX_VARIABLE_NUMBER: usize = 32766;
+
+/// Byte offsets of every `?` placeholder outside literals, identifiers and comments.
+/// Errors if the statement also uses numbered or named placeholders.
+fn qmark_positions(sql: &str) -> PyResult<Vec<usize>> {
+    let mut found = Vec::new();
+    for token in sql_tokens(sql).filter(|t| t.kind == SqlTokenKind::Parameter) {
+        match token.text {
+            "?" => found.push(token.start),
+            numbered if numbered.starts_with('?') => {
+                return Err(ProgrammingError::new_err(
+                    "sequence parameters cannot be used with numbered placeholders (?NNN)",
+                ));
+            }
+            _ => {
+                return Err(ProgrammingError::new_err(
+                    "sequence parameters cannot be used with named placeholders",
+                ));
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Checks that a statement gets exactly as many positional values as it has parameters.
+/// For numbered placeholders that is the largest `?NNN`, so `?1, ?3` needs three values
+/// and every index used must be in range. Remote statements don't know their parameters
+/// before they run, so callers skip the check for them.
+fn check_parameter_count(expected: usize, params: &libsql_core::params::Params) -> PyResult<()> {
+    let supplied = match params {
+        libsql_core::params::Params::Positional(values) => values.len(),
+        libsql_core::params::Params::None => 0,
+        libsql_core::params::Params::Named(_) => return Ok(()),
+    };
+    if supplied != expected {
+        return Err(ProgrammingError::new_err(format!(
+            "Incorrect number of bindings supplied. The current statement uses {}, and there are {} supplied.",
+            expected, supplied
+        )));
+    }
+    Ok(())
+}
+
+/// The values bound by `extract_parameters`, which only produces positional parameters.
+fn positional_values(params: &libsql_core::params::Params) -> Vec<libsql_core::Value> {
+    match params {
+        libsql_core::params::Params::Positional(values) => values.clone(),
+        _ => Vec::new(),
+    }
+}
+
+/// An SQL literal for `value`, as `sqlite3_expanded_sql` writes it.
+fn sql_literal(value: &libsql_core::Value) -> String {
+    match value {
+        libsql_core::Value::Null => "NULL".to_string(),
+        libsql_core::Value::Integer(i) => i.to_string(),
+        libsql_core::Value::Real(f) => format!("{:?}", f),
+        libsql_core::Value::Text(t) => format!("'{}'", t.replace('\'', "''")),
+        libsql_core::Value::Blob(b) => {
+            format!("x'{}'", b.iter().map(|c| format!("{:02x}", c)).collect::<String>())
+        }
+    }
+}
+
+/// `sql` with every placeholder replaced by the literal of the value bound to it. Placeholders
+/// are numbered as SQLite does: `?` takes the next index, `?NNN` index NNN, and a named
+/// parameter the next index at its first use. Unbound parameters become `NULL`.
+fn expand_sql(sql: &str, values: &[libsql_core::Value]) -> String {
+    let mut out = String::with_capacity(sql.len());
+    let mut names: Vec<(&str, usize)> = Vec::new();
+    let mut next = 0usize;
+    let mut last = 0;
+    for token in sql_tokens(sql).filter(|t| t.kind == SqlTokenKind::Parameter) {
+        let index = match token.text.strip_prefix('?') {
+            Some(digits) => digits.parse::<usize>().unwrap_or(next + 1),
+            None => match names.iter().find(|(n, _)| *n == token.text) {
+                Some(&(_, index)) => index,
+                None => {
+                    names.push((token.text, next + 1));
+                    next + 1
+                }
+            },
+        };
+        next = next.max(index);
+        out.push_str(&sql[last..token.start]);
+        out.push_str(&values.get(index.wrapping_sub(1)).map_or("NULL".to_string(), sql_literal));
+        last = token.end();
+    }
+    out.push_str(&sql[last..]);
+    out
+}
+
+/// Expands list/tuple parameters into one placeholder per element, so that
+/// `x IN (?)` can be bound to a sequence.
+fn expand_sequence_parameters(
+    py: Python,
+    sql: String,
+    parameters: Option<ListOrTuple>,
+) -> PyResult<(String, Option<ListOrTuple>)> {
+    let Some(p) = parameters else {
+        return Ok((sql, None));
+    };
+    let items: Vec<Bound<'_, PyAny>> = match &p {
+        ListOrTuple::List(l) => l.bind(py).iter().collect(),
+        ListOrTuple::Tuple(t) => t.bind(py).iter().collect(),
+    };
+    let is_seq = |item: &Bound<'_, PyAny>| item.is_instance_of::<PyList>() || item.is_instance_of::<PyTuple>();
+    if !items.iter().any(is_seq) {
+        return Ok((sql, Some(p)));
+    }
+    let positions = qmark_positions(&sql)?;
+    if positions.len() != items.len() {
+        return Err(ProgrammingError::new_err(format!(
+            "Incorrect number of bindings supplied. The current statement uses {}, and there are {} supplied.",
+            positions.len(),
+            items.len()
+        )));
+    }
+    let mut expanded = String::with_capacity(sql.len());
+    let mut flat = Vec::new();
+    let mut last = 0;
+    for (pos, item) in positions.into_iter().zip(items) {
+        expanded.push_str(&sql[last..pos]);
+        last = pos + 1;
+        if is_seq(&item) {
+            let n = item.len()?;
+            expanded.push_str(&vec!["?"; n].join(", "));
+            flat.extend(item.try_iter()?.collect::<PyResult<Vec<_>>>()?);
+        } else {
+            expanded.push('?');
+            flat.push(item);
+        }
+    }
+    expanded.push_str(&sql[last..]);
+    if flat.len() > MAX_VARIABLE_NUMBER {
+        return Err(ProgrammingError::new_err(format!(
+            "too many SQL variables after expanding sequence parameters ({} > {}); only DELETE ... IN (?) is split automatically",
+            flat.len(),
+            MAX_VARIABLE_NUMBER
+        )));
+    }
+    Ok((expanded, Some(ListOrTuple::Tuple(PyTuple::new(py, flat)?.unbind()))))
+}
+
+/// Splits a `DELETE` whose one oversized `IN (?)` sequence would expand past
+/// `MAX_VARIABLE_NUMBER` into parameter sets for a single statement with a fixed-size list.
+/// The sequence is de-duplicated and its last chunk padded with repeats, which `IN` ignores.
+/// Deleting is idempotent, so a row that another condition matches in every chunk is still
+/// deleted and counted once. Returns `None` when the statement fits or isn't a `DELETE`, and
+/// errors for a `DELETE` that [`delete_in_splits`] rejects.
+fn split_sequence_parameters<'py>(
+    py: Python<'py>,
+    sql: &str,
+    parameters: Option<&ListOrTuple>,
+) -> PyResult<Option<(String, Bound<'py, PyList>)>> {
+    let Some(p) = parameters else {
+        return Ok(None);
+    };
+    let items: Vec<Bound<'_, PyAny>> = match p {
+        ListOrTuple::List(l) => l.bind(py).iter().collect(),
+        ListOrTuple::Tuple(t) => t.bind(py).iter().collect(),
+    };
+    let is_seq = |item: &Bound<'_, PyAny>| item.is_instance_of::<PyList>() || item.is_instance_of::<PyTuple>();
+    let lens = items
+        .iter()
+        .map(|item| if is_seq(item) { item.len() } else { Ok(1) })
+        .collect::<PyResult<Vec<_>>>()?;
+    let total: usize = lens.iter().sum();
+    let largest = lens
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| is_seq(&items[*i]))
+        .max_by_key(|(_, n)| **n);
+    let Some((big, &big_len)) = largest else {
+        return Ok(None);
+    };
+    let fixed = total - big_len;
+    if total <= MAX_VARIABLE_NUMBER || fixed >= MAX_VARIABLE_NUMBER || statement_verb(sql) != "DELETE" {
+        return Ok(None);
+    }
+    let positions = qmark_positions(sql)?;
+    if positions.len() != items.len() {
+        return Ok(None);
+    }
+    let tokens: Vec<SqlToken<'_>> = sql_tokens(sql).collect();
+    if !delete_in_splits(&tokens, positions[big]) {
+        return Err(ProgrammingError::new_err(format!(
+            "too many SQL variables after expanding sequence parameters ({} > {}); a DELETE is only split when its WHERE is `column IN (?)` AND/OR-ed with conditions that don't read the table",
+            total, MAX_VARIABLE_NUMBER
+        )));
+    }
+    // Unhashable values such as `bytearray` are kept as they are instead of de-duplicated.
+    let seen = pyo3::types::PySet::empty(py)?;
+    let mut values = Vec::new();
+    for value in items[big].try_iter()? {
+        let value = value?;
+        if value.hash().is_ok() {
+            if seen.contains(&value)? {
+                continue;
+            }
+            seen.add(&value)?;
+        }
+        values.push(value);
+    }
+    let chunk = (MAX_VARIABLE_NUMBER - fixed).min(values.len()).max(1);
+    let mut expanded = String::with_capacity(sql.len());
+    let mut last = 0;
+    for (i, (pos, n)) in positions.iter().zip(&lens).enumerate() {
+        expanded.push_str(&sql[last..*pos]);
+        last = pos + 1;
+        let n = if i == big { chunk } else { *n };
+        expanded.push_str(&vec!["?"; n].join(", "));
+    }
+    expanded.push_str(&sql[last..]);
+    let sets = PyList::empty(py);
+    for part in values.chunks(chunk) {
+        let mut flat = Vec::with_capacity(fixed + chunk);
+        for (i, item) in items.iter().enumerate() {
+            if i == big {
+                flat.extend(part.iter().cloned());
+                flat.extend(std::iter::repeat_n(part[0].clone(), chunk - part.len()));
+            } else if is_seq(item) {
+                flat.extend(item.try_iter()?.collect::<PyResult<Vec<_>>>()?);
+            } else {
+                flat.push(item.clone());
+            }
+        }
+        sets.append(PyTuple::new(py, flat)?)?;
+    }
+    Ok(Some((expanded, sets)))
+}
+
+/// Whether running a `DELETE` once per chunk of the `IN (?)` list whose placeholder starts at
+/// byte `param` deletes the same rows as running it once. That holds when its `WHERE` is
+/// `column IN (?)` AND/OR-ed with other terms: the condition can only go from false to true
+/// as the list grows, so some chunk matches a row exactly when the whole list does. The other
+/// terms must not read the table, since each chunk sees the rows the earlier ones deleted,
+/// and `ORDER BY` / `LIMIT` would apply per chunk.
+fn delete_in_splits(tokens: &[SqlToken<'_>], param: usize) -> bool {
+    let name = |t: &SqlToken<'_>| match t.kind {
+        SqlTokenKind::Word => Some(t.text.to_ascii_lowercase()),
+        SqlTokenKind::Quoted if !t.text.starts_with('\'') => {
+            Some(t.text[1..t.text.len().max(2) - 1].to_ascii_lowercase())
+        }
+        _ => None,
+    };
+    if !tokens.first().is_some_and(|t| t.is_word("DELETE")) || !tokens.get(1).is_some_and(|t| t.is_word("FROM")) {
+        return false;
+    }
+    let Some(where_at) = tokens.iter().position(|t| t.is_word("WHERE")) else {
+        return false;
+    };
+    // The table, its schema and alias, and an index name, which only makes this stricter.
+    let targets: Vec<String> = tokens[2..where_at]
+        .iter()
+        .filter_map(name)
+        .filter(|n| !["as", "indexed", "not", "by"].contains(&n.as_str()))
+        .collect();
+    let mut terms: Vec<&[SqlToken<'_>]> = Vec::new();
+    let (mut depth, mut start, mut end) = (0usize, where_at + 1, tokens.len());
+    for (i, token) in tokens.iter().enumerate().skip(where_at + 1) {
+        if token.is_punct("(") {
+            depth += 1;
+        } else if token.is_punct(")") {
+            depth = depth.saturating_sub(1);
+        } else if depth > 0 {
+            continue;
+        } else if token.is_word("AND") || token.is_word("OR") {
+            terms.push(&tokens[start..i]);
+            start = i + 1;
+        } else if token.is_word("RETURNING") || token.is_punct(";") {
+            end = i;
+            break;
+        } else if ["ORDER", "LIMIT", "BETWEEN", "CASE"].iter().any(|w| token.is_word(w)) {
+            return false;
+        }
+    }
+    terms.push(&tokens[start..end]);
+    let is_list = |term: &[SqlToken<'_>]| match term {
+        [column @ .., keyword, open, placeholder, close] => {
+            keyword.is_word("IN")
+                && open.is_punct("(")
+                && placeholder.kind == SqlTokenKind::Parameter
+                && placeholder.start == param
+                && close.is_punct(")")
+                && column.len() % 2 == 1
+                && column
+                    .iter()
+                    .enumerate()
+                    .all(|(i, t)| if i % 2 == 1 { t.is_punct(".") } else { name(t).is_some() })
+        }
+        _ => false,
+    };
+    let reads_target = |term: &[SqlToken<'_>]| {
+        term.iter().any(|t| t.is_word("SELECT")) && term.iter().filter_map(name).any(|n| targets.contains(&n))
+    };
+    terms.iter().any(|&t| is_list(t)) && terms.iter().all(|&t| is_list(t) || !reads_target(t))
+}
+
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Turns `on_conflict="ignore"` into the ` OR IGNORE` that follows `INSERT`.
+fn conflict_clause(on_conflict: Option<String>) -> PyResult<String> {
+    match on_conflict {
+        Some(clause) => {
+            let clause = clause.to_ascii_uppercase();
+            if !["ABORT", "FAIL", "IGNORE", "REPLACE", "ROLLBACK"].contains(&clause.as_str()) {
+                return Err(PyValueError::new_err(format!("unknown conflict clause: {}", clause)));
+            }
+            Ok(format!(" OR {}", clause))
+        }
+        None => Ok(String::new()),
+    }
+}
+
+/// `(type, name, sql)` of the user objects in a connection's main schema, in creation order.
+async fn schema_objects(conn: &libsql_core::Connection) -> PyResult<Vec<(String, String, String)>> {
+    let mut rows = conn
+        .query(
+            "SELECT type, name, sql FROM sqlite_schema \
+             WHERE name NOT LIKE 'sqlite_%' AND sql IS NOT NULL ORDER BY rowid",
+            (),
+        )
+        .await
+        .map_err(to_py_err)?;
+    let mut objects = Vec::new();
+    while let Some(row) = rows.next().await.map_err(to_py_err)? {
+        objects.push((
+            row.get::<String>(0).map_err(to_py_err)?,
+            row.get::<String>(1).map_err(to_py_err)?,
+            row.get::<String>(2).map_err(to_py_err)?,
+        ));
+    }
+    Ok(objects)
+}
+
+/// Replaces the main database of `dst` with the tables, rows, indexes, triggers, views and
+/// `user_version` of `src`'s, for `Connection.backup()`. libsql does not expose the
+/// `sqlite3_backup` API, so the copy is made with SQL on both connections, which also works
+/// when either of them is remote. Returns the number of rows copied.
+async fn copy_database(src: &libsql_core::Connection, dst: &libsql_core::Connection) -> PyResult<u64> {
+    let objects = schema_objects(src).await?;
+    if let Some((_, name, _)) = objects
+        .iter()
+        .find(|(_, _, sql)| sql.to_ascii_uppercase().starts_with("CREATE VIRTUAL TABLE"))
+    {
+        return Err(ProgrammingError::new_err(format!(
+            "backup() cannot copy virtual table {}",
+            name
+        )));
+    }
+    // Rows are inserted table by table, so foreign keys are only checked at commit.
+    dst.execute("PRAGMA defer_foreign_keys = ON", ()).await.map_err(to_py_err)?;
+    let existing = schema_objects(dst).await?;
+    for kind in ["view", "table"] {
+        for (_, name, _) in existing.iter().filter(|(k, _, _)| k == kind) {
+            let sql = format!("DROP {} IF EXISTS {}", kind.to_ascii_uppercase(), quote_identifier(name));
+            dst.execute(&sql, ()).await.map_err(to_py_err)?;
+        }
+    }
+    let mut total = 0u64;
+    for (_, name, sql) in objects.iter().filter(|(kind, _, _)| kind == "table") {
+        dst.execute(sql, ()).await.map_err(to_py_err)?;
+        let table = quote_identifier(name);
+        let mut rows = src.query(&format!("SELECT * FROM {}", table), ()).await.map_err(to_py_err)?;
+        let width = rows.column_count();
+        let stmt = dst
+            .prepare(&format!("INSERT INTO {} VALUES ({})", table, vec!["?"; width as usize].join(", ")))
+            .await
+            .map_err(to_py_err)?;
+        while let Some(row) = rows.next().await.map_err(to_py_err)? {
+            let values = (0..width)
+                .map(|i| row_value(&row, i, TextErrors::Strict))
+                .collect::<PyResult<Vec<_>>>()?;
+            let res = stmt.execute(values).await;
+            stmt.reset();
+            res.map_err(to_py_err)?;
+            total += 1;
+        }
+    }
+    let mut sequence = src
+        .query("SELECT name, seq FROM sqlite_sequence", ())
+        .await
+        .map(Some)
+        .or_else(|e| if e.to_string().contains("no such table") { Ok(None) } else { Err(e) })
+        .map_err(to_py_err)?;
+    if let Some(rows) = sequence.as_mut() {
+        dst.execute("DELETE FROM sqlite_sequence", ()).await.map_err(to_py_err)?;
+        while let Some(row) = rows.next().await.map_err(to_py_err)? {
+            let values = vec![
+                row_value(&row, 0, TextErrors::Strict)?,
+                row_value(&row, 1, TextErrors::Strict)?,
+            ];
+            dst.execute("INSERT INTO sqlite_sequence (name, seq) VALUES (?, ?)", values)
+                .await
+                .map_err(to_py_err)?;
+        }
+    }
+    for (_, _, sql) in objects.iter().filter(|(kind, _, _)| kind != "table") {
+        dst.execute(sql, ()).await.map_err(to_py_err)?;
+    }
+    let mut rows = src.query("PRAGMA user_version", ()).await.map_err(to_py_err)?;
+    if let Some(row) = rows.next().await.map_err(to_py_err)? {
+        let version = row.get::<i64>(0).map_err(to_py_err)?;
+        dst.execute_batch(&format!("PRAGMA user_version = {}", version))
+            .await
+            .map_err(to_py_err)?;
+    }
+    Ok(total)
+}
+
+/// Values of one `insert_many` row: a mapping is looked up by column name, anything else is
+/// taken as a sequence in column order.
+fn insert_row_values(
+    py: Python,
+    row: &Bound<'_, PyAny>,
+    columns: Option<&[String]>,
+    width: usize,
+    types: &TypeConfig,
+) -> PyResult<Vec<libsql_core::Value>> {
+    let items: Vec<Bound<'_, PyAny>> = if let Ok(map) = row.downcast::<PyDict>() {
+        let columns = columns.ok_or_else(|| PyValueError::new_err("mapping rows need columns"))?;
+        columns
+            .iter()
+            .map(|c| {
+                map.get_item(c)?
+                    .ok_or_else(|| PyValueError::new_err(format!("row has no value for column {:?}", c)))
+            })
+            .collect::<PyResult<_>>()?
+    } else {
+        row.extract()?
+    };
+    if items.len() != width {
+        return Err(PyValueError::new_err(format!(
+            "expected {} values per row, got {}",
+            width,
+            items.len()
+        )));
+    }
+    items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| extract_parameter(py, i, item, types))
+        .collect()
+}
+
+fn open_text_source<'py>(
+    source: &Bound<'py, PyAny>,
+    mode: &str,
+) -> PyResult<(Bound<'py, PyAny>, bool)> {
+    let py = source.py();
+    if source.is_instance_of::<PyString>() || source.hasattr("__fspath__")? {
+        let kwargs = PyDict::new(py);
+        kwargs.set_item("newline", "")?;
+        kwargs.set_item("encoding", "utf-8")?;
+        let file = py
+            .import("builtins")?
+            .getattr("open")?
+            .call((source, mode), Some(&kwargs))?;
+        Ok((file, true))
+    } else {
+        Ok((source.clone(), false))
+    }
+}
+
+/// Wraps a statement in its own transaction when `is_tx` is set. If the future running the
+/// statement is dropped (the Python task was cancelled) before `commit` or `rollback`, the
+/// transaction is rolled back in the background while still holding the connection's
+/// statement lock, so the next statement never runs inside it.
+struct TxGuard {
+    conn: libsql_core::Connection,
+    started: bool,
+    done: bool,
+    exec: Option<OwnedMutexGuard<()>>,
+}
+
+impl TxGuard {
+    async fn new(
+        conn: libsql_core::Connection,
+        is_tx: bool,
+        behavior: Option<&str>,
+        exec: Option<OwnedMutexGuard<()>>,
+    ) -> PyResult<Self> {
+        let started = if is_tx && conn.is_autocommit() {
+            begin_transaction(&conn, behavior).await?;
+            true
+        } else {
+            false
+        };
+        Ok(Self {
+            conn,
+            started,
+            done: false,
+            exec,
+        })
+    }
+
+    async fn commit(mut self) -> PyResult<()> {
+        if self.started && !self.done {
+            // A failed COMMIT leaves the transaction open, as in sqlite3.
+            self.done = true;
+            self.conn.execute("COMMIT", ()).await.map_err(to_py_err)?;
+        }
+        Ok(())
+    }
+
+    async fn rollback(mut self) -> PyResult<()> {
+        if self.started && !self.done {
+            let _ = self.conn.execute("ROLLBACK", ()).await;
+            self.done = true;
+        }
+        Ok(())
+    }
+    
+    fn conn(&self) -> &libsql_core::Connection {
+        &self.conn
+    }
+}
+
+impl Drop for TxGuard {
+    fn drop(&mut self) {
+        if self.started && !self.done {
+            let conn = self.conn.clone();
+            let exec = self.exec.take();
+            pyo3_async_runtimes::tokio::get_runtime().spawn(async move {
+                let _ = conn.execute("ROLLBACK", ()).await;
+                drop(exec);
+            });
+        }
+    }
+}
+
+#[derive(Default)]
+struct ConnectionMetrics {
+    statements: AtomicU64,
+    rows_fetched: AtomicU64,
+    frames_synced: AtomicU64,
+    busy_errors: AtomicU64,
+    busy_retries: AtomicU64,
+    errors: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    failovers: AtomicU64,
+}
+
+impl ConnectionMetrics {
+    fn add_statements(&self, n: u64) {
+        self.statements.fetch_add(n, Ordering::Relaxed);
+    }
+
+    fn add_rows(&self, n: u64) {
+        self.rows_fetched.fetch_add(n, Ordering::Relaxed);
+    }
+
+    fn track<T>(&self, res: PyResult<T>) -> PyResult<T> {
+        if let Err(e) = &res {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+            if Python::with_gil(|py| e.is_instance_of::<TimeoutError>(py)) {
+                self.busy_errors.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        res
+    }
+
+    fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let d = PyDict::new(py);
+        d.set_item("statements_executed", self.statements.load(Ordering::Relaxed))?;
+        d.set_item("rows_fetched", self.rows_fetched.load(Ordering::Relaxed))?;
+        d.set_item("frames_synced", self.frames_synced.load(Ordering::Relaxed))?;
+        d.set_item("busy_errors", self.busy_errors.load(Ordering::Relaxed))?;
+        d.set_item("busy_retries", self.busy_retries.load(Ordering::Relaxed))?;
+        d.set_item("errors", self.errors.load(Ordering::Relaxed))?;
+        d.set_item("cache_hits", self.cache_hits.load(Ordering::Relaxed))?;
+        d.set_item("cache_misses", self.cache_misses.load(Ordering::Relaxed))?;
+        d.set_item("failovers", self.failovers.load(Ordering::Relaxed))?;
+        Ok(d)
+    }
+}
+
+struct WalStats {
+    size: u64,
+    checkpoint_seq: Option<u32>,
+}
+
+impl WalStats {
+    /// Each WAL frame is a 24 byte header followed by one page, after the 32 byte file header.
+    fn frames(&self, page_size: i64) -> u64 {
+        match u64::try_from(page_size) {
+            Ok(page_size) if page_size > 0 => self.size.saturating_sub(32) / (page_size + 24),
+            _ => 0,
+        }
+    }
+}
+
+/// Reads the size of a WAL file and the checkpoint sequence number from its header, which
+/// SQLite bumps every time a checkpoint restarts the log.
+fn read_wal_stats(path: &str) -> Option<WalStats> {
+    use std::io::Read;
+    let mut file = std::fs::File::open(path).ok()?;
+    let size = file.metadata().ok()?.len();
+    let mut header = [0u8; 16];
+    let checkpoint_seq = file
+        .read_exact(&mut header)
+        .ok()
+        .map(|_| u32::from_be_bytes([header[12], header[13], header[14], header[15]]));
+    Some(WalStats { size, checkpoint_seq })
+}
+
+#[derive(Default)]
+struct ReplicationState {
+    is_replica: bool,
+    frame_no: Option<u64>,
+    last_sync: Option<f64>,
+    last_error: Option<String>,
+    bootstrap_seconds: Option<f64>,
+}
+
+/// Calls a bulk operation's `progress(done, total)` callback.
+fn report_progress(progress: &PyObject, done: u64, total: Option<usize>) -> PyResult<()> {
+    Python::with_gil(|py| progress.call1(py, (done, total)).map(|_| ()))
+}
+
+impl ReplicationState {
+    fn record_sync(&mut self, res: &libsql_core::Result<libsql_core::replication::Replicated>) {
+        match res {
+            Ok(replicated) => {
+                if let Some(frame_no) = replicated.frame_no() {
+                    self.frame_no = Some(frame_no);
+                }
+                self.last_sync = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .ok()
+                    .map(|d| d.as_secs_f64());
+                self.last_error = None;
+            }
+            Err(e) => self.last_error = Some(e.to_string()),
+        }
+    }
+}
+
+/// Reads an unsigned integer field from the flat JSON object libsql writes to `<replica>-info`.
+fn json_u64_field(json: &str, key: &str) -> Option<u64> {
+    let rest = &json[json.find(&format!("\"{}\"", key))? + key.len() + 2..];
+    let rest = rest.trim_start().strip_prefix(':')?.trim_start();
+    let end = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+    rest[..end].parse().ok()
+}
+
+/// How far an embedded replica has pulled from its primary, as `generation << 32 | frame`.
+/// libsql's replica sync reports no frame number itself, but persists both after every pulled
+/// transaction, so later positions always compare greater.
+fn replica_position(path: &str) -> Option<u64> {
+    let json = std::fs::read_to_string(format!("{}-info", path)).ok()?;
+    let generation = json_u64_field(&json, "generation")?;
+    let frame = json_u64_field(&json, "durable_frame_num")?;
+    Some((generation << 32) | frame)
+}
+
+/// The replica behind a connection opened with `sync_url`, for the replication indexes of
+/// `cursor.replication_index` and `execute(min_replication_index=...)`.
+#[derive(Clone)]
+struct ReplicaSync {
+    db: Arc<libsql_core::Database>,
+    replication: Arc<SyncMutex<ReplicationState>>,
+    metrics: Arc<ConnectionMetrics>,
+    path: String,
+    remote_writes: bool,
+}
+
+impl ReplicaSync {
+    fn position(&self) -> Option<u64> {
+        replica_position(&self.path)
+    }
+
+    /// Syncs unless the replica has already reached `index`, and fails if the primary has not
+    /// reached it either.
+    async fn catch_up(&self, index: u64) -> PyResult<()> {
+        if self.position().is_some_and(|p| p >= index) {
+            return Ok(());
+        }
+        let res = self.db.sync().await;
+        self.replication.lock().record_sync(&res);
+        let replicated = self.metrics.track(res.map_err(to_py_err))?;
+        self.metrics
+            .frames_synced
+            .fetch_add(replicated.frames_synced() as u64, Ordering::Relaxed);
+        match self.position() {
+            Some(p) if p >= index => Ok(()),
+            p => Err(OperationalError::new_err(format!(
+                "replica is at replication index {}, primary has not reached {}",
+                p.unwrap_or(0),
+                index
+            ))),
+        }
+    }
+}
+
+#[pyfunction]
+#[pyo3(
+    signature = (
+        database,
+        timeout=5.0,
+        isolation_level="DEFERRED".to_string(),
+        check_same_thread=false,
+        _uri=false,
+        sync_url=None,
+        sync_interval=None,
+        initial_sync="bootstrap".to_string(),
+        sync_timeout=None,
+        offline=false,
+        auth_token=None,
+        encryption_key=None,
+        create=true,
+        autocommit=LEGACY_TRANSACTION_CONTROL,
+        from_env=false,
+        detect_types=0,
+        datetime_format="iso".to_string(),
+        decimal_format="text".to_string(),
+        uuid_format="text".to_string(),
+        int_overflow="raise".to_string(),
+        nonfinite="keep".to_string(),
+        timezone=None,
+        text_errors="strict".to_string(),
+        strict_parameters=false,
+        journal_mode=None,
+        synchronous=None,
+        foreign_keys=None,
+        cache_size=None,
+        mmap_size=None,
+        wal_autocheckpoint=None,
+        soft_heap_limit=None,
+        hard_heap_limit=None,
+        http_keepalive=None,
+        http_connect_timeout=None,
+        http2=false,
+        fallback_urls=None,
+        failover_after=3,
+        optimize_on_close=false,
+        close_on_exit=false,
+        prefetch=0,
+        max_rows=None,
+        max_result_bytes=None,
+        result_cache_size=0,
+        result_cache_ttl=None,
+        busy_retries=0,
+        busy_retry_delay=0.05,
+        max_queue=None,
+        factory=None,
+        _check_same_thread=None
+    )
+)]
+fn connect<'py>(
+    py: Python<'py>,
+    database: String,
+    timeout: f64,
+    isolation_level: Option<String>,
+    check_same_thread: bool,
+    _uri: bool,
+    sync_url: Option<String>,
+    sync_interval: Option<f64>,
+    initial_sync: String,
+    sync_timeout: Option<f64>,
+    offline: bool,
+    auth_token: Option<String>,
+    encryption_key: Option<String>,
+    create: bool,
+    autocommit: i32,
+    from_env: bool,
+    detect_types: i32,
+    datetime_format: String,
+    decimal_format: String,
+    uuid_format: String,
+    int_overflow: String,
+    nonfinite: String,
+    timezone: Option<String>,
+    text_errors: String,
+    strict_parameters: bool,
+    journal_mode: Option<String>,
+    synchronous: Option<String>,
+    foreign_keys: Option<bool>,
+    cache_size: Option<i64>,
+    mmap_size: Option<i64>,
+    wal_autocheckpoint: Option<i64>,
+    soft_heap_limit: Option<i64>,
+    hard_heap_limit: Option<i64>,
+    http_keepalive: Option<f64>,
+    http_connect_timeout: Option<f64>,
+    http2: bool,
+    fallback_urls: Option<Vec<String>>,
+    failover_after: u32,
+    optimize_on_close: bool,
+    close_on_exit: bool,
+    prefetch: usize,
+    max_rows: Option<u64>,
+    max_result_bytes: Option<u64>,
+    result_cache_size: usize,
+    result_cache_ttl: Option<f64>,
+    busy_retries: u32,
+    busy_retry_delay: f64,
+    max_queue: Option<usize>,
+    factory: Option<PyObject>,
+    _check_same_thread: Option<bool>,
+) -> PyResult<Bound<'py, PyAny>> {
+    check_runtime_pid()?;
+    let check_same_thread = match _check_same_thread {
+        Some(value) => {
+            PyErr::warn(
+                py,
+                &py.get_type::<pyo3::exceptions::PyDeprecationWarning>(),
+                c"_check_same_thread is deprecated; use check_same_thread",
+                1,
+            )?;
+            check_same_thread || value
+        }
+        None => check_same_thread,
+    };
+    let busy_retry = BusyRetry {
+        retries: busy_retries,
+        delay: seconds_arg("busy_retry_delay", busy_retry_delay)?,
+    };
+    let isolation_level = isolation_keyword(isolation_level)?;
+    let result_cache_ttl = result_cache_ttl.map(|t| seconds_arg("result_cache_ttl", t)).transpose()?;
+    let result_cache = (result_cache_size > 0)
+        .then(|| Arc::new(SyncMutex::new(ResultCache::new(result_cache_size, result_cache_ttl))));
+    let types = TypeConfig {
+        detect_types,
+        datetime_format: DateTimeFormat::parse(&datetime_format)?,
+        decimal_format: DecimalFormat::parse(&decimal_format)?,
+        uuid_format: UuidFormat::parse(&uuid_format)?,
+        int_overflow: IntOverflow::parse(&int_overflow)?,
+        nonfinite: NonFinite::parse(&nonfinite)?,
+        timezone: TimeZone::parse(py, timezone.as_deref())?,
+        text_errors: TextErrors::parse(&text_errors)?,
+        strict_parameters,
+    };
+    let pragmas = Pragmas {
+        journal_mode: journal_mode
+            .map(|m| {
+                sql_keyword(
+                    "journal_mode",
+                    &m,
+                    &["DELETE", "TRUNCATE", "PERSIST", "MEMORY", "WAL", "OFF"],
+                )
+            })
+            .transpose()?,
+        synchronous: synchronous
+            .map(|s| sql_keyword("synchronous", &s, &["OFF", "NORMAL", "FULL", "EXTRA"]))
+            .transpose()?,
+        foreign_keys,
+        cache_size,
+        mmap_size,
+        wal_autocheckpoint,
+        soft_heap_limit,
+        hard_heap_limit,
+    };
+    let http = HttpConfig {
+        keepalive: http_keepalive.map(|t| seconds_arg("http_keepalive", t)).transpose()?,
+        connect_timeout: http_connect_timeout.map(|t| seconds_arg("http_connect_timeout", t)).transpose()?,
+        http2,
+    };
+    let sync_interval = sync_interval.map(|t| seconds_arg("sync_interval", t)).transpose()?;
+    let sync_timeout = sync_timeout
+        .map(|t| seconds_arg("sync_timeout", t).map(|d| (t, d)))
+        .transpose()?;
+    let (sync_url, auth_token) = if from_env {
+        (
+            sync_url.or_else(|| std::env::var("LIBSQL_URL").ok()),
+            auth_token.or_else(|| std::env::var("LIBSQL_AUTH_TOKEN").ok()),
+        )
+    } else {
+        (sync_url, auth_token)
+    };
+    let database = http_for_websocket(py, database)?;
+    let sync_url = sync_url.map(|url| http_for_websocket(py, url)).transpose()?;
+    let (database, url_auth_token) = parse_database_url(&database)?;
+    let (sync_url, sync_url_auth_token) = match sync_url {
+        Some(url) => {
+            let (url, token) = parse_database_url(&url)?;
+            (Some(url), token)
+        }
+        None => (None, None),
+    };
+    let auth_token = auth_token
+        .or(url_auth_token)
+        .or(sync_url_auth_token)
+        .unwrap_or_default();
+    let remote = is_remote_path(&database);
+    let is_replica = sync_url.is_some() && !remote;
+    if remote && (soft_heap_limit.is_some() || hard_heap_limit.is_some()) {
+        return Err(PyValueError::new_err("heap limits only apply to local databases"));
+    }
+    if !create && (remote || is_replica) {
+        return Err(PyValueError::new_err("create=False only applies to local databases"));
+    }
+    let endpoints = match fallback_urls {
+        Some(urls) if !urls.is_empty() => {
+            if !remote {
+                return Err(PyValueError::new_err("fallback_urls only apply to remote databases"));
+            }
+            let mut endpoints = vec![(database.clone(), auth_token.clone())];
+            for url in urls {
+                let (url, token) = parse_database_url(&http_for_websocket(py, url)?)?;
+                if !is_remote_path(&url) {
+                    return Err(PyValueError::new_err(format!("fallback URL is not remote: {}", url)));
+                }
+                endpoints.push((url, token.unwrap_or_else(|| auth_token.clone())));
+            }
+            Some(endpoints)
+        }
+        _ => None,
+    };
+    let initial_sync = InitialSync::parse(&initial_sync)?;
+    if is_replica
+        && initial_sync == InitialSync::Skip
+        && !(std::path::Path::new(&database).exists()
+            && std::path::Path::new(&format!("{}-info", database)).exists())
+    {
+        return Err(OperationalError::new_err(format!(
+            "replica {} has not been bootstrapped yet, initial_sync=\"skip\" needs an existing replica",
+            database
+        )));
+    }
+    let started = Instant::now();
+    let metrics = Arc::new(ConnectionMetrics::default());
+    let replica_path = is_replica.then(|| database.clone());
+    let owner = Owner::current(check_same_thread);
+    future_into_py(py, async move {
+        let encryption_config = match encryption_key {
+            Some(key) => {
+                let cipher = libsql_core::Cipher::default();
+                Some(libsql_core::EncryptionConfig::new(cipher, key.into()))
+            }
+            None => None,
+        };
+        let open = async {
+            let mut endpoint = 0;
+            let mut primary = None;
+            let db = if remote {
+                match &endpoints {
+                    Some(endpoints) => {
+                        let (db, _, idx) = open_reachable(endpoints, 0, http).await?;
+                        endpoint = idx;
+                        db
+                    }
+                    None => open_remote(&database, &auth_token, http).await?,
+                }
+            } else {
+                match sync_url {
+                    Some(sync_url) => {
+                        if !offline {
+                            let remote_db = open_remote(&sync_url, &auth_token, http).await?;
+                            primary = Some(remote_db.connect().map_err(to_py_err)?);
+                        }
+                        let mut builder = libsql_core::Builder::new_synced_database(
+                            database,
+                            sync_url,
+                            auth_token.clone(),
+                        );
+                        if encryption_config.is_some() {
+                            return Err(PyValueError::new_err("no encryption sync"));
+                        }
+                        if let Some(sync_interval) = sync_interval {
+                            builder = builder.sync_interval(sync_interval);
+                        }
+                        builder = builder.remote_writes(!offline);
+                        if http != HttpConfig::default() {
+                            builder = builder.connector(http.connector()?);
+                        }
+                        builder.build().await.map_err(to_py_err)?
+                    }
+                    None => {
+                        let mut builder = libsql_core::Builder::new_local(database);
+                        if !create {
+                            builder = builder.flags(libsql_core::OpenFlags::SQLITE_OPEN_READ_WRITE);
+                        }
+                        if let Some(config) = encryption_config {
+                            builder = builder.encryption_config(config);
+                        }
+                        builder.build().await.map_err(to_py_err)?
+                    }
+                }
+            };
+            let db = Arc::new(db);
+            let conn = if is_replica {
+                // Connecting bootstraps a new replica file and blocks until the download is done.
+                let replica = db.clone();
+                tokio::task::spawn_blocking(move || replica.connect())
+                    .await
+                    .map_err(|e| DatabaseError::new_err(e.to_string()))?
+                    .map_err(to_py_err)?
+            } else {
+                db.connect().map_err(to_py_err)?
+            };
+            let synced = (is_replica && initial_sync == InitialSync::Full).then_some(db.sync().await);
+            Ok::<_, PyErr>((db, conn, synced, endpoint, primary))
+        };
+        let (db, conn, synced, endpoint, primary) = match sync_timeout {
+            Some((secs, limit)) if is_replica => tokio::time::timeout(limit, open)
+                .await
+                .map_err(|_| {
+                    TimeoutError::new_err(format!("initial sync did not finish within {} seconds", secs))
+                })??,
+            _ => open.await?,
+        };
+        let mut replication = ReplicationState {
+            is_replica,
+            bootstrap_seconds: is_replica.then(|| started.elapsed().as_secs_f64()),
+            ..Default::default()
+        };
+        if let Some(res) = synced {
+            replication.record_sync(&res);
+            let replicated = metrics.track(res.map_err(to_py_err))?;
+            metrics
+                .frames_synced
+                .fetch_add(replicated.frames_synced() as u64, Ordering::Relaxed);
+        }
+        conn.busy_timeout(Duration::from_secs_f64(timeout)).map_err(to_py_err)?;
+        if let Some(sql) = pragmas.to_sql() {
+            conn.execute_batch(&sql).await.map_err(to_py_err)?;
+        }
+        let autocommit_val = if autocommit == LEGACY_TRANSACTION_CONTROL {
+            if isolation_level.is_none() { 1 } else { 0 }
+        } else {
+            autocommit
+        };
+        let update_hooks = Arc::new(SyncMutex::new(UpdateHooks::default()));
+        if result_cache.is_some() {
+            install_update_hooks(&conn, &update_hooks)?;
+            update_hooks.lock().cache = result_cache.clone();
+        }
+        let conn = Arc::new(SyncMutex::new(Some(conn)));
+        let exec_lock = Arc::new(AsyncMutex::new(()));
+        let failover = endpoints.map(|endpoints| {
+            Arc::new(Failover {
+                endpoints,
+                http,
+                threshold: failover_after.max(1),
+                conn: conn.clone(),
+                exec_lock: exec_lock.clone(),
+                state: SyncMutex::new(FailoverState {
+                    current: endpoint,
+                    ..FailoverState::default()
+                }),
+            })
+        });
+        let replication = Arc::new(SyncMutex::new(replication));
+        let replica = replica_path.map(|path| ReplicaSync {
+            db: db.clone(),
+            replication: replication.clone(),
+            metrics: metrics.clone(),
+            path,
+            remote_writes: !offline,
+        });
+        let connection = Connection {
+            db,
+            conn,
+            isolation_level,
+            autocommit: autocommit_val,
+            remote,
+            types,
+            optimize_on_close,
+            close_on_exit,
+            prefetch,
+            limits: ResultLimits {
+                max_rows,
+                max_bytes: max_result_bytes,
+            },
+            metrics,
+            replication,
+            open_cursors: Arc::new(SyncMutex::new(Vec::new())),
+            update_hooks,
+            result_cache,
+            busy_retry,
+            queries: Arc::new(SyncMutex::new(QueryRegistry {
+                max_queue,
+                ..QueryRegistry::default()
+            })),
+            exec_lock,
+            owner,
+            failover,
+            primary,
+            replica,
+        };
+        Python::with_gil(|py| {
+            let mut connection = Py::new(py, connection)?.into_any();
+            if let Some(factory) = factory {
+                connection = factory.call1(py, (connection,))?;
+                if !connection.bind(py).is_instance_of::<Connection>() {
+                    return Err(PyTypeError::new_err("factory must return an aiolibsql.Connection"));
+                }
+            }
+            track_open_handle(connection.bind(py))?;
+            Ok(connection)
+        })
+    })
+}
+
+#[pyclass(subclass, weakref)]
+#[derive(Clone)]
+pub struct Connection {
+    db: Arc<libsql_core::Database>,
+    conn: Arc<SyncMutex<Option<libsql_core::Connection>>>,
+    isolation_level: Option<String>,
+    #[pyo3(get, set)]
+    autocommit: i32,
+    remote: bool,
+    types: TypeConfig,
+    optimize_on_close: bool,
+    #[pyo3(get)]
+    close_on_exit: bool,
+    prefetch: usize,
+    limits: ResultLimits,
+    metrics: Arc<ConnectionMetrics>,
+    replication: Arc<SyncMutex<ReplicationState>>,
+    open_cursors: Arc<SyncMutex<Vec<CursorEntry>>>,
+    update_hooks: Arc<SyncMutex<UpdateHooks>>,
+    result_cache: Option<ResultCacheRef>,
+    busy_retry: BusyRetry,
+    queries: QueryRegistryRef,
+    exec_lock: Arc<AsyncMutex<()>>,
+    owner: Owner,
+    failover: Option<Arc<Failover>>,
+    /// Remote connection to the primary of an embedded replica, for `execute(primary=True)`.
+    primary: Option<libsql_core::Connection>,
+    replica: Option<ReplicaSync>,
+}
+
+impl Connection {
+    /// Detaches open cursors and change streams, then returns the work that rolls back and
+    /// drops the underlying connection.
+    fn shutdown(&self) -> impl Future<Output = PyResult<()>> + Send + 'static {
+        let conn_arc = self.conn.clone();
+        let cursors: Vec<RowsSlot> = self
+            .open_cursors
+            .lock()
+            .drain(..)
+            .filter_map(|cursor| cursor.rows.upgrade())
+            .collect();
+        self.update_hooks.lock().subscribers.clear();
+        let optimize = self.optimize_on_close;
+        async move {
+            for rows in cursors {
+                rows.lock().await.take();
+            }
+            let conn = conn_arc.lock().take();
+            if let Some(conn) = conn {
+                if !conn.is_autocommit() {
+                    conn.execute("ROLLBACK", ()).await.map_err(to_py_err)?;
+                }
+                if optimize {
+                    conn.execute_batch("PRAGMA optimize").await.map_err(to_py_err)?;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// `shutdown` once the running and queued statements are done. Past `deadline` the queued
+    /// statements are cancelled and the running one is interrupted instead of waited for.
+    fn graceful_shutdown(&self, deadline: Option<Instant>) -> impl Future<Output = PyResult<()>> + Send + 'static {
+        let this = self.clone();
+        async move {
+            let lock = this.exec_lock.clone().lock_owned();
+            let exec = match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline.into(), lock).await.ok(),
+                None => Some(lock.await),
+            };
+            let exec = match exec {
+                Some(exec) => exec,
+                None => {
+                    for q in this.queries.lock().queries.iter_mut() {
+                        q.cancelled = true;
+                    }
+                    if let Some(conn) = this.conn.lock().as_ref() {
+                        conn.interrupt().map_err(to_py_err)?;
+                    }
+                    this.exec_lock.clone().lock_owned().await
+                }
+            };
+            let res = this.shutdown().await;
+            drop(exec);
+            res
+        }
+    }
+
+    /// Runs `PRAGMA integrity_check` / `quick_check`, which report `ok` or one row per problem.
+    fn run_check<'py>(&self, py: Python<'py>, sql: String) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
+        let conn_arc = self.conn.clone();
+        let exec_lock = self.exec_lock.clone();
+        future_into_py(py, async move {
+            let _exec = exec_lock.lock().await;
+            let conn = {
+                let guard = conn_arc.lock();
+                guard.as_ref().cloned()
+            }
+            .ok_or_else(|| PyValueError::new_err("closed"))?;
+            let mut rows = conn.query(&sql, ()).await.map_err(to_py_err)?;
+            let mut problems = Vec::new();
+            while let Some(row) = rows.next().await.map_err(to_py_err)? {
+                let line = row.get::<String>(0).map_err(to_py_err)?;
+                if line != "ok" {
+                    problems.push(line);
+                }
+            }
+            Python::with_gil(|py| {
+                let d = PyDict::new(py);
+                d.set_item("ok", problems.is_empty())?;
+                d.set_item("problems", problems)?;
+                Ok(d.unbind())
+            })
+        })
+    }
+
+    fn new_cursor(&self, connection: Py<Connection>) -> Cursor {
+        let rows: RowsSlot = Arc::new(AsyncMutex::new(None));
+        let last_sql: LastSql = Arc::new(SyncMutex::new(None));
+        {
+            let mut open_cursors = self.open_cursors.lock();
+            open_cursors.retain(|c| c.rows.strong_count() > 0);
+            open_cursors.push(CursorEntry {
+                rows: Arc::downgrade(&rows),
+                last_sql: Arc::downgrade(&last_sql),
+            });
+        }
+        Cursor {
+            connection: Arc::new(connection),
+            arraysize: 1,
+            conn: self.conn.clone(),
+            rows,
+            columns: Arc::new(SyncMutex::new(None)),
+            column_meta: Arc::new(SyncMutex::new(Vec::new())),
+            parameters: Arc::new(SyncMutex::new(Vec::new())),
+            column_kinds: Arc::new(SyncMutex::new(Vec::new())),
+            rowcount: Arc::new(AtomicI64::new(0)),
+            last_insert_rowid: Arc::new(SyncMutex::new(None)),
+            last_sql,
+            cache: self.result_cache.clone(),
+            busy_retry: self.busy_retry,
+            queries: self.queries.clone(),
+            query_id: None,
+            isolation_level: self.isolation_level.clone(),
+            autocommit: self.autocommit,
+            remote: self.remote,
+            types: self.types.clone(),
+            prefetch: self.prefetch,
+            max_rows: self.limits.max_rows,
+            max_result_bytes: self.limits.max_bytes,
+            done: Arc::new(AtomicBool::new(false)),
+            metrics: self.metrics.clone(),
+            exec_lock: self.exec_lock.clone(),
+            owner: self.owner,
+            failover: self.failover.clone(),
+            primary: self.primary.clone(),
+            replica: self.replica.clone(),
+            write_index: Arc::new(SyncMutex::new(None)),
+        }
+    }
+}
+
+#[pymethods]
+impl Connection {
+    /// Another handle on an open connection; subclasses passed as `connect(factory=...)`
+    /// are constructed this way.
+    #[new]
+    fn new(connection: PyRef<'_, Connection>) -> Self {
+        connection.clone()
+    }
+
+    fn close<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
+        let conn_arc = self.conn.clone();
+        let open_tx = conn_arc.lock().as_ref().is_some_and(|c| !c.is_autocommit());
+        if open_tx {
+            warn(py, "open transaction rolled back by close()")?;
+        }
+        future_into_py(py, self.shutdown())
+    }
+
+    fn optimize<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
+        let conn_arc = self.conn.clone();
+        let exec_lock = self.exec_lock.clone();
+        future_into_py(py, async move {
+            let _exec = exec_lock.lock().await;
+            let conn = {
+                let guard = conn_arc.lock();
+                guard.as_ref().cloned()
+            }
+            .ok_or_else(|| PyValueError::new_err("closed"))?;
+            conn.execute_batch("PRAGMA optimize").await.map_err(to_py_err)?;
+            Ok(())
+        })
+    }
+
+    /// `PRAGMA user_version` of the main database; the getter returns an awaitable.
+    #[getter]
+    fn user_version<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
+        let conn_arc = self.conn.clone();
+        let exec_lock = self.exec_lock.clone();
+        future_into_py(py, async move {
+            let _exec = exec_lock.lock().await;
+            let conn = {
+                let guard = conn_arc.lock();
+                guard.as_ref().cloned()
+            }
+            .ok_or_else(|| PyValueError::new_err("closed"))?;
+            let mut rows = conn.query("PRAGMA user_version", ()).await.map_err(to_py_err)?;
+            match rows.next().await.map_err(to_py_err)? {
+                Some(row) => row.get::<i64>(0).map_err(to_py_err),
+                None => Ok(0),
+            }
+        })
+    }
+
+    fn set_user_version<'py>(&self, py: Python<'py>, version: i32) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
+        let conn_arc = self.conn.clone();
+        let exec_lock = self.exec_lock.clone();
+        future_into_py(py, async move {
+            let _exec = exec_lock.lock().await;
+            let conn = {
+                let guard = conn_arc.lock();
+                guard.as_ref().cloned()
+            }
+            .ok_or_else(|| PyValueError::new_err("closed"))?;
+            conn.execute_batch(&format!("PRAGMA user_version = {}", version))
+                .await
+                .map_err(to_py_err)?;
+            Ok(())
+        })
+    }
+
+    /// Sets `PRAGMA wal_autocheckpoint`: checkpoint once the WAL reaches `pages` pages, or
+    /// never when `pages` is zero or negative.
+    fn set_wal_autocheckpoint<'py>(&self, py: Python<'py>, pages: i64) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
+        let conn_arc = self.conn.clone();
+        let exec_lock = self.exec_lock.clone();
+        future_into_py(py, async move {
+            let _exec = exec_lock.lock().await;
+            let conn = {
+                let guard = conn_arc.lock();
+                guard.as_ref().cloned()
+            }
+            .ok_or_else(|| PyValueError::new_err("closed"))?;
+            conn.execute_batch(&format!("PRAGMA wal_autocheckpoint = {}", pages))
+                .await
+                .map_err(to_py_err)?;
+            Ok(())
+        })
+    }
+
+    #[pyo3(signature = (max_errors=100))]
+    fn integrity_check<'py>(&self, py: Python<'py>, max_errors: u32) -> PyResult<Bound<'py, PyAny>> {
+        self.run_check(py, format!("PRAGMA integrity_check({})", max_errors.max(1)))
+    }
+
+    #[pyo3(signature = (max_errors=100))]
+    fn quick_check<'py>(&self, py: Python<'py>, max_errors: u32) -> PyResult<Bound<'py, PyAny>> {
+        self.run_check(py, format!("PRAGMA quick_check({})", max_errors.max(1)))
+    }
+
+    #[pyo3(signature = (*, into=None))]
+    fn vacuum<'py>(
+        &self,
+        py: Python<'py>,
+        into: Option<std::path::PathBuf>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
+        let conn_arc = self.conn.clone();
+        let exec_lock = self.exec_lock.clone();
+        let into = into.map(|p| p.to_string_lossy().into_owned());
+        future_into_py(py, async move {
+            let _exec = exec_lock.lock().await;
+            let conn = {
+                let guard = conn_arc.lock();
+                guard.as_ref().cloned()
+            }
+            .ok_or_else(|| PyValueError::new_err("closed"))?;
+            if !conn.is_autocommit() {
+                return Err(ProgrammingError::new_err(
+                    "vacuum() cannot run inside an open transaction",
+                ));
+            }
+            match into {
+                Some(path) => conn.execute("VACUUM INTO ?", [path]).await,
+                None => conn.execute("VACUUM", ()).await,
+            }
+            .map_err(to_py_err)?;
+            Ok(())
+        })
+    }
+
+    /// Copies this connection's main database into `target`, replacing its contents. The
+    /// source is read inside one transaction and the target written in another, with both
+    /// connections held until the copy is committed.
+    fn backup<'py>(&self, py: Python<'py>, target: PyRef<'py, Connection>) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
+        if Arc::ptr_eq(&self.conn, &target.conn) {
+            return Err(ProgrammingError::new_err("cannot back up a connection into itself"));
+        }
+        let (src_arc, src_lock) = (self.conn.clone(), self.exec_lock.clone());
+        let (dst_arc, dst_lock) = (target.conn.clone(), target.exec_lock.clone());
+        let cache = target.result_cache.clone();
+        future_into_py(py, async move {
+            let src_exec = src_lock.lock_owned().await;
+            let dst_exec = dst_lock.lock_owned().await;
+            let src = {
+                let guard = src_arc.lock();
+                guard.as_ref().cloned()
+            }
+            .ok_or_else(|| PyValueError::new_err("closed"))?;
+            let dst = {
+                let guard = dst_arc.lock();
+                guard.as_ref().cloned()
+            }
+            .ok_or_else(|| PyValueError::new_err("closed"))?;
+            let reader = TxGuard::new(src, true, None, Some(src_exec)).await?;
+            let writer = TxGuard::new(dst, true, None, Some(dst_exec)).await?;
+            let res = copy_database(reader.conn(), writer.conn()).await;
+            reader.rollback().await?;
+            let total = match res {
+                Ok(total) => total,
+                Err(e) => {
+                    writer.rollback().await?;
+                    return Err(e);
+                }
+            };
+            writer.commit().await?;
+            if let Some(cache) = &cache {
+                cache.lock().entries.clear();
+            }
+            Ok(total)
+        })
+    }
+
+    #[pyo3(signature = (callback))]
+    fn set_update_hook(&self, py: Python<'_>, callback: Option<PyObject>) -> PyResult<()> {
+        self.owner.check()?;
+        if let Some(cb) = &callback
+            && !cb.bind(py).is_callable()
+        {
+            return Err(PyTypeError::new_err("update hook must be callable or None"));
+        }
+        let guard = self.conn.lock();
+        let conn = guard.as_ref().ok_or_else(|| PyValueError::new_err("closed"))?;
+        install_update_hooks(conn, &self.update_hooks)?;
+        self.update_hooks.lock().callback = callback;
+        Ok(())
+    }
+
+    #[pyo3(signature = (tables=None))]
+    fn changes_stream(&self, tables: Option<Vec<String>>) -> PyResult<ChangeStream> {
+        self.owner.check()?;
+        let guard = self.conn.lock();
+        let conn = guard.as_ref().ok_or_else(|| PyValueError::new_err("closed"))?;
+        install_update_hooks(conn, &self.update_hooks)?;
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let mut hooks = self.update_hooks.lock();
+        hooks.next_id += 1;
+        let id = hooks.next_id;
+        hooks.subscribers.push(ChangeSubscriber { id, tables, sender });
+        Ok(ChangeStream {
+            id,
+            conn: self.conn.clone(),
+            exec_lock: self.exec_lock.clone(),
+            hooks: self.update_hooks.clone(),
+            receiver: Arc::new(AsyncMutex::new(receiver)),
+            text_errors: self.types.text_errors,
+            owner: self.owner,
+        })
+    }
+
+    fn ping<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
+        let conn_arc = self.conn.clone();
+        let exec_lock = self.exec_lock.clone();
+        future_into_py(py, async move {
+            let _exec = exec_lock.lock().await;
+            let conn = {
+                let guard = conn_arc.lock();
+                guard.as_ref().cloned()
+            }
+            .ok_or_else(|| PyValueError::new_err("closed"))?;
+            let start = Instant::now();
+            let mut rows = conn.query("SELECT 1", ()).await.map_err(to_py_err)?;
+            rows.next().await.map_err(to_py_err)?;
+            Ok(start.elapsed().as_secs_f64())
+        })
+    }
+
+    fn reset<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
+        let conn_arc = self.conn.clone();
+        let exec_lock = self.exec_lock.clone();
+        let cursors: Vec<RowsSlot> = self
+            .open_cursors
+            .lock()
+            .drain(..)
+            .filter_map(|cursor| cursor.rows.upgrade())
+            .collect();
+        future_into_py(py, async move {
+            let _exec = exec_lock.lock().await;
+            for rows in cursors {
+                rows.lock().await.take();
+            }
+            let conn = {
+                let guard = conn_arc.lock();
+                guard.as_ref().cloned()
+            }
+            .ok_or_else(|| PyValueError::new_err("closed"))?;
+            if !conn.is_autocommit() {
+                conn.execute("ROLLBACK", ()).await.map_err(to_py_err)?;
+            }
+            conn.reset().await;
+            Ok(())
+        })
+    }
+
+    #[pyo3(signature = (factory=None))]
+    fn cursor(slf: &Bound<'_, Self>, factory: Option<Bound<'_, PyAny>>) -> PyResult<PyObject> {
+        slf.borrow().owner.check()?;
+        let Some(factory) = factory else {
+            let cursor = slf.borrow().new_cursor(slf.clone().unbind());
+            return Ok(cursor.into_pyobject(slf.py())?.into_any().unbind());
+        };
+        let cursor = factory.call1((slf,))?;
+        if !cursor.is_instance_of::<Cursor>() {
+            return Err(PyTypeError::new_err("factory must return an aiolibsql.Cursor"));
+        }
+        Ok(cursor.unbind())
+    }
+
+    /// One dict per cursor of this connection that is still alive: `sql` is its last
+    /// statement and `active` whether it still holds a result set with unread rows.
+    fn open_cursors<'py>(&self, py: Python<'py>) -> PyResult<Vec<Bound<'py, PyDict>>> {
+        let mut open_cursors = self.open_cursors.lock();
+        open_cursors.retain(|c| c.rows.strong_count() > 0);
+        let mut out = Vec::with_capacity(open_cursors.len());
+        for cursor in open_cursors.iter() {
+            let (Some(rows), Some(last_sql)) = (cursor.rows.upgrade(), cursor.last_sql.upgrade()) else {
+                continue;
+            };
+            // A locked result set belongs to a statement that is running or being fetched.
+            let active = rows.try_lock().map_or(true, |rs| rs.as_ref().is_some_and(|rs| !rs.is_exhausted()));
+            let d = PyDict::new(py);
+            d.set_item("sql", last_sql.lock().as_ref().map(|(sql, _)| sql.clone()))?;
+            d.set_item("active", active)?;
+            out.push(d);
+        }
+        Ok(out)
+    }
+
+    /// One dict per statement submitted through a cursor of this connection that has not
+    /// returned yet, oldest first: `id` (the cursor's `query_id`), `sql`, `running` (false while
+    /// it waits for the connection) and `seconds` since it was submitted.
+    fn queries<'py>(&self, py: Python<'py>) -> PyResult<Vec<Bound<'py, PyDict>>> {
+        let registry = self.queries.lock();
+        let mut out = Vec::with_capacity(registry.queries.len());
+        for q in &registry.queries {
+            let d = PyDict::new(py);
+            d.set_item("id", q.id)?;
+            d.set_item("sql", &q.sql)?;
+            d.set_item("running", q.running)?;
+            d.set_item("seconds", q.submitted.elapsed().as_secs_f64())?;
+            out.push(d);
+        }
+        Ok(out)
+    }
+
+    #[getter]
+    fn queue_length(&self) -> usize {
+        self.queries.lock().queued()
+    }
+
+    /// Cancels the statement with the given `query_id`: a queued statement fails as soon as
+    /// it gets the connection, a running one is interrupted. Returns `False` if it already
+    /// returned.
+    fn cancel(&self, query_id: u64) -> PyResult<bool> {
+        let mut registry = self.queries.lock();
+        let Some(q) = registry.queries.iter_mut().find(|q| q.id == query_id) else {
+            return Ok(false);
+        };
+        q.cancelled = true;
+        // Only one statement holds the connection at a time, so interrupting it while the
+        // registry still shows this one as running can't hit another statement's work.
+        if q.running
+            && let Some(conn) = self.conn.lock().as_ref()
+        {
+            conn.interrupt().map_err(to_py_err)?;
+        }
+        Ok(true)
+    }
+
+    fn sync<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
+        let db = self.db.clone();
+        let metrics = self.metrics.clone();
+        let replication = self.replication.clone();
+        future_into_py(py, async move {
+            let res = db.sync().await;
+            replication.lock().record_sync(&res);
+            let replicated = metrics.track(res.map_err(to_py_err))?;
+            metrics
+                .frames_synced
+                .fetch_add(replicated.frames_synced() as u64, Ordering::Relaxed);
+            Ok(())
+        })
+    }
+
+    fn metrics<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        self.metrics.to_dict(py)
+    }
+
+    fn db_stats<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
+        let conn_arc = self.conn.clone();
+        let exec_lock = self.exec_lock.clone();
+        let remote = self.remote;
+        future_into_py(py, async move {
+            let _exec = exec_lock.lock().await;
+            let conn = {
+                let guard = conn_arc.lock();
+                guard.as_ref().cloned()
+            }
+            .ok_or_else(|| PyValueError::new_err("closed"))?;
+            let mut values = Vec::new();
+            for pragma in ["page_count", "page_size", "freelist_count", "journal_mode"] {
+                let mut rows = conn.query(&format!("PRAGMA {}", pragma), ()).await.map_err(to_py_err)?;
+                let value = match rows.next().await.map_err(to_py_err)? {
+                    Some(row) => row_value(&row, 0, TextErrors::Strict)?,
+                    None => libsql_core::Value::Null,
+                };
+                values.push(value);
+            }
+            let file = if remote {
+                None
+            } else {
+                let mut rows = conn.query("PRAGMA database_list", ()).await.map_err(to_py_err)?;
+                let mut file = None;
+                while let Some(row) = rows.next().await.map_err(to_py_err)? {
+                    if row.get::<String>(1).map_err(to_py_err)? == "main" {
+                        file = row.get::<String>(2).ok().filter(|f| !f.is_empty());
+                    }
+                }
+                file
+            };
+            let wal = file.as_ref().and_then(|f| read_wal_stats(&format!("{}-wal", f)));
+            let page_size = match values[1] {
+                libsql_core::Value::Integer(n) => n,
+                _ => 0,
+            };
+            Python::with_gil(|py| {
+                let d = PyDict::new(py);
+                for (key, value) in ["page_count", "page_size", "freelist_count", "journal_mode"]
+                    .into_iter()
+                    .zip(values)
+                {
+                    d.set_item(key, convert_value(py, value)?)?;
+                }
+                let (wal_size, wal_frames, checkpoint_seq) = match wal {
+                    Some(wal) => (
+                        Some(wal.size),
+                        Some(wal.frames(page_size)),
+                        wal.checkpoint_seq,
+                    ),
+                    None => (None, None, None),
+                };
+                d.set_item("file", file)?;
+                d.set_item("wal_size", wal_size)?;
+                d.set_item("wal_frames", wal_frames)?;
+                d.set_item("checkpoint_seq", checkpoint_seq)?;
+                Ok(d.unbind())
+            })
+        })
+    }
+
+    fn replication_info<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
+        let db = self.db.clone();
+        let replication = self.replication.clone();
+        let position = self.replica.as_ref().and_then(ReplicaSync::position);
+        future_into_py(py, async move {
+            let (is_replica, tracked_frame_no, last_sync, last_error, bootstrap_seconds) = {
+                let state = replication.lock();
+                (
+                    state.is_replica,
+                    state.frame_no,
+                    state.last_sync,
+                    state.last_error.clone(),
+                    state.bootstrap_seconds,
+                )
+            };
+            let replication_index = match db.replication_index().await {
+                Ok(Some(index)) => Some(index),
+                _ => tracked_frame_no.or(position),
+            };
+            let max_write_index = db.max_write_replication_index();
+            Python::with_gil(|py| {
+                let d = PyDict::new(py);
+                d.set_item("is_replica", is_replica)?;
+                d.set_item("replication_index", replication_index)?;
+                d.set_item("max_write_replication_index", max_write_index)?;
+                d.set_item("last_sync", last_sync)?;
+                d.set_item("last_sync_error", last_error.clone())?;
+                d.set_item("connected", is_replica && last_sync.is_some() && last_error.is_none())?;
+                d.set_item("pending_frames", py.None())?;
+                d.set_item("bootstrap_seconds", bootstrap_seconds)?;
+                Ok(d.unbind())
+            })
+        })
+    }
+
+    fn commit<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
+        let conn_arc = self.conn.clone();
+        let exec_lock = self.exec_lock.clone();
+        let remote = self.remote;
+        future_into_py(py, async move {
+            let _exec = exec_lock.lock().await;
+            let conn_opt = {
+                let guard = conn_arc.lock();
+                guard.as_ref().cloned()
+            };
+            if let Some(conn) = conn_opt {
+                if !conn.is_autocommit() {
+                    retry_lost_stream(&conn, remote, || conn.execute("COMMIT", ()))
+                        .await
+                        .map_err(to_py_err)?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn rollback<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
+        let conn_arc = self.conn.clone();
+        let exec_lock = self.exec_lock.clone();
+        let remote = self.remote;
+        future_into_py(py, async move {
+            let _exec = exec_lock.lock().await;
+            let conn_opt = {
+                let guard = conn_arc.lock();
+                guard.as_ref().cloned()
+            };
+            if let Some(conn) = conn_opt {
+                if !conn.is_autocommit() {
+                    retry_lost_stream(&conn, remote, || conn.execute("ROLLBACK", ()))
+                        .await
+                        .map_err(to_py_err)?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    #[pyo3(signature = (sql, parameters=None, *, buffered=false, primary=false, min_replication_index=None))]
+    fn execute<'py>(
+        slf: &Bound<'py, Self>,
+        py: Python<'py>,
+        sql: String,
+        parameters: Option<ListOrTuple>,
+        buffered: bool,
+        primary: bool,
+        min_replication_index: Option<u64>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let cursor = slf.borrow().new_cursor(slf.clone().unbind());
+        let py_cursor = cursor.into_pyobject(py)?.unbind();
+        Cursor::execute(py_cursor, py, sql, parameters, buffered, primary, min_replication_index)
+    }
+
+    fn prepare<'py>(&self, py: Python<'py>, sql: String) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
+        check_single_statement(&sql)?;
+        let conn_arc = self.conn.clone();
+        let exec_lock = self.exec_lock.clone();
+        let metrics = self.metrics.clone();
+        let (types, limits, remote) = (self.types.clone(), self.limits, self.remote);
+        let (cache, busy_retry, owner) = (self.result_cache.clone(), self.busy_retry, self.owner);
+        future_into_py(py, async move {
+            let result: PyResult<Statement> = async {
+                let _exec = exec_lock.lock().await;
+                let conn = {
+                    let guard = conn_arc.lock();
+                    guard.as_ref().cloned()
+                }
+                .ok_or_else(|| PyValueError::new_err("closed"))?;
+                let stmt = retry_lost_stream(&conn, remote, || conn.prepare(&sql))
+                    .await
+                    .map_err(|e| sql_err(e, &sql))?;
+                Ok(Statement {
+                    parameter_names: statement_parameters(&stmt),
+                    meta: ColumnMeta::for_statement(&stmt),
+                    columns: stmt.columns().iter().map(|c| description_name(c.name(), &types)).collect(),
+                    kinds: ColumnKind::for_statement(&stmt, &types),
+                    stmt: Arc::new(AsyncMutex::new(Some(stmt))),
+                    conn: conn_arc.clone(),
+                    exec_lock: exec_lock.clone(),
+                    sql,
+                    types,
+                    limits,
+                    metrics: metrics.clone(),
+                    remote,
+                    cache,
+                    busy_retry,
+                    owner,
+                })
+            }
+            .await;
+            metrics.track(result)
+        })
+    }
+
+    /// Runs a read-only query and returns its rows, answering repeats of the same SQL and
+    /// parameters from the result cache while none of the tables it reads have been written.
+    #[pyo3(signature = (sql, parameters=None))]
+    fn query_cached<'py>(
+        &self,
+        py: Python<'py>,
+        sql: String,
+        parameters: Option<ListOrTuple>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
+        check_single_statement(&sql)?;
+        if stmt_writes(&sql) {
+            return Err(ProgrammingError::new_err("query_cached() only runs read-only statements"));
+        }
+        let (sql, parameters) = expand_sequence_parameters(py, sql, parameters)?;
+        let params = extract_parameters(py, parameters, &self.types)?;
+        let key = (sql.clone(), format!("{:?}", params));
+        let (types, limits, remote) = (self.types.clone(), self.limits, self.remote);
+        let conn_arc = self.conn.clone();
+        let exec_lock = self.exec_lock.clone();
+        let metrics = self.metrics.clone();
+        let (cache, busy_retry) = (self.result_cache.clone(), self.busy_retry);
+        future_into_py(py, async move {
+            let cached = cache.as_ref().and_then(|c| c.lock().get(&key));
+            if let Some((data, kinds)) = cached {
+                metrics.cache_hits.fetch_add(1, Ordering::Relaxed);
+                return rows_to_list(data, &kinds, &types).await;
+            }
+            let mut attempt = 0;
+            let result = loop {
+                let in_tx = in_transaction(&conn_arc);
+                let res: PyResult<(Vec<Vec<libsql_core::Value>>, Vec<ColumnKind>)> = async {
+                    let _exec = exec_lock.lock().await;
+                    let conn = {
+                        let guard = conn_arc.lock();
+                        guard.as_ref().cloned()
+                    }
+                    .ok_or_else(|| PyValueError::new_err("closed"))?;
+                    let stmt = retry_lost_stream(&conn, remote, || conn.prepare(&sql))
+                        .await
+                        .map_err(|e| sql_err(e, &sql))?;
+                    if !remote {
+                        check_parameter_count(stmt.parameter_count(), &params)?;
+                    }
+                    let kinds = ColumnKind::for_statement(&stmt, &types);
+                    metrics.add_statements(1);
+                    let mut rs = ResultSet::collected(limits, types.text_errors);
+                    let rows = stmt.query(params.clone()).await.map_err(|e| sql_err(e, &sql))?;
+                    rs.append(rows).await?;
+                    let data: Vec<_> = rs.ahead.into();
+                    // Results read inside a transaction may include uncommitted writes that a
+                    // rollback would take back without the update hook noticing.
+                    if let Some(cache) = &cache
+                        && conn.is_autocommit()
+                        && let Some(tables) = statement_tables(&conn, &sql).await?
+                    {
+                        cache.lock().insert(key.clone(), tables, data.clone(), kinds.clone());
+                    }
+                    Ok((data, kinds))
+                }
+                .await;
+                if let Err(e) = &res
+                    && busy_retry.backoff(e, &mut attempt, in_tx, &conn_arc, &metrics).await
+                {
+                    continue;
+                }
+                break res;
+            };
+            let (data, kinds) = metrics.track(result)?;
+            if cache.is_some() {
+                metrics.cache_misses.fetch_add(1, Ordering::Relaxed);
+            }
+            metrics.add_rows(data.len() as u64);
+            rows_to_list(data, &kinds, &types).await
+        })
+    }
+
+    /// Drops every entry from the result cache.
+    fn clear_result_cache(&self) {
+        if let Some(cache) = &self.result_cache {
+            cache.lock().entries.clear();
+        }
+    }
+
+    #[pyo3(signature = (sql, parameters=None, *, progress=None, progress_every=100))]
+    fn executemany<'py>(
+        slf: &Bound<'py, Self>,
+        py: Python<'py>,
+        sql: String,
+        parameters: Option<Bound<'py, PyAny>>,
+        progress: Option<PyObject>,
+        progress_every: u64,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let cursor = slf.borrow().new_cursor(slf.clone().unbind());
+        let py_cursor = cursor.into_pyobject(py)?.unbind();
+        Cursor::executemany(py_cursor, py, sql, parameters, progress, progress_every)
+    }
+
+    #[pyo3(signature = (script, *, commit=true))]
+    fn executescript<'py>(
+        slf: &Bound<'py, Self>,
+        py: Python<'py>,
+        script: String,
+        commit: bool,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let cursor = slf.borrow().new_cursor(slf.clone().unbind());
+        let py_cursor = cursor.into_pyobject(py)?.unbind();
+        Cursor::executescript(py_cursor, py, script, commit)
+    }
+
+    fn batch<'py>(&self, py: Python<'py>, statements: Vec<String>) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
+        for sql in &statements {
+            check_single_statement(sql)?;
+        }
+        let conn_arc = self.conn.clone();
+        let exec_lock = self.exec_lock.clone();
+        let behavior = self.isolation_level.clone();
+        let metrics = self.metrics.clone();
+        let remote = self.remote;
+        let text_errors = self.types.text_errors;
+        let (cache, busy_retry) = (self.result_cache.clone(), self.busy_retry);
+        future_into_py(py, async move {
+            let mut attempt = 0;
+            let result = loop {
+                let in_tx = in_transaction(&conn_arc);
+                let res: PyResult<Vec<Vec<Vec<libsql_core::Value>>>> = async {
+                    let exec = exec_lock.clone().lock_owned().await;
+                    let conn = {
+                        let guard = conn_arc.lock();
+                        guard.as_ref().cloned()
+                    }
+                    .ok_or_else(|| PyValueError::new_err("closed"))?;
+                    if statements.is_empty() {
+                        return Ok(Vec::new());
+                    }
+                    if !conn.is_autocommit() {
+                        return Err(ProgrammingError::new_err(
+                            "batch() cannot run inside an open transaction",
+                        ));
+                    }
+                    metrics.add_statements(statements.len() as u64);
+                    let mut results = Vec::with_capacity(statements.len());
+                    if remote {
+                        let script = statements
+                            .iter()
+                            .map(|s| s.trim().trim_end_matches(';'))
+                            .collect::<Vec<_>>()
+                            .join("\n;\n");
+                        let mut batch = conn
+                            .execute_transactional_batch(&script)
+                            .await
+                            .map_err(to_py_err)?;
+                        while let Some(rows) = batch.next_stmt_row() {
+                            results.push(match rows {
+                                Some(mut rows) => collect_rows(&mut rows, text_errors).await?,
+                                None => Vec::new(),
+                            });
+                        }
+                    } else {
+                        let guard = TxGuard::new(conn, true, behavior.as_deref(), Some(exec)).await?;
+                        for sql in &statements {
+                            let seen = cache_mark(&cache);
+                            let step: PyResult<Vec<Vec<libsql_core::Value>>> = async {
+                                let stmt = guard.conn().prepare(sql).await.map_err(|e| sql_err(e, sql))?;
+                                if stmt.column_count() > 0 {
+                                    let mut rows = stmt.query(()).await.map_err(|e| sql_err(e, sql))?;
+                                    collect_rows(&mut rows, text_errors).await
+                                } else {
+                                    stmt.execute(()).await.map_err(|e| sql_err(e, sql))?;
+                                    Ok(Vec::new())
+                                }
+                            }
+                            .await;
+                            finish_write(&cache, sql, seen);
+                            match step {
+                                Ok(rows) => results.push(rows),
+                                Err(e) => {
+                                    guard.rollback().await?;
+                                    return Err(e);
+                                }
+                            }
+                        }
+                        guard.commit().await?;
+                    }
+                    Ok(results)
+                }
+                .await;
+                if let Err(e) = &res
+                    && busy_retry.backoff(e, &mut attempt, in_tx, &conn_arc, &metrics).await
+                {
+                    continue;
+                }
+                break res;
+            };
+            let results = metrics.track(result)?;
+            metrics.add_rows(results.iter().map(|rows| rows.len() as u64).sum());
+            Python::with_gil(|py| {
+                let mut elements = Vec::with_capacity(results.len());
+                for rows in results {
+                    let mut py_rows = Vec::with_capacity(rows.len());
+                    for row in rows {
+                        let values = row
+                            .into_iter()
+                            .map(|v| convert_value(py, v))
+                            .collect::<PyResult<Vec<_>>>()?;
+                        py_rows.push(PyTuple::new(py, values)?.unbind().into_any());
+                    }
+                    elements.push(PyList::new(py, py_rows)?.unbind().into_any());
+                }
+                Ok(PyList::new(py, elements)?.unbind().into_any())
+            })
+        })
+    }
+
+    /// Runs through `Cursor::execute` like any other statement, so it waits its turn on the
+    /// connection and shows up in `queries()`.
+    #[pyo3(signature = (path, alias, encryption_key=None))]
+    fn attach<'py>(
+        slf: &Bound<'py, Self>,
+        py: Python<'py>,
+        path: std::path::PathBuf,
+        alias: String,
+        encryption_key: Option<String>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let path = path.to_string_lossy().into_owned();
+        let sql = format!("ATTACH DATABASE ? AS {}", quote_identifier(&alias));
+        let (sql, params) = match encryption_key {
+            Some(key) => (format!("{} KEY ?", sql), PyTuple::new(py, [path, key])?),
+            None => (sql, PyTuple::new(py, [path])?),
+        };
+        let cursor = slf.borrow().new_cursor(slf.clone().unbind());
+        let py_cursor = cursor.into_pyobject(py)?.unbind();
+        Cursor::execute(py_cursor, py, sql, Some(ListOrTuple::Tuple(params.unbind())), false, false, None)
+    }
+
+    fn detach<'py>(slf: &Bound<'py, Self>, py: Python<'py>, alias: String) -> PyResult<Bound<'py, PyAny>> {
+        let sql = format!("DETACH DATABASE {}", quote_identifier(&alias));
+        let cursor = slf.borrow().new_cursor(slf.clone().unbind());
+        let py_cursor = cursor.into_pyobject(py)?.unbind();
+        Cursor::execute(py_cursor, py, sql, None, false, false, None)
+    }
+
+    #[pyo3(signature = (table, source, *, batch_size=1000, columns=None, header=true, on_conflict=None, progress=None))]
+    #[allow(clippy::too_many_arguments)]
+    fn import_csv<'py>(
+        &self,
+        py: Python<'py>,
+        table: String,
+        source: Bound<'py, PyAny>,
+        batch_size: usize,
+        columns: Option<Vec<String>>,
+        header: bool,
+        on_conflict: Option<String>,
+        progress: Option<PyObject>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
+        let conflict = conflict_clause(on_conflict)?;
+        let batch_size = batch_size.max(1);
+        let (file, owned) = open_text_source(&source, "r")?;
+        let setup = || -> PyResult<(Bound<'py, PyAny>, Vec<String>)> {
+            let reader = py.import("csv")?.call_method1("reader", (&file,))?;
+            let mut columns = columns;
+            if header && let Some(first) = reader.try_iter()?.next() {
+                let first: Vec<String> = first?.extract()?;
+                columns.get_or_insert(first);
+            }
+            let columns = columns.filter(|c| !c.is_empty()).ok_or_else(|| {
+                PyValueError::new_err("no columns to import")
+            })?;
+            Ok((reader, columns))
+        };
+        let (reader, columns) = match setup() {
+            Ok(v) => v,
+            Err(e) => {
+                if owned {
+                    file.call_method0("close")?;
+                }
+                return Err(e);
+            }
+        };
+        let sql = format!(
+            "INSERT{} INTO {} ({}) VALUES ({})",
+            conflict,
+            quote_identifier(&table),
+            columns.iter().map(|c| quote_identifier(c)).collect::<Vec<_>>().join(", "),
+            vec!["?"; columns.len()].join(", "),
+        );
+        let width = columns.len();
+        let reader = reader.unbind();
+        let file = owned.then(|| file.unbind());
+        let conn_arc = self.conn.clone();
+        let metrics = self.metrics.clone();
+        let exec_lock = self.exec_lock.clone();
+        let behavior = self.isolation_level.clone();
+        let cache = self.result_cache.clone();
+        future_into_py(py, async move {
+            let result: PyResult<u64> = async {
+                let exec = exec_lock.lock_owned().await;
+                let conn = {
+                    let guard = conn_arc.lock();
+                    guard.as_ref().cloned()
+                }
+                .ok_or_else(|| PyValueError::new_err("closed"))?;
+                let txguard = TxGuard::new(conn, true, behavior.as_deref(), Some(exec)).await?;
+                let seen = cache_mark(&cache);
+                let stmt = match txguard.conn().prepare(&sql).await {
+                    Ok(stmt) => stmt,
+                    Err(e) => {
+                        txguard.rollback().await?;
+                        return Err(to_py_err(e));
+                    }
+                };
+                let mut total = 0u64;
+                loop {
+                    let chunk_res: PyResult<Vec<Vec<libsql_core::Value>>> = Python::with_gil(|py| {
+                        let reader = reader.bind(py);
+                        let mut chunk = Vec::with_capacity(batch_size);
+                        for _ in 0..batch_size {
+                            let record = match reader.call_method0("__next__") {
+                                Ok(record) => record,
+                                Err(e) if e.is_instance_of::<pyo3::exceptions::PyStopIteration>(py) => break,
+                                Err(e) => return Err(e),
+                            };
+                            let fields: Vec<String> = record.extract()?;
+                            if fields.is_empty() {
+                                continue;
+                            }
+                            if fields.len() != width {
+                                return Err(PyValueError::new_err(format!(
+                                    "csv line {}: expected {} fields, got {}",
+                                    reader.getattr("line_num")?,
+                                    width,
+                                    fields.len()
+                                )));
+                            }
+                            chunk.push(fields.into_iter().map(libsql_core::Value::Text).collect());
+                        }
+                        Ok(chunk)
+                    });
+                    let chunk = match chunk_res {
+                        Ok(chunk) => chunk,
+                        Err(e) => {
+                            txguard.rollback().await?;
+                            return Err(e);
+                        }
+                    };
+                    if chunk.is_empty() {
+                        break;
+                    }
+                    let rows = chunk.len() as u64;
+                    for values in chunk {
+                        if let Err(e) = stmt.execute(values).await {
+                            txguard.rollback().await?;
+                            return Err(to_py_err(e));
+                        }
+                        stmt.reset();
+                    }
+                    total += rows;
+                    metrics.add_statements(rows);
+                    if let Some(progress) = &progress {
+                        let res = Python::with_gil(|py| progress.call1(py, (total,)).map(|_| ()));
+                        if let Err(e) = res {
+                            txguard.rollback().await?;
+                            return Err(e);
+                        }
+                    }
+                }
+                txguard.commit().await?;
+                finish_write(&cache, &sql, seen);
+                Ok(total)
+            }
+            .await;
+            if let Some(file) = file {
+                Python::with_gil(|py| file.call_method0(py, "close"))?;
+            }
+            metrics.track(result)
+        })
+    }
+
+    #[pyo3(signature = (table, rows, *, columns=None, on_conflict=None, batch_size=500, progress=None))]
+    #[allow(clippy::too_many_arguments)]
+    fn insert_many<'py>(
+        &self,
+        py: Python<'py>,
+        table: String,
+        rows: Bound<'py, PyAny>,
+        columns: Option<Vec<String>>,
+        on_conflict: Option<String>,
+        batch_size: usize,
+        progress: Option<PyObject>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
+        let conflict = conflict_clause(on_conflict)?;
+        let progress_total = rows.len().ok();
+        let iter = rows.try_iter()?;
+        let first = iter.clone().next().transpose()?;
+        let mut columns = columns;
+        if columns.is_none()
+            && let Some(map) = first.as_ref().and_then(|r| r.downcast::<PyDict>().ok())
+        {
+            columns = Some(map.keys().extract()?);
+        }
+        let width = match (&columns, &first) {
+            (Some(columns), _) => columns.len(),
+            (None, Some(first)) => first.len()?,
+            (None, None) => 0,
+        };
+        if first.is_some() && width == 0 {
+            return Err(PyValueError::new_err("no columns to insert"));
+        }
+        let prefix = format!(
+            "INSERT{} INTO {}{} VALUES ",
+            conflict,
+            quote_identifier(&table),
+            match &columns {
+                Some(columns) => format!(
+                    " ({})",
+                    columns.iter().map(|c| quote_identifier(c)).collect::<Vec<_>>().join(", ")
+                ),
+                None => String::new(),
+            },
+        );
+        let group = format!("({})", vec!["?"; width].join(", "));
+        let sql_for = move |n: usize| format!("{}{}", prefix, vec![group.as_str(); n].join(", "));
+        let batch_size = batch_size.clamp(1, (MAX_VARIABLE_NUMBER / width.max(1)).max(1));
+        let first = first.map(Bound::unbind);
+        let iter = iter.into_any().unbind();
+        let types = self.types.clone();
+        let conn_arc = self.conn.clone();
+        let metrics = self.metrics.clone();
+        let exec_lock = self.exec_lock.clone();
+        let behavior = self.isolation_level.clone();
+        let cache = self.result_cache.clone();
+        future_into_py(py, async move {
+            let result: PyResult<u64> = async {
+                let exec = exec_lock.lock_owned().await;
+                let conn = {
+                    let guard = conn_arc.lock();
+                    guard.as_ref().cloned()
+                }
+                .ok_or_else(|| PyValueError::new_err("closed"))?;
+                let Some(first) = first else {
+                    return Ok(0);
+                };
+                let txguard = TxGuard::new(conn, true, behavior.as_deref(), Some(exec)).await?;
+                let seen = cache_mark(&cache);
+                let mut pending = Some(first);
+                let mut full_stmt = None;
+                let mut total = 0u64;
+                let mut done = 0u64;
+                loop {
+                    let chunk_res: PyResult<(usize, Vec<libsql_core::Value>)> = Python::with_gil(|py| {
+                        let iter = iter.bind(py);
+                        let mut values = Vec::with_capacity(batch_size * width);
+                        let mut count = 0;
+                        while count < batch_size {
+                            let row = match pending.take() {
+                                Some(row) => row.into_bound(py),
+                                None => match iter.call_method0("__next__") {
+                                    Ok(row) => row,
+                                    Err(e) if e.is_instance_of::<pyo3::exceptions::PyStopIteration>(py) => break,
+                                    Err(e) => return Err(e),
+                                },
+                            };
+                            values.extend(insert_row_values(py, &row, columns.as_deref(), width, &types)?);
+                            count += 1;
+                        }
+                        Ok((count, values))
+                    });
+                    let (count, values) = match chunk_res {
+                        Ok(chunk) => chunk,
+                        Err(e) => {
+                            txguard.rollback().await?;
+                            return Err(e);
+                        }
+                    };
+                    if count == 0 {
+                        break;
+                    }
+                    let step = async {
+                        if count == batch_size {
+                            if full_stmt.is_none() {
+                                full_stmt = Some(txguard.conn().prepare(&sql_for(count)).await?);
+                            }
+                            let stmt = full_stmt.as_ref().unwrap();
+                            let res = stmt.execute(values).await;
+                            stmt.reset();
+                            res?;
+                        } else {
+                            txguard.conn().execute(&sql_for(count), values).await?;
+                        }
+                        Ok::<_, libsql_core::Error>(txguard.conn().changes())
+                    };
+                    match step.await {
+                        Ok(changes) => total += changes,
+                        Err(e) => {
+                            txguard.rollback().await?;
+                            return Err(to_py_err(e));
+                        }
+                    }
+                    metrics.add_statements(1);
+                    done += count as u64;
+                    if let Some(progress) = &progress
+                        && let Err(e) = report_progress(progress, done, progress_total)
+                    {
+                        txguard.rollback().await?;
+                        return Err(e);
+                    }
+                    if count < batch_size {
+                        break;
+                    }
+                }
+                txguard.commit().await?;
+                finish_write(&cache, "INSERT", seen);
+                Ok(total)
+            }
+            .await;
+            metrics.track(result)
+        })
+    }
+
+    #[getter]
+    fn isolation_level(&self) -> Option<String> {
+        self.isolation_level.clone()
+    }
+
+    /// Like `sqlite3`: `None` commits an open transaction and switches to autocommit, a
+    /// behavior sets the `BEGIN` used for later implicit transactions. The commit is queued
+    /// ahead of the next statement on the connection.
+    #[setter]
+    fn set_isolation_level(&mut self, value: Option<String>) -> PyResult<()> {
+        self.owner.check()?;
+        let value = isolation_keyword(value)?;
+        if value.is_none() {
+            // sqlite3 commits here, but a setter can't await the COMMIT or report its failure.
+            if in_transaction(&self.conn) {
+                return Err(ProgrammingError::new_err(
+                    "cannot set isolation_level to None inside a transaction; \
+                     commit or roll back first",
+                ));
+            }
+            self.autocommit = 1;
+        } else if self.isolation_level.is_none() && self.autocommit == 1 {
+            self.autocommit = 0;
+        }
+        self.isolation_level = value;
+        Ok(())
+    }
+
+    #[getter]
+    fn total_changes(&self) -> PyResult<u64> {
+        self.owner.check()?;
+        let guard = self.conn.lock();
+        Ok(guard.as_ref().map(|conn| conn.total_changes()).unwrap_or(0))
+    }
+
+    #[getter]
+    fn in_transaction(&self) -> PyResult<bool> {
+        self.owner.check()?;
+        let guard = self.conn.lock();
+        if let Some(conn) = guard.as_ref() {
+            Ok(!conn.is_autocommit() || self.autocommit == 0)
+        } else {
+            Ok(false)
+        }
+    }
+
+    #[pyo3(signature = (behavior=None, *, concurrent=false))]
+    fn transaction(&self, behavior: Option<String>, concurrent: bool) -> PyResult<Transaction> {
+        self.owner.check()?;
+        if concurrent && behavior.is_some() {
+            return Err(PyValueError::new_err("a concurrent transaction takes no behavior"));
+        }
+        let behavior = match behavior.or_else(|| self.isolation_level.clone()) {
+            _ if concurrent => "CONCURRENT".to_string(),
+            Some(b) if !b.is_empty() => {
+                sql_keyword("transaction behavior", &b, &["DEFERRED", "IMMEDIATE", "EXCLUSIVE"])?
+            }
+            _ => "DEFERRED".to_string(),
+        };
+        Ok(Transaction {
+            conn: self.conn.clone(),
+            exec_lock: self.exec_lock.clone(),
+            behavior,
+            concurrent,
+            busy_retry: self.busy_retry,
+            metrics: self.metrics.clone(),
+            owner: self.owner,
+        })
+    }
+
+    fn __aenter__<'py>(slf: Py<Self>, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        future_into_py(py, async move { Ok(slf) })
+    }
+
+    #[pyo3(signature = (exc_type=None, _exc_val=None, _exc_tb=None))]
+    fn __aexit__<'py>(
+        &self,
+        py: Python<'py>,
+        exc_type: Option<PyObject>,
+        _exc_val: Option<PyObject>,
+        _exc_tb: Option<PyObject>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
+        let conn_arc = self.conn.clone();
+        let is_error = exc_type.is_some();
+        let open_tx = conn_arc.lock().as_ref().is_some_and(|c| !c.is_autocommit());
+        if open_tx && !is_error {
+            warn(py, "open transaction committed by __aexit__")?;
+        }
+        let shutdown = self.close_on_exit.then(|| self.shutdown());
+        future_into_py(py, async move {
+            let conn_opt = {
+                let guard = conn_arc.lock();
+                guard.as_ref().cloned()
+            };
+            if let Some(conn) = conn_opt {
+                if !conn.is_autocommit() {
+                    if is_error {
+                        let _ = conn.execute("ROLLBACK", ()).await;
+                    } else {
+                        let _ = conn.execute("COMMIT", ()).await;
+                    }
+                }
+            }
+            if let Some(shutdown) = shutdown {
+                shutdown.await?;
+            }
+            Ok(false)
+        })
+    }
 }
 
 #[pyclass]
-pub struct Connection {
-    db: Arc<libsql_core::Database>,
+pub struct Statement {
+    stmt: Arc<AsyncMutex<Option<libsql_core::Statement>>>,
     conn: Arc<SyncMutex<Option<libsql_core::Connection>>>,
-    isolation_level: Option<String>,
-    #[pyo3(get, set)]
-    autocommit: i32,
+    exec_lock: Arc<AsyncMutex<()>>,
+    #[pyo3(get)]
+    sql: String,
+    #[pyo3(get)]
+    columns: Vec<String>,
+    #[pyo3(get)]
+    parameter_names: Vec<Option<String>>,
+    meta: Vec<ColumnMeta>,
+    kinds: Vec<ColumnKind>,
+    types: TypeConfig,
+    limits: ResultLimits,
+    metrics: Arc<ConnectionMetrics>,
+    remote: bool,
+    cache: Option<ResultCacheRef>,
+    busy_retry: BusyRetry,
+    owner: Owner,
+}
+
+impl Statement {
+    /// Locks the statement for the duration of one call, checking that it and its connection
+    /// are still open.
+    async fn lock<'a>(
+        stmt: &'a AsyncMutex<Option<libsql_core::Statement>>,
+        conn: &SyncMutex<Option<libsql_core::Connection>>,
+    ) -> PyResult<tokio::sync::MutexGuard<'a, Option<libsql_core::Statement>>> {
+        let guard = stmt.lock().await;
+        if guard.is_none() {
+            return Err(ProgrammingError::new_err("statement is finalized"));
+        }
+        if conn.lock().is_none() {
+            return Err(PyValueError::new_err("closed"));
+        }
+        Ok(guard)
+    }
+
+    fn try_lock(&self) -> PyResult<tokio::sync::MutexGuard<'_, Option<libsql_core::Statement>>> {
+        self.stmt
+            .try_lock()
+            .map_err(|_| ProgrammingError::new_err("statement is in use"))
+    }
 }
 
 #[pymethods]
-impl Connection {
-    fn close<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
-        let conn_arc = self.conn.clone();
+impl Statement {
+    #[pyo3(signature = (parameters=None))]
+    fn execute<'py>(
+        &self,
+        py: Python<'py>,
+        parameters: Option<ListOrTuple>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
+        let params = extract_parameters(py, parameters, &self.types)?;
+        if !self.remote {
+            check_parameter_count(self.parameter_names.len(), &params)?;
+        }
+        let (stmt, conn, exec_lock, metrics) = (
+            self.stmt.clone(),
+            self.conn.clone(),
+            self.exec_lock.clone(),
+            self.metrics.clone(),
+        );
+        let sql = self.sql.clone();
+        let (cache, busy_retry) = (self.cache.clone(), self.busy_retry);
         future_into_py(py, async move {
-            let lock = conn_arc.lock().take();
-            drop(lock);
-            Ok(())
+            let mut attempt = 0;
+            let result = loop {
+                let in_tx = in_transaction(&conn);
+                let res: PyResult<u64> = async {
+                    let _exec = exec_lock.lock().await;
+                    let guard = Statement::lock(&stmt, &conn).await?;
+                    let stmt = guard.as_ref().unwrap();
+                    metrics.add_statements(1);
+                    let seen = cache_mark(&cache);
+                    let res = stmt.run(params.clone()).await;
+                    stmt.reset();
+                    res.map_err(|e| sql_err(e, &sql))?;
+                    finish_write(&cache, &sql, seen);
+                    let changes = conn.lock().as_ref().map_or(0, |c| c.changes());
+                    Ok(changes)
+                }
+                .await;
+                if let Err(e) = &res
+                    && busy_retry.backoff(e, &mut attempt, in_tx, &conn, &metrics).await
+                {
+                    continue;
+                }
+                break res;
+            };
+            metrics.track(result)
         })
     }
 
-    fn cursor(&self) -> PyResult<Cursor> {
-        Ok(Cursor {
-            arraysize: 1,
-            conn: self.conn.clone(),
-            rows: Arc::new(AsyncMutex::new(None)),
-            columns: Arc::new(SyncMutex::new(None)),
-            rowcount: Arc::new(AtomicI64::new(0)),
-            last_insert_rowid: Arc::new(AtomicI64::new(0)),
-            isolation_level: self.isolation_level.clone(),
-            autocommit: self.autocommit,
-            done: Arc::new(AtomicBool::new(false)),
+    #[pyo3(signature = (parameters=None))]
+    fn query<'py>(
+        &self,
+        py: Python<'py>,
+        parameters: Option<ListOrTuple>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
+        let params = extract_parameters(py, parameters, &self.types)?;
+        if !self.remote {
+            check_parameter_count(self.parameter_names.len(), &params)?;
+        }
+        let (stmt, conn, exec_lock, metrics) = (
+            self.stmt.clone(),
+            self.conn.clone(),
+            self.exec_lock.clone(),
+            self.metrics.clone(),
+        );
+        let (kinds, types, limits) = (self.kinds.clone(), self.types.clone(), self.limits);
+        let sql = self.sql.clone();
+        let busy_retry = self.busy_retry;
+        future_into_py(py, async move {
+            let mut attempt = 0;
+            let result = loop {
+                let in_tx = in_transaction(&conn);
+                let res: PyResult<VecDeque<Vec<libsql_core::Value>>> = async {
+                    let _exec = exec_lock.lock().await;
+                    let guard = Statement::lock(&stmt, &conn).await?;
+                    let stmt = guard.as_ref().unwrap();
+                    metrics.add_statements(1);
+                    let mut rs = ResultSet::collected(limits, types.text_errors);
+                    let res = match stmt.query(params.clone()).await {
+                        Ok(rows) => rs.append(rows).await,
+                        Err(e) => Err(sql_err(e, &sql)),
+                    };
+                    stmt.reset();
+                    res.map(|_| rs.ahead)
+                }
+                .await;
+                if let Err(e) = &res
+                    && busy_retry.backoff(e, &mut attempt, in_tx, &conn, &metrics).await
+                {
+                    continue;
+                }
+                break res;
+            };
+            let data = metrics.track(result)?;
+            metrics.add_rows(data.len() as u64);
+            rows_to_list(data.into(), &kinds, &types).await
         })
     }
 
-    fn sync<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
-        let db = self.db.clone();
+    #[getter]
+    fn parameter_count(&self) -> usize {
+        self.parameter_names.len()
+    }
+
+    #[getter]
+    fn column_metadata<'py>(&self, py: Python<'py>) -> PyResult<Vec<Bound<'py, PyDict>>> {
+        self.meta.iter().map(|m| m.to_dict(py)).collect()
+    }
+
+    fn reset(&self) -> PyResult<()> {
+        self.owner.check()?;
+        if let Some(stmt) = self.try_lock()?.as_ref() {
+            stmt.reset();
+        }
+        Ok(())
+    }
+
+    fn finalize(&self) -> PyResult<()> {
+        if let Some(mut stmt) = self.try_lock()?.take() {
+            stmt.finalize();
+        }
+        Ok(())
+    }
+}
+
+#[pyclass]
+pub struct Transaction {
+    conn: Arc<SyncMutex<Option<libsql_core::Connection>>>,
+    exec_lock: Arc<AsyncMutex<()>>,
+    behavior: String,
+    concurrent: bool,
+    busy_retry: BusyRetry,
+    metrics: Arc<ConnectionMetrics>,
+    owner: Owner,
+}
+
+#[pymethods]
+impl Transaction {
+    fn __aenter__<'py>(slf: Py<Self>, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        slf.borrow(py).owner.check()?;
+        let (conn_arc, exec_lock, behavior, busy_retry, metrics) = {
+            let b = slf.borrow(py);
+            (b.conn.clone(), b.exec_lock.clone(), b.behavior.clone(), b.busy_retry, b.metrics.clone())
+        };
+        let concurrent = slf.borrow(py).concurrent;
         future_into_py(py, async move {
-            db.sync().await.map_err(to_py_err)?;
-            Ok(())
+            let mut attempt = 0;
+            loop {
+                let in_tx = in_transaction(&conn_arc);
+                let exec = exec_lock.lock().await;
+                let conn = {
+                    let guard = conn_arc.lock();
+                    guard.as_ref().cloned()
+                }
+                .ok_or_else(|| PyValueError::new_err("closed"))?;
+                if !conn.is_autocommit() {
+                    return Err(ProgrammingError::new_err("a transaction is already open"));
+                }
+                let res = conn.execute(&format!("BEGIN {}", behavior), ()).await.map_err(|e| {
+                    if concurrent && e.to_string().contains("syntax error") {
+                        OperationalError::new_err("BEGIN CONCURRENT is not supported by this database")
+                    } else {
+                        to_py_err(e)
+                    }
+                });
+                drop(exec);
+                if let Err(e) = &res
+                    && busy_retry.backoff(e, &mut attempt, in_tx, &conn_arc, &metrics).await
+                {
+                    continue;
+                }
+                res?;
+                return Ok(slf);
+            }
         })
     }
 
-    fn commit<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+    #[pyo3(signature = (exc_type=None, _exc_val=None, _exc_tb=None))]
+    fn __aexit__<'py>(
+        &self,
+        py: Python<'py>,
+        exc_type: Option<PyObject>,
+        _exc_val: Option<PyObject>,
+        _exc_tb: Option<PyObject>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
         let conn_arc = self.conn.clone();
+        let exec_lock = self.exec_lock.clone();
+        let is_error = exc_type.is_some();
+        let concurrent = self.concurrent;
         future_into_py(py, async move {
+            let _exec = exec_lock.lock().await;
             let conn_opt = {
                 let guard = conn_arc.lock();
                 guard.as_ref().cloned()
             };
-            if let Some(conn) = conn_opt {
-                if !conn.is_autocommit() {
-                    conn.execute("COMMIT", ()).await.map_err(to_py_err)?;
+            if let Some(conn) = conn_opt
+                && !conn.is_autocommit()
+            {
+                if is_error {
+                    conn.execute("ROLLBACK", ()).await.map_err(to_py_err)?;
+                } else if let Err(e) = conn.execute("COMMIT", ()).await {
+                    let err = to_py_err(e);
+                    // A concurrent transaction that lost the race to another writer stays open
+                    // after the failed COMMIT; it is rolled back so the caller can simply retry.
+                    if concurrent && Python::with_gil(|py| err.is_instance_of::<TimeoutError>(py)) {
+                        conn.execute("ROLLBACK", ()).await.map_err(to_py_err)?;
+                        let msg = Python::with_gil(|py| err.value(py).to_string());
+                        return Err(ConflictError::new_err(msg));
+                    }
+                    return Err(err);
                 }
             }
-            Ok(())
+            Ok(false)
         })
     }
+}
 
-    fn rollback<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+struct ChangeEvent {
+    op: &'static str,
+    db: String,
+    table: String,
+    rowid: i64,
+}
+
+struct ChangeSubscriber {
+    id: u64,
+    tables: Option<Vec<String>>,
+    sender: mpsc::UnboundedSender<ChangeEvent>,
+}
+
+impl ChangeSubscriber {
+    fn wants(&self, table: &str) -> bool {
+        self.tables
+            .as_ref()
+            .is_none_or(|tables| tables.iter().any(|t| t.eq_ignore_ascii_case(table)))
+    }
+}
+
+/// A statement submitted through a cursor that has not returned yet, for `Connection.queries()`
+/// and `Connection.cancel()`.
+struct InFlightQuery {
+    id: u64,
+    sql: String,
+    submitted: Instant,
+    running: bool,
+    cancelled: bool,
+}
+
+#[derive(Default)]
+struct QueryRegistry {
+    next_id: u64,
+    queries: Vec<InFlightQuery>,
+    max_queue: Option<usize>,
+}
+
+impl QueryRegistry {
+    /// Statements waiting for the connection behind the one that holds it.
+    fn queued(&self) -> usize {
+        self.queries.iter().filter(|q| !q.running).count()
+    }
+}
+
+type QueryRegistryRef = Arc<SyncMutex<QueryRegistry>>;
+
+/// Registration of one in-flight statement; dropping it, also when the call is cancelled,
+/// removes the statement from the registry.
+struct QueryTicket {
+    registry: QueryRegistryRef,
+    id: u64,
+}
+
+impl QueryTicket {
+    fn register(registry: &QueryRegistryRef, sql: &str) -> PyResult<Self> {
+        let mut reg = registry.lock();
+        if reg.max_queue.is_some_and(|max| reg.queued() >= max) {
+            return Err(OperationalError::new_err(
+                "too many statements queued on this connection",
+            ));
+        }
+        reg.next_id += 1;
+        let id = reg.next_id;
+        reg.queries.push(InFlightQuery {
+            id,
+            sql: sql.to_string(),
+            submitted: Instant::now(),
+            running: false,
+            cancelled: false,
+        });
+        Ok(Self { registry: registry.clone(), id })
+    }
+
+    /// Marks the statement as running once it holds the connection, or fails if it was
+    /// cancelled while queued.
+    fn start(&self) -> PyResult<()> {
+        let mut reg = self.registry.lock();
+        match reg.queries.iter_mut().find(|q| q.id == self.id) {
+            Some(q) if q.cancelled => Err(OperationalError::new_err("interrupted")),
+            Some(q) => {
+                q.running = true;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for QueryTicket {
+    fn drop(&mut self) {
+        self.registry.lock().queries.retain(|q| q.id != self.id);
+    }
+}
+
+#[derive(Default)]
+struct UpdateHooks {
+    installed: bool,
+    callback: Option<PyObject>,
+    subscribers: Vec<ChangeSubscriber>,
+    next_id: u64,
+    cache: Option<ResultCacheRef>,
+}
+
+type ResultCacheRef = Arc<SyncMutex<ResultCache>>;
+
+/// Rows returned by `query_cached()`, keyed by SQL and parameter values. An entry is dropped
+/// when the update hook reports a change to a table it read, once it is older than `ttl`,
+/// and least recently used first when `capacity` is reached.
+struct ResultCache {
+    capacity: usize,
+    ttl: Option<Duration>,
+    entries: HashMap<(String, String), CachedResult>,
+    /// Bumped by every change the update hook reports, see `after_write`.
+    changes: u64,
+    tick: u64,
+}
+
+struct CachedResult {
+    tables: Vec<String>,
+    rows: Vec<Vec<libsql_core::Value>>,
+    kinds: Vec<ColumnKind>,
+    stored: Instant,
+    used: u64,
+}
+
+impl ResultCache {
+    fn new(capacity: usize, ttl: Option<Duration>) -> Self {
+        Self {
+            capacity,
+            ttl,
+            entries: HashMap::new(),
+            changes: 0,
+            tick: 0,
+        }
+    }
+
+    fn get(&mut self, key: &(String, String)) -> Option<(Vec<Vec<libsql_core::Value>>, Vec<ColumnKind>)> {
+        let entry = self.entries.get_mut(key)?;
+        if self.ttl.is_some_and(|ttl| entry.stored.elapsed() >= ttl) {
+            self.entries.remove(key);
+            return None;
+        }
+        self.tick += 1;
+        entry.used = self.tick;
+        Some((entry.rows.clone(), entry.kinds.clone()))
+    }
+
+    fn insert(&mut self, key: (String, String), tables: Vec<String>, rows: Vec<Vec<libsql_core::Value>>, kinds: Vec<ColumnKind>) {
+        if !self.entries.contains_key(&key)
+            && self.entries.len() >= self.capacity
+            && let Some(oldest) = self.entries.iter().min_by_key(|(_, e)| e.used).map(|(k, _)| k.clone())
+        {
+            self.entries.remove(&oldest);
+        }
+        self.tick += 1;
+        let entry = CachedResult {
+            tables,
+            rows,
+            kinds,
+            stored: Instant::now(),
+            used: self.tick,
+        };
+        self.entries.insert(key, entry);
+    }
+
+    fn invalidate(&mut self, table: &str) {
+        self.changes += 1;
+        self.entries
+            .retain(|_, e| !e.tables.iter().any(|t| t.eq_ignore_ascii_case(table)));
+    }
+
+    /// Called after a write statement with the value of `changes` from before it. Schema
+    /// changes, truncating `DELETE`s and writes to `WITHOUT ROWID` tables don't reach the
+    /// update hook, so a write that reported nothing clears the whole cache.
+    fn after_write(&mut self, seen: u64) {
+        if self.changes == seen {
+            self.entries.clear();
+        }
+    }
+}
+
+/// The cache's change counter before a statement, for `finish_write`.
+fn cache_mark(cache: &Option<ResultCacheRef>) -> u64 {
+    cache.as_ref().map_or(0, |c| c.lock().changes)
+}
+
+fn finish_write(cache: &Option<ResultCacheRef>, sql: &str, seen: u64) {
+    if let Some(cache) = cache
+        && stmt_writes(sql)
+    {
+        cache.lock().after_write(seen);
+    }
+}
+
+/// Names of the tables a read-only statement reads, from the `OpenRead` instructions of its
+/// `EXPLAIN` listing: the root page of each opened table or index is looked up in
+/// `sqlite_master`. `None` for statements that are not safe to cache: ones reading attached
+/// or temporary databases, or virtual tables, whose changes the update hook does not cover.
+async fn statement_tables(conn: &libsql_core::Connection, sql: &str) -> PyResult<Option<Vec<String>>> {
+    let mut rows = conn.query(&format!("EXPLAIN {}", sql), ()).await.map_err(to_py_err)?;
+    let mut pages = Vec::new();
+    while let Some(row) = rows.next().await.map_err(to_py_err)? {
+        let opcode = row.get::<String>(1).map_err(to_py_err)?;
+        match opcode.as_str() {
+            "OpenRead" => {
+                if row.get::<i64>(4).map_err(to_py_err)? != 0 {
+                    return Ok(None);
+                }
+                pages.push(row.get::<i64>(3).map_err(to_py_err)?);
+            }
+            "VOpen" => return Ok(None),
+            _ => {}
+        }
+    }
+    drop(rows);
+    let mut tables: Vec<String> = Vec::new();
+    let mut rows = conn
+        .query("SELECT rootpage, tbl_name FROM sqlite_master WHERE rootpage > 0", ())
+        .await
+        .map_err(to_py_err)?;
+    while let Some(row) = rows.next().await.map_err(to_py_err)? {
+        let table = row.get::<String>(1).map_err(to_py_err)?;
+        if pages.contains(&row.get::<i64>(0).map_err(to_py_err)?) && !tables.contains(&table) {
+            tables.push(table);
+        }
+    }
+    Ok(Some(tables))
+}
+
+fn install_update_hooks(
+    conn: &libsql_core::Connection,
+    hooks: &Arc<SyncMutex<UpdateHooks>>,
+) -> PyResult<()> {
+    if hooks.lock().installed {
+        return Ok(());
+    }
+    let state = hooks.clone();
+    conn.add_update_hook(Box::new(move |op, db, table, rowid| {
+        dispatch_update(&state, op, db, table, rowid)
+    }))
+    .map_err(to_py_err)?;
+    hooks.lock().installed = true;
+    Ok(())
+}
+
+fn dispatch_update(
+    hooks: &SyncMutex<UpdateHooks>,
+    op: libsql_core::Op,
+    db: &str,
+    table: &str,
+    rowid: i64,
+) {
+    let op = match op {
+        libsql_core::Op::Insert => "INSERT",
+        libsql_core::Op::Update => "UPDATE",
+        libsql_core::Op::Delete => "DELETE",
+    };
+    let has_callback = {
+        let mut state = hooks.lock();
+        if let Some(cache) = &state.cache {
+            cache.lock().invalidate(table);
+        }
+        state.subscribers.retain(|s| {
+            if !s.wants(table) {
+                return !s.sender.is_closed();
+            }
+            s.sender
+                .send(ChangeEvent {
+                    op,
+                    db: db.to_string(),
+                    table: table.to_string(),
+                    rowid,
+                })
+                .is_ok()
+        });
+        state.callback.is_some()
+    };
+    if has_callback {
+        Python::with_gil(|py| {
+            let callback = hooks.lock().callback.as_ref().map(|cb| cb.clone_ref(py));
+            if let Some(cb) = callback
+                && let Err(e) = cb.call1(py, (op, db, table, rowid))
+            {
+                e.write_unraisable(py, Some(cb.bind(py)));
+            }
+        });
+    }
+}
+
+async fn lookup_changed_row(
+    conn: &libsql_core::Connection,
+    event: &ChangeEvent,
+    text_errors: TextErrors,
+) -> PyResult<Option<Vec<(String, libsql_core::Value)>>> {
+    let sql = format!(
+        "SELECT * FROM {}.{} WHERE rowid = ?",
+        quote_identifier(&event.db),
+        quote_identifier(&event.table)
+    );
+    let stmt = conn.prepare(&sql).await.map_err(to_py_err)?;
+    let names: Vec<String> = stmt.columns().iter().map(|c| c.name().to_string()).collect();
+    let mut rows = stmt.query([event.rowid]).await.map_err(to_py_err)?;
+    let Some(row) = rows.next().await.map_err(to_py_err)? else {
+        return Ok(None);
+    };
+    let mut values = Vec::with_capacity(names.len());
+    for (i, name) in names.into_iter().enumerate() {
+        values.push((name, row_value(&row, i as i32, text_errors)?));
+    }
+    Ok(Some(values))
+}
+
+#[pyclass]
+pub struct ChangeStream {
+    id: u64,
+    conn: Arc<SyncMutex<Option<libsql_core::Connection>>>,
+    exec_lock: Arc<AsyncMutex<()>>,
+    hooks: Arc<SyncMutex<UpdateHooks>>,
+    receiver: Arc<AsyncMutex<mpsc::UnboundedReceiver<ChangeEvent>>>,
+    text_errors: TextErrors,
+    owner: Owner,
+}
+
+#[pymethods]
+impl ChangeStream {
+    fn close(&self) {
+        self.hooks.lock().subscribers.retain(|s| s.id != self.id);
+    }
+
+    fn __aiter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __anext__<'py>(&self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyAny>>> {
+        self.owner.check()?;
         let conn_arc = self.conn.clone();
+        let exec_lock = self.exec_lock.clone();
+        let receiver = self.receiver.clone();
+        let text_errors = self.text_errors;
         future_into_py(py, async move {
-            let conn_opt = {
-                let guard = conn_arc.lock();
-                guard.as_ref().cloned()
+            let Some(event) = receiver.lock().await.recv().await else {
+                return Err(pyo3::exceptions::PyStopAsyncIteration::new_err("done"));
             };
-            if let Some(conn) = conn_opt {
-                if !conn.is_autocommit() {
-                    conn.execute("ROLLBACK", ()).await.map_err(to_py_err)?;
+            let row = if event.op == "DELETE" {
+                None
+            } else {
+                let _exec = exec_lock.lock().await;
+                let conn = {
+                    let guard = conn_arc.lock();
+                    guard.as_ref().cloned()
+                };
+                match conn {
+                    Some(conn) => lookup_changed_row(&conn, &event, text_errors).await?,
+                    None => None,
                 }
-            }
-            Ok(())
+            };
+            Python::with_gil(|py| {
+                let d = PyDict::new(py);
+                d.set_item("op", event.op)?;
+                d.set_item("db", &event.db)?;
+                d.set_item("table", &event.table)?;
+                d.set_item("rowid", event.rowid)?;
+                match row {
+                    Some(values) => {
+                        let row = PyDict::new(py);
+                        for (name, value) in values {
+                            row.set_item(name, convert_value(py, value)?)?;
+                        }
+                        d.set_item("row", row)?;
+                    }
+                    None => d.set_item("row", py.None())?,
+                }
+                Ok(d.into_any().unbind())
+            })
         })
+        .map(Some)
     }
+}
 
-    #[pyo3(signature = (sql, parameters=None))]
-    fn execute<'py>(
-        &self,
-        py: Python<'py>,
-        sql: String,
-        parameters: Option<ListOrTuple>,
-    ) -> PyResult<Bound<'py, PyAny>> {
-        let cursor = self.cursor()?;
-        let py_cursor = cursor.into_pyobject(py)?.unbind();
-        Cursor::execute(py_cursor, py, sql, parameters)
+const EXPORT_CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Clone, Copy)]
+enum ExportFormat {
+    Csv { header: bool },
+    Jsonl,
+}
+
+enum ExportSink {
+    File(std::io::BufWriter<std::fs::File>),
+    Python(PyObject),
+}
+
+impl ExportSink {
+    fn open(target: &Bound<'_, PyAny>) -> PyResult<Self> {
+        if target.is_instance_of::<PyString>() || target.hasattr("__fspath__")? {
+            let path: std::path::PathBuf = target.extract()?;
+            Ok(ExportSink::File(std::io::BufWriter::new(std::fs::File::create(path)?)))
+        } else {
+            Ok(ExportSink::Python(target.clone().unbind()))
+        }
     }
 
-    #[pyo3(signature = (sql, parameters=None))]
-    fn executemany<'py>(
-        &self,
-        py: Python<'py>,
-        sql: String,
-        parameters: Option<Bound<'py, PyAny>>,
-    ) -> PyResult<Bound<'py, PyAny>> {
-        let cursor = self.cursor()?;
-        let py_cursor = cursor.into_pyobject(py)?.unbind();
-        Cursor::executemany(py_cursor, py, sql, parameters)
+    fn write(&mut self, data: &str) -> PyResult<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        match self {
+            ExportSink::File(file) => Ok(std::io::Write::write_all(file, data.as_bytes())?),
+            ExportSink::Python(file) => {
+                Python::with_gil(|py| file.call_method1(py, "write", (data,)).map(|_| ()))
+            }
+        }
+    }
+
+    fn finish(self) -> PyResult<()> {
+        match self {
+            ExportSink::File(mut file) => Ok(std::io::Write::flush(&mut file)?),
+            ExportSink::Python(_) => Ok(()),
+        }
+    }
+}
+
+fn write_hex(out: &mut String, bytes: &[u8]) {
+    for b in bytes {
+        let _ = write!(out, "{:02x}", b);
+    }
+}
+
+fn write_csv_text(out: &mut String, text: &str) {
+    if text.contains([',', '"', '\n', '\r']) {
+        out.push('"');
+        out.push_str(&text.replace('"', "\"\""));
+        out.push('"');
+    } else {
+        out.push_str(text);
+    }
+}
+
+fn write_csv_value(out: &mut String, value: &libsql_core::Value) {
+    match value {
+        libsql_core::Value::Null => {}
+        libsql_core::Value::Integer(v) => {
+            let _ = write!(out, "{}", v);
+        }
+        libsql_core::Value::Real(v) => {
+            let _ = write!(out, "{:?}", v);
+        }
+        libsql_core::Value::Text(v) => write_csv_text(out, v),
+        libsql_core::Value::Blob(v) => write_hex(out, v),
+    }
+}
+
+fn write_json_string(out: &mut String, text: &str) {
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn write_json_value(out: &mut String, value: &libsql_core::Value) {
+    match value {
+        libsql_core::Value::Null => out.push_str("null"),
+        libsql_core::Value::Integer(v) => {
+            let _ = write!(out, "{}", v);
+        }
+        libsql_core::Value::Real(v) if v.is_finite() => {
+            let _ = write!(out, "{:?}", v);
+        }
+        libsql_core::Value::Real(_) => out.push_str("null"),
+        libsql_core::Value::Text(v) => write_json_string(out, v),
+        libsql_core::Value::Blob(v) => {
+            out.push('"');
+            write_hex(out, v);
+            out.push('"');
+        }
+    }
+}
+
+fn export_rows<'py>(
+    py: Python<'py>,
+    cursor: &Cursor,
+    target: &Bound<'py, PyAny>,
+    format: ExportFormat,
+) -> PyResult<Bound<'py, PyAny>> {
+    let mut sink = ExportSink::open(target)?;
+    let columns = cursor.columns.lock().clone().unwrap_or_default();
+    let rows_arc = cursor.rows.clone();
+    let done_arc = cursor.done.clone();
+    let metrics = cursor.metrics.clone();
+    future_into_py(py, async move {
+        let mut buf = String::with_capacity(EXPORT_CHUNK_SIZE);
+        let mut count = 0u64;
+        if let ExportFormat::Csv { header: true } = format
+            && !columns.is_empty()
+        {
+            for (i, name) in columns.iter().enumerate() {
+                if i > 0 {
+                    buf.push(',');
+                }
+                write_csv_text(&mut buf, name);
+            }
+            buf.push_str("\r\n");
+        }
+        let keys: Vec<String> = columns
+            .iter()
+            .map(|name| {
+                let mut key = String::new();
+                write_json_string(&mut key, name);
+                key.push(':');
+                key
+            })
+            .collect();
+        let mut guard = rows_arc.lock().await;
+        if let Some(rs) = guard.as_mut() {
+            while let Some(row) = metrics.track(rs.next_row().await)? {
+                match format {
+                    ExportFormat::Csv { .. } => {
+                        for (i, value) in row.iter().enumerate() {
+                            if i > 0 {
+                                buf.push(',');
+                            }
+                            write_csv_value(&mut buf, value);
+                        }
+                        buf.push_str("\r\n");
+                    }
+                    ExportFormat::Jsonl => {
+                        buf.push('{');
+                        for (i, value) in row.iter().enumerate() {
+                            if i > 0 {
+                                buf.push(',');
+                            }
+                            if let Some(key) = keys.get(i) {
+                                buf.push_str(key);
+                            }
+                            write_json_value(&mut buf, value);
+                        }
+                        buf.push_str("}\n");
+                    }
+                }
+                count += 1;
+                if buf.len() >= EXPORT_CHUNK_SIZE {
+                    sink.write(&buf)?;
+                    buf.clear();
+                }
+            }
+        }
+        *guard = None;
+        done_arc.store(true, Ordering::SeqCst);
+        drop(guard);
+        metrics.add_rows(count);
+        sink.write(&buf)?;
+        sink.finish()?;
+        Ok(count)
+    })
+}
+
+#[cfg(feature = "parquet")]
+const PARQUET_BATCH_ROWS: usize = 65536;
+
+#[cfg(feature = "parquet")]
+fn value_type_name(value: &libsql_core::Value) -> &'static str {
+    match value {
+        libsql_core::Value::Null => "NULL",
+        libsql_core::Value::Integer(_) => "INTEGER",
+        libsql_core::Value::Real(_) => "REAL",
+        libsql_core::Value::Text(_) => "TEXT",
+        libsql_core::Value::Blob(_) => "BLOB",
+    }
+}
+
+/// The narrowest parquet type for a column's values in `rows`, or `None` if they are all NULL.
+#[cfg(feature = "parquet")]
+fn infer_parquet_type(rows: &[Vec<libsql_core::Value>], col: usize) -> Option<arrow_schema::DataType> {
+    let (mut ints, mut reals, mut texts, mut blobs) = (false, false, false, false);
+    for row in rows {
+        match &row[col] {
+            libsql_core::Value::Null => {}
+            libsql_core::Value::Integer(_) => ints = true,
+            libsql_core::Value::Real(_) => reals = true,
+            libsql_core::Value::Text(_) => texts = true,
+            libsql_core::Value::Blob(_) => blobs = true,
+        }
+    }
+    match (ints, reals, texts, blobs) {
+        (false, false, false, false) => None,
+        (_, true, false, false) => Some(arrow_schema::DataType::Float64),
+        (true, false, false, false) => Some(arrow_schema::DataType::Int64),
+        (false, false, false, true) => Some(arrow_schema::DataType::Binary),
+        _ => Some(arrow_schema::DataType::Utf8),
+    }
+}
+
+/// A type that holds the values of both `a` and `b`: integers next to reals become `DOUBLE`,
+/// and any other mix `STRING`.
+#[cfg(feature = "parquet")]
+fn widen_parquet_type(
+    a: Option<arrow_schema::DataType>,
+    b: Option<arrow_schema::DataType>,
+) -> Option<arrow_schema::DataType> {
+    use arrow_schema::DataType::{
+        Float64,
+        Int64,
+        Utf8,
+    };
+    match (a, b) {
+        (None, t) | (t, None) => t,
+        (Some(a), Some(b)) if a == b => Some(a),
+        (Some(Int64 | Float64), Some(Int64 | Float64)) => Some(Float64),
+        _ => Some(Utf8),
+    }
+}
+
+/// The schema for inferred column types; a column that has only held NULL so far is `STRING`.
+#[cfg(feature = "parquet")]
+fn parquet_schema(columns: &[String], types: &[Option<arrow_schema::DataType>]) -> arrow_schema::SchemaRef {
+    let fields: Vec<arrow_schema::Field> = columns
+        .iter()
+        .zip(types)
+        .map(|(name, t)| arrow_schema::Field::new(name, t.clone().unwrap_or(arrow_schema::DataType::Utf8), true))
+        .collect();
+    Arc::new(arrow_schema::Schema::new(fields))
+}
+
+/// The values of a batch read back from a parquet export, as rows.
+#[cfg(feature = "parquet")]
+fn record_batch_rows(batch: &arrow_array::RecordBatch) -> Vec<Vec<libsql_core::Value>> {
+    use arrow_array::{
+        Array,
+        cast::AsArray,
+        types::{
+            Float64Type,
+            Int64Type,
+        },
+    };
+    let mut rows = vec![Vec::with_capacity(batch.num_columns()); batch.num_rows()];
+    for array in batch.columns() {
+        for (i, row) in rows.iter_mut().enumerate() {
+            row.push(if array.is_null(i) {
+                libsql_core::Value::Null
+            } else {
+                match array.data_type() {
+                    arrow_schema::DataType::Int64 => libsql_core::Value::Integer(array.as_primitive::<Int64Type>().value(i)),
+                    arrow_schema::DataType::Float64 => libsql_core::Value::Real(array.as_primitive::<Float64Type>().value(i)),
+                    arrow_schema::DataType::Binary => libsql_core::Value::Blob(array.as_binary::<i32>().value(i).to_vec()),
+                    _ => libsql_core::Value::Text(array.as_string::<i32>().value(i).to_string()),
+                }
+            });
+        }
+    }
+    rows
+}
+
+/// A parquet export in progress: rows go to a file next to the target, which is renamed into
+/// place once everything is written.
+#[cfg(feature = "parquet")]
+struct ParquetPart {
+    writer: parquet::arrow::ArrowWriter<std::fs::File>,
+    schema: arrow_schema::SchemaRef,
+    path: std::path::PathBuf,
+}
+
+#[cfg(feature = "parquet")]
+impl ParquetPart {
+    fn create(
+        path: std::path::PathBuf,
+        schema: arrow_schema::SchemaRef,
+        props: &parquet::file::properties::WriterProperties,
+    ) -> PyResult<Self> {
+        let file = std::fs::File::create(&path)?;
+        let writer = parquet::arrow::ArrowWriter::try_new(file, schema.clone(), Some(props.clone())).map_err(to_py_err)?;
+        Ok(Self { writer, schema, path })
+    }
+
+    fn write(&mut self, rows: &[Vec<libsql_core::Value>]) -> PyResult<()> {
+        let arrays = self
+            .schema
+            .fields()
+            .iter()
+            .enumerate()
+            .map(|(i, field)| build_parquet_array(field, rows, i))
+            .collect::<PyResult<Vec<_>>>()?;
+        let record = arrow_array::RecordBatch::try_new(self.schema.clone(), arrays).map_err(to_py_err)?;
+        self.writer.write(&record).map_err(to_py_err)
+    }
+
+    /// Copies the rows written so far into `path` with the wider `schema`, for a batch whose
+    /// values don't fit the types chosen from the earlier ones, and continues there.
+    fn widen(
+        self,
+        path: std::path::PathBuf,
+        schema: arrow_schema::SchemaRef,
+        props: &parquet::file::properties::WriterProperties,
+    ) -> PyResult<Self> {
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+        self.writer.close().map_err(to_py_err)?;
+        let mut widened = Self::create(path, schema, props)?;
+        let reader = ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(&self.path)?)
+            .and_then(|b| b.build())
+            .map_err(to_py_err)?;
+        for batch in reader {
+            widened.write(&record_batch_rows(&batch.map_err(to_py_err)?))?;
+        }
+        std::fs::remove_file(&self.path)?;
+        Ok(widened)
+    }
+}
+
+/// `path` with `suffix` appended to its file name.
+#[cfg(feature = "parquet")]
+fn path_with_suffix(path: &std::path::Path, suffix: &str) -> std::path::PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    name.into()
+}
+
+#[cfg(feature = "parquet")]
+fn build_parquet_array(
+    field: &arrow_schema::Field,
+    rows: &[Vec<libsql_core::Value>],
+    col: usize,
+) -> PyResult<arrow_array::ArrayRef> {
+    use arrow_array::builder::{
+        BinaryBuilder,
+        Float64Builder,
+        Int64Builder,
+        StringBuilder,
+    };
+    let mismatch = |value: &libsql_core::Value| {
+        PyValueError::new_err(format!(
+            "column {}: cannot write {} value to {} parquet column",
+            field.name(),
+            value_type_name(value),
+            field.data_type()
+        ))
+    };
+    match field.data_type() {
+        arrow_schema::DataType::Int64 => {
+            let mut builder = Int64Builder::with_capacity(rows.len());
+            for row in rows {
+                match &row[col] {
+                    libsql_core::Value::Null => builder.append_null(),
+                    libsql_core::Value::Integer(v) => builder.append_value(*v),
+                    v => return Err(mismatch(v)),
+                }
+            }
+            Ok(Arc::new(builder.finish()))
+        }
+        arrow_schema::DataType::Float64 => {
+            let mut builder = Float64Builder::with_capacity(rows.len());
+            for row in rows {
+                match &row[col] {
+                    libsql_core::Value::Null => builder.append_null(),
+                    libsql_core::Value::Integer(v) => builder.append_value(*v as f64),
+                    libsql_core::Value::Real(v) => builder.append_value(*v),
+                    v => return Err(mismatch(v)),
+                }
+            }
+            Ok(Arc::new(builder.finish()))
+        }
+        arrow_schema::DataType::Binary => {
+            let mut builder = BinaryBuilder::new();
+            for row in rows {
+                match &row[col] {
+                    libsql_core::Value::Null => builder.append_null(),
+                    libsql_core::Value::Blob(v) => builder.append_value(v),
+                    v => return Err(mismatch(v)),
+                }
+            }
+            Ok(Arc::new(builder.finish()))
+        }
+        _ => {
+            let mut builder = StringBuilder::new();
+            let mut text = String::new();
+            for row in rows {
+                text.clear();
+                match &row[col] {
+                    libsql_core::Value::Null => {
+                        builder.append_null();
+                        continue;
+                    }
+                    libsql_core::Value::Text(v) => text.push_str(v),
+                    libsql_core::Value::Blob(v) => write_hex(&mut text, v),
+                    v => write_csv_value(&mut text, v),
+                }
+                builder.append_value(&text);
+            }
+            Ok(Arc::new(builder.finish()))
+        }
     }
+}
 
-    fn executescript<'py>(
-        &self,
-        py: Python<'py>,
-        script: String,
-    ) -> PyResult<Bound<'py, PyAny>> {
-        let cursor = self.cursor()?;
-        let py_cursor = cursor.into_pyobject(py)?.unbind();
-        Cursor::executescript(py_cursor, py, script)
+#[cfg(feature = "parquet")]
+fn export_parquet<'py>(
+    py: Python<'py>,
+    cursor: &Cursor,
+    path: std::path::PathBuf,
+    compression: &str,
+) -> PyResult<Bound<'py, PyAny>> {
+    use parquet::{
+        basic::Compression,
+        file::properties::WriterProperties,
+    };
+    let compression = match compression.to_ascii_lowercase().as_str() {
+        "snappy" => Compression::SNAPPY,
+        "none" => Compression::UNCOMPRESSED,
+        _ => return Err(PyValueError::new_err(format!("unsupported compression: {}", compression))),
+    };
+    let columns = cursor.columns.lock().clone().unwrap_or_default();
+    if columns.is_empty() {
+        return Err(PyValueError::new_err("no result set to export"));
     }
+    let rows_arc = cursor.rows.clone();
+    let done_arc = cursor.done.clone();
+    let metrics = cursor.metrics.clone();
+    future_into_py(py, async move {
+        let props = WriterProperties::builder().set_compression(compression).build();
+        // Rows go to one of two part files, switching on every widening, so the target only
+        // ever holds a complete file.
+        let parts = [path_with_suffix(&path, ".part"), path_with_suffix(&path, ".part2")];
+        let mut guard = rows_arc.lock().await;
+        let mut part: Option<ParquetPart> = None;
+        let mut types = vec![None; columns.len()];
+        let mut count = 0u64;
+        let written: PyResult<()> = async {
+            loop {
+                let mut batch = Vec::new();
+                if let Some(rs) = guard.as_mut() {
+                    while batch.len() < PARQUET_BATCH_ROWS {
+                        match metrics.track(rs.next_row().await)? {
+                            Some(row) => batch.push(row),
+                            None => break,
+                        }
+                    }
+                }
+                let widened: Vec<_> = types
+                    .iter()
+                    .enumerate()
+                    .map(|(i, t)| widen_parquet_type(t.clone(), infer_parquet_type(&batch, i)))
+                    .collect();
+                let schema = parquet_schema(&columns, &widened);
+                part = Some(match part.take() {
+                    None => ParquetPart::create(parts[0].clone(), schema, &props)?,
+                    Some(p) if p.schema != schema => {
+                        let next = if p.path == parts[0] { &parts[1] } else { &parts[0] };
+                        p.widen(next.clone(), schema, &props)?
+                    }
+                    Some(p) => p,
+                });
+                types = widened;
+                let full = batch.len() == PARQUET_BATCH_ROWS;
+                if let Some(p) = part.as_mut()
+                    && !batch.is_empty()
+                {
+                    p.write(&batch)?;
+                    count += batch.len() as u64;
+                }
+                if !full {
+                    break;
+                }
+            }
+            if let Some(p) = part.take() {
+                p.writer.close().map_err(to_py_err)?;
+                std::fs::rename(&p.path, &path)?;
+            }
+            Ok(())
+        }
+        .await;
+        if written.is_err() {
+            drop(part);
+            for p in &parts {
+                let _ = std::fs::remove_file(p);
+            }
+        }
+        written?;
+        *guard = None;
+        done_arc.store(true, Ordering::SeqCst);
+        drop(guard);
+        metrics.add_rows(count);
+        Ok(count)
+    })
+}
 
-    #[getter]
-    fn isolation_level(&self) -> Option<String> {
-        self.isolation_level.clone()
+fn warn_unclosed(py: Python<'_>, message: &str) {
+    let res = std::ffi::CString::new(message).map_err(PyErr::from).and_then(|message| {
+        PyErr::warn(
+            py,
+            &py.get_type::<pyo3::exceptions::PyResourceWarning>(),
+            &message,
+            1,
+        )
+    });
+    if let Err(e) = res {
+        e.write_unraisable(py, None);
     }
+}
 
-    #[getter]
-    fn in_transaction(&self) -> PyResult<bool> {
-        let guard = self.conn.lock();
-        if let Some(conn) = guard.as_ref() {
-            Ok(!conn.is_autocommit() || self.autocommit == 0)
-        } else {
-            Ok(false)
+fn close_in_background(conn: libsql_core::Connection) {
+    pyo3_async_runtimes::tokio::get_runtime().spawn(async move {
+        if !conn.is_autocommit() {
+            let _ = conn.execute("ROLLBACK", ()).await;
         }
+        drop(conn);
+    });
+}
+
+fn release_rows_in_background(rows: RowsSlot) {
+    pyo3_async_runtimes::tokio::get_runtime().spawn(async move {
+        rows.lock().await.take();
+    });
+}
+
+fn drop_unclosed_connection(py: Python<'_>, conn: &Arc<SyncMutex<Option<libsql_core::Connection>>>) {
+    if Arc::strong_count(conn) == 1
+        && let Some(conn) = conn.lock().take()
+    {
+        warn_unclosed(py, "unclosed aiolibsql.Connection");
+        close_in_background(conn);
     }
+}
 
-    fn __aenter__<'py>(slf: Py<Self>, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
-        future_into_py(py, async move { Ok(slf) })
+impl Drop for Connection {
+    fn drop(&mut self) {
+        Python::with_gil(|py| drop_unclosed_connection(py, &self.conn));
     }
+}
 
-    #[pyo3(signature = (exc_type=None, _exc_val=None, _exc_tb=None))]
-    fn __aexit__<'py>(
-        &self,
-        py: Python<'py>,
-        exc_type: Option<PyObject>,
-        _exc_val: Option<PyObject>,
-        _exc_tb: Option<PyObject>,
-    ) -> PyResult<Bound<'py, PyAny>> {
-        let conn_arc = self.conn.clone();
-        let is_error = exc_type.is_some();
-        future_into_py(py, async move {
-            let conn_opt = {
-                let guard = conn_arc.lock();
-                guard.as_ref().cloned()
-            };
-            if let Some(conn) = conn_opt {
-                if !conn.is_autocommit() {
-                    if is_error {
-                        let _ = conn.execute("ROLLBACK", ()).await;
-                    } else {
-                        let _ = conn.execute("COMMIT", ()).await;
-                    }
-                }
+impl Drop for Cursor {
+    fn drop(&mut self) {
+        Python::with_gil(|py| {
+            if Arc::strong_count(&self.rows) == 1
+                && self.rows.try_lock().map_or(true, |rows| rows.is_some())
+            {
+                warn_unclosed(py, "unclosed aiolibsql.Cursor with pending rows");
+                release_rows_in_background(self.rows.clone());
             }
-            Ok(false)
-        })
+            drop_unclosed_connection(py, &self.conn);
+        });
     }
 }
 
-#[pyclass]
+#[pyclass(subclass)]
 #[derive(Clone)]
 pub struct Cursor {
+    connection: Arc<Py<Connection>>,
     #[pyo3(get, set)]
     arraysize: usize,
+    #[pyo3(get, set)]
+    prefetch: usize,
+    #[pyo3(get, set)]
+    max_rows: Option<u64>,
+    #[pyo3(get, set)]
+    max_result_bytes: Option<u64>,
     conn: Arc<SyncMutex<Option<libsql_core::Connection>>>,
-    rows: Arc<AsyncMutex<Option<libsql_core::Rows>>>,
+    rows: RowsSlot,
     columns: Arc<SyncMutex<Option<Vec<String>>>>,
+    column_meta: Arc<SyncMutex<Vec<ColumnMeta>>>,
+    parameters: Arc<SyncMutex<Vec<Option<String>>>>,
+    column_kinds: Arc<SyncMutex<Vec<ColumnKind>>>,
     rowcount: Arc<AtomicI64>,
-    last_insert_rowid: Arc<AtomicI64>,
+    last_insert_rowid: Arc<SyncMutex<Option<i64>>>,
+    /// Replication index of the replica after the last write, see `ReplicaSync`.
+    write_index: Arc<SyncMutex<Option<u64>>>,
+    last_sql: LastSql,
+    cache: Option<ResultCacheRef>,
+    busy_retry: BusyRetry,
+    queries: QueryRegistryRef,
+    #[pyo3(get)]
+    query_id: Option<u64>,
     done: Arc<AtomicBool>,
     isolation_level: Option<String>,
     autocommit: i32,
+    remote: bool,
+    types: TypeConfig,
+    metrics: Arc<ConnectionMetrics>,
+    exec_lock: Arc<AsyncMutex<()>>,
+    owner: Owner,
+    failover: Option<Arc<Failover>>,
+    /// Remote connection to the primary of an embedded replica, for `execute(primary=True)`.
+    primary: Option<libsql_core::Connection>,
+    replica: Option<ReplicaSync>,
 }
 
-#[pymethods]
 impl Cursor {
-    fn close<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
-        let r = self.rows.clone();
-        future_into_py(py, async move {
-            r.lock().await.take();
-            Ok(())
-        })
+    /// The next row as a tuple, or `None` once the result set is exhausted.
+    fn next_row(&self) -> impl Future<Output = PyResult<PyObject>> + Send + 'static {
+        let rows_arc = self.rows.clone();
+        let kinds_arc = self.column_kinds.clone();
+        let types = self.types.clone();
+        let metrics = self.metrics.clone();
+        let prefetch = self.prefetch;
+        async move {
+            let mut guard = rows_arc.lock().await;
+            if let Some(rs) = guard.as_mut() {
+                if let Some(vals) = metrics.track(rs.next_row().await)? {
+                    metrics.add_rows(1);
+                    drop(guard);
+                    prefetch_rows(&rows_arc, prefetch);
+                    return Python::with_gil(|py| {
+                        let kinds = kinds_arc.lock();
+                        let mut py_vals = Vec::with_capacity(vals.len());
+                        for (i, v) in vals.into_iter().enumerate() {
+                            py_vals.push(convert_column(py, v, kinds.get(i), &types)?);
+                        }
+                        Ok(PyTuple::new(py, py_vals)?.unbind().into_any())
+                    });
+                }
+            }
+            *guard = None;
+            Python::with_gil(|py| Ok(py.None()))
+        }
     }
 
-    #[pyo3(signature = (sql, parameters=None))]
-    fn execute<'py>(
+    /// Every remaining row as a list of tuples.
+    fn remaining_rows(&self) -> impl Future<Output = PyResult<PyObject>> + Send + 'static {
+        let rows_arc = self.rows.clone();
+        let kinds_arc = self.column_kinds.clone();
+        let types = self.types.clone();
+        let metrics = self.metrics.clone();
+        async move {
+            let mut guard = rows_arc.lock().await;
+            let mut data = Vec::new();
+            if let Some(rs) = guard.as_mut() {
+                data = metrics.track(rs.take_rows(None).await)?;
+            }
+            *guard = None;
+            drop(guard);
+            metrics.add_rows(data.len() as u64);
+            let kinds = kinds_arc.lock().clone();
+            rows_to_list(data, &kinds, &types).await
+        }
+    }
+
+    /// `executemany()`; with `atomic` the parameter sets of a write also share one transaction
+    /// in autocommit mode.
+    fn run_many<'py>(
         slf: Py<Self>,
         py: Python<'py>,
         sql: String,
-        parameters: Option<ListOrTuple>,
+        parameters: Option<Bound<'py, PyAny>>,
+        progress: Option<PyObject>,
+        progress_every: u64,
+        atomic: bool,
     ) -> PyResult<Bound<'py, PyAny>> {
-        let params = extract_parameters(py, parameters)?;
-        let (conn, rows, cols, rc, rid, ac, isl, dn) = {
+        slf.borrow(py).owner.check()?;
+        check_single_statement(&sql)?;
+        let progress_every = progress_every.max(1);
+        let progress_total = parameters.as_ref().and_then(|p| p.len().ok());
+        let (types, exec_lock, remote) = {
+            let b = slf.borrow(py);
+            (b.types.clone(), b.exec_lock.clone(), b.remote)
+        };
+        let (conn, rows, cols, kinds, rc, rid, ac, isl, dn, metrics) = {
             let b = slf.borrow(py);
             (
                 b.conn.clone(),
                 b.rows.clone(),
                 b.columns.clone(),
+                b.column_kinds.clone(),
                 b.rowcount.clone(),
                 b.last_insert_rowid.clone(),
                 b.autocommit,
                 b.isolation_level.clone(),
                 b.done.clone(),
+                b.metrics.clone(),
             )
         };
-        future_into_py(py, async move {
-            let conn_opt = {
-                let guard = conn.lock();
-                guard.as_ref().cloned()
+        let (decls, param_names, last_sql, limits) = {
+            let b = slf.borrow(py);
+            let limits = ResultLimits {
+                max_rows: b.max_rows,
+                max_bytes: b.max_result_bytes,
             };
-            if let Some(c) = conn_opt {
-                let is_tx = (!determine_autocommit(ac, &isl)) && stmt_is_dml(&sql);
-                let guard = TxGuard::new(c, is_tx).await?;
+            (b.column_meta.clone(), b.parameters.clone(), b.last_sql.clone(), limits)
+        };
+        let cache = slf.borrow(py).cache.clone();
+        
+        let parameters_iter: Option<PyObject> = parameters.map(|p| {
+            p.try_iter().unwrap().into_pyobject(p.py()).unwrap().into_any().unbind()
+        });
+        let ticket = QueryTicket::register(&slf.borrow(py).queries, &sql)?;
+        slf.borrow_mut(py).query_id = Some(ticket.id);
+        
+        let (replica, write_index) = {
+            let b = slf.borrow(py);
+            (b.replica.clone(), b.write_index.clone())
+        };
+        let wrote = stmt_writes(&sql);
+        let failover = slf.borrow(py).failover.clone();
+        future_into_py(py, async move {
+            let result: PyResult<()> = async {
+                let exec = exec_lock.lock_owned().await;
+                ticket.start()?;
+                let conn_opt = {
+                    let guard = conn.lock();
+                    guard.as_ref().cloned()
+                };
+                *rid.lock() = None;
+                if let Some(c) = conn_opt {
+                    let mut slot = rows.lock().await;
+                    *slot = None;
+                    *cols.lock() = None;
+                    kinds.lock().clear();
+                    dn.store(true, Ordering::SeqCst);
                 
-                let stmt_res = guard.conn().prepare(&sql).await;
-                if let Err(e) = stmt_res {
-                    guard.rollback().await?;
-                    return Err(to_py_err(e));
-                }
-                let stmt = stmt_res.unwrap();
-                let col_count = stmt.column_count();
+                    let is_tx = (atomic || !determine_autocommit(ac, &isl)) && stmt_is_dml(&sql);
+                    let txguard = TxGuard::new(c, is_tx, isl.as_deref(), Some(exec)).await?;
+                    let seen = cache_mark(&cache);
                 
-                let mut c_names = Vec::new();
-                for c in stmt.columns() {
-                    c_names.push(c.name().to_string());
-                }
-                *cols.lock() = if col_count > 0 { Some(c_names) } else { None };
+                    let stmt_res =
+                        retry_lost_stream(txguard.conn(), remote, || txguard.conn().prepare(&sql)).await;
+                    if let Err(e) = stmt_res {
+                        txguard.rollback().await?;
+                        return Err(sql_err(e, &sql));
+                    }
+                    let stmt = stmt_res.unwrap();
+                    *param_names.lock() = statement_parameters(&stmt);
+                    let is_insert = stmt_is_insert(&sql);
+                    let mut total_changes = 0;
+                    let mut done = 0u64;
+                    let mut last_id = None;
+                    let mut last_values = Vec::new();
+                    // Rows from a RETURNING clause are collected across all parameter sets.
+                    let mut returned = (stmt.column_count() > 0 && stmt_is_dml(&sql))
+                        .then(|| ResultSet::collected(limits, types.text_errors));
                 
-                if col_count > 0 {
-                    let rs = match stmt.query(params).await {
-                        Ok(r) => r,
-                        Err(e) => {
-                            guard.rollback().await?;
-                            return Err(to_py_err(e));
+                    if let Some(iterator_obj) = parameters_iter {
+                        loop {
+                            let chunk_res: PyResult<Vec<libsql_core::params::Params>> = Python::with_gil(|py| {
+                                let mut chunk = Vec::with_capacity(100);
+                                let iter = iterator_obj.bind(py);
+                                for _ in 0..100 {
+                                    match iter.call_method0("__next__") {
+                                        Ok(item) => {
+                                            chunk.push(extract_parameters(py, Some(ListOrTuple::extract_bound(&item)?), &types)?);
+                                        }
+                                        Err(e) => {
+                                            if e.is_instance_of::<pyo3::exceptions::PyStopIteration>(py) {
+                                                break;
+                                            }
+                                            return Err(e);
+                                        }
+                                    }
+                                }
+                                Ok(chunk)
+                            });
+                        
+                            let chunk = match chunk_res {
+                                Ok(c) => c,
+                                Err(e) => {
+                                    txguard.rollback().await?;
+                                    return Err(e);
+                                }
+                            };
+                        
+                            if chunk.is_empty() { break; }
+                            for p in chunk {
+                                if !remote && let Err(e) = check_parameter_count(stmt.parameter_count(), &p) {
+                                    txguard.rollback().await?;
+                                    return Err(e);
+                                }
+                                last_values = positional_values(&p);
+                                let step = match returned.as_mut() {
+                                    Some(rs) => match stmt.query(p).await {
+                                        Ok(r) => rs.append(r).await,
+                                        Err(e) => Err(sql_err(e, &sql)),
+                                    },
+                                    None => stmt.execute(p).await.map(|_| ()).map_err(|e| sql_err(e, &sql)),
+                                };
+                                stmt.reset();
+                                if let Err(e) = step {
+                                    txguard.rollback().await?;
+                                    return Err(e);
+                                }
+                                total_changes += txguard.conn().changes() as i64;
+                                if is_insert {
+                                    last_id = Some(txguard.conn().last_insert_rowid());
+                                }
+                                metrics.add_statements(1);
+                                done += 1;
+                                if let Some(progress) = &progress
+                                    && done % progress_every == 0
+                                    && let Err(e) = report_progress(progress, done, progress_total)
+                                {
+                                    txguard.rollback().await?;
+                                    return Err(e);
+                                }
+                            }
                         }
-                    };
-                    *rows.lock().await = Some(rs);
-                    dn.store(false, Ordering::SeqCst);
-                } else {
-                    if let Err(e) = stmt.execute(params).await {
-                        guard.rollback().await?;
-                        return Err(to_py_err(e));
                     }
-                    *rows.lock().await = None;
-                    dn.store(true, Ordering::SeqCst);
+                    if let Some(progress) = &progress
+                        && done % progress_every != 0
+                        && let Err(e) = report_progress(progress, done, progress_total)
+                    {
+                        txguard.rollback().await?;
+                        return Err(e);
+                    }
+                    txguard.commit().await?;
+                    finish_write(&cache, &sql, seen);
+                    rc.store(total_changes, Ordering::SeqCst);
+                    *rid.lock() = last_id;
+                    *last_sql.lock() = Some((sql.clone(), last_values));
+                    if let Some(rs) = returned {
+                        *cols.lock() = Some(stmt.columns().iter().map(|c| description_name(c.name(), &types)).collect());
+                        *decls.lock() = ColumnMeta::for_statement(&stmt);
+                        *kinds.lock() = ColumnKind::for_statement(&stmt, &types);
+                        *slot = Some(rs);
+                        dn.store(false, Ordering::SeqCst);
+                    }
+                } else {
+                    return Err(PyValueError::new_err("closed"));
                 }
-                
-                rc.store(guard.conn().changes() as i64, Ordering::SeqCst);
-                rid.store(guard.conn().last_insert_rowid(), Ordering::SeqCst);
-                guard.commit().await?;
+                Ok(())
             }
+            .await;
+            if let Some(failover) = &failover {
+                failover.observe(result.as_ref().err(), &metrics).await;
+            }
+            metrics.track(result)?;
+            *write_index.lock() = replica
+                .as_ref()
+                .filter(|r| wrote && r.remote_writes)
+                .and_then(ReplicaSync::position);
             Ok(slf)
         })
     }
+}
 
-    #[pyo3(signature = (sql, parameters=None))]
-    fn executemany<'py>(
+#[pymethods]
+impl Cursor {
+    #[new]
+    fn new(connection: &Bound<'_, Connection>) -> Self {
+        connection.borrow().new_cursor(connection.clone().unbind())
+    }
+
+    #[getter]
+    fn connection(&self, py: Python<'_>) -> Py<Connection> {
+        self.connection.clone_ref(py)
+    }
+
+    fn close<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
+        let r = self.rows.clone();
+        future_into_py(py, async move {
+            r.lock().await.take();
+            Ok(())
+        })
+    }
+
+    /// With `primary=True` a read on an embedded replica is sent to the primary instead of the
+    /// local file, so it sees every write that has been committed there. `min_replication_index`
+    /// syncs the replica first unless it has already reached that index.
+    #[pyo3(signature = (sql, parameters=None, *, buffered=false, primary=false, min_replication_index=None))]
+    fn execute<'py>(
         slf: Py<Self>,
         py: Python<'py>,
         sql: String,
-        parameters: Option<Bound<'py, PyAny>>,
+        parameters: Option<ListOrTuple>,
+        buffered: bool,
+        primary: bool,
+        min_replication_index: Option<u64>,
     ) -> PyResult<Bound<'py, PyAny>> {
-        let (conn, rows, cols, rc, rid, ac, isl, dn) = {
+        slf.borrow(py).owner.check()?;
+        let (types, exec_lock, remote) = {
+            let b = slf.borrow(py);
+            (b.types.clone(), b.exec_lock.clone(), b.remote)
+        };
+        let primary_conn = if primary {
+            let b = slf.borrow(py);
+            if !b.remote && b.primary.is_none() {
+                return Err(PyValueError::new_err(
+                    "primary=True needs a remote connection or an embedded replica with remote writes",
+                ));
+            }
+            b.primary.clone()
+        } else {
+            None
+        };
+        let (replica, write_index) = {
+            let b = slf.borrow(py);
+            if min_replication_index.is_some() && !b.remote && b.replica.is_none() {
+                return Err(PyValueError::new_err(
+                    "min_replication_index needs a remote connection or an embedded replica",
+                ));
+            }
+            (b.replica.clone(), b.write_index.clone())
+        };
+        let wrote = stmt_writes(&sql);
+        check_single_statement(&sql)?;
+        if let Some((sql, sets)) = split_sequence_parameters(py, &sql, parameters.as_ref())? {
+            return Cursor::run_many(slf, py, sql, Some(sets.into_any()), None, 100, true);
+        }
+        let (sql, parameters) = expand_sequence_parameters(py, sql, parameters)?;
+        let params = extract_parameters(py, parameters, &types)?;
+        let (conn, rows, cols, kinds, rc, rid, ac, isl, dn, metrics) = {
             let b = slf.borrow(py);
             (
                 b.conn.clone(),
                 b.rows.clone(),
                 b.columns.clone(),
+                b.column_kinds.clone(),
                 b.rowcount.clone(),
                 b.last_insert_rowid.clone(),
                 b.autocommit,
                 b.isolation_level.clone(),
                 b.done.clone(),
+                b.metrics.clone(),
             )
         };
-        
-        let parameters_iter: Option<PyObject> = parameters.map(|p| {
-            p.try_iter().unwrap().into_pyobject(p.py()).unwrap().into_any().unbind()
-        });
-        
-        future_into_py(py, async move {
-            let conn_opt = {
-                let guard = conn.lock();
-                guard.as_ref().cloned()
+        let (decls, param_names, last_sql, prefetch, limits) = {
+            let b = slf.borrow(py);
+            let limits = ResultLimits {
+                max_rows: b.max_rows,
+                max_bytes: b.max_result_bytes,
             };
-            if let Some(c) = conn_opt {
-                *rows.lock().await = None;
-                *cols.lock() = None;
-                dn.store(true, Ordering::SeqCst);
-                
-                let is_tx = (!determine_autocommit(ac, &isl)) && stmt_is_dml(&sql);
-                let txguard = TxGuard::new(c, is_tx).await?;
+            (b.column_meta.clone(), b.parameters.clone(), b.last_sql.clone(), b.prefetch, limits)
+        };
+        let (cache, busy_retry) = {
+            let b = slf.borrow(py);
+            (b.cache.clone(), b.busy_retry)
+        };
+        let ticket = QueryTicket::register(&slf.borrow(py).queries, &sql)?;
+        slf.borrow_mut(py).query_id = Some(ticket.id);
+        let failover = slf.borrow(py).failover.clone();
+        future_into_py(py, async move {
+            if let (Some(index), Some(replica)) = (min_replication_index, &replica) {
+                replica.catch_up(index).await?;
+            }
+            let mut attempt = 0;
+            let result = loop {
+                let in_tx = in_transaction(&conn);
+                let res: PyResult<()> = async {
+                    let exec = exec_lock.clone().lock_owned().await;
+                    ticket.start()?;
+                    let conn_opt = {
+                        let guard = conn.lock();
+                        guard.as_ref().cloned()
+                    };
+                    // Inside a transaction the replica already sends every statement to the primary.
+                    let (conn_opt, remote) = match (conn_opt, &primary_conn) {
+                        (Some(c), Some(p)) if c.is_autocommit() && is_plain_read(&sql) => (Some(p.clone()), true),
+                        (c, _) => (c, remote),
+                    };
+                    *rid.lock() = None;
+                    // Held from here on so that the cursor's state only changes in one synchronous
+                    // step at the end; a cancelled execute leaves the previous result untouched.
+                    let mut slot = rows.lock().await;
+                    if let Some(c) = conn_opt {
+                        let is_tx = (!determine_autocommit(ac, &isl)) && stmt_is_dml(&sql);
+                        let guard = TxGuard::new(c, is_tx, isl.as_deref(), Some(exec)).await?;
+                        metrics.add_statements(1);
+                        let seen = cache_mark(&cache);
                 
-                let stmt_res = txguard.conn().prepare(&sql).await;
-                if let Err(e) = stmt_res {
-                    txguard.rollback().await?;
-                    return Err(to_py_err(e));
-                }
-                let stmt = stmt_res.unwrap();
-                let mut total_changes = 0;
-                let mut last_id = 0;
+                        let stmt_res =
+                            retry_lost_stream(guard.conn(), remote, || guard.conn().prepare(&sql)).await;
+                        if let Err(e) = stmt_res {
+                            guard.rollback().await?;
+                            return Err(sql_err(e, &sql));
+                        }
+                        let stmt = stmt_res.unwrap();
+                        if !remote && let Err(e) = check_parameter_count(stmt.parameter_count(), &params) {
+                            guard.rollback().await?;
+                            return Err(e);
+                        }
+                        let col_count = stmt.column_count();
+                        // INSERT/UPDATE/DELETE ... RETURNING only reports its changes once every row
+                        // has been stepped, so it is always read to the end.
+                        let returning = col_count > 0 && stmt_is_dml(&sql);
+                        let buffered = buffered || returning;
                 
-                if let Some(iterator_obj) = parameters_iter {
-                    loop {
-                        let chunk_res: PyResult<Vec<libsql_core::params::Params>> = Python::with_gil(|py| {
-                            let mut chunk = Vec::with_capacity(100);
-                            let iter = iterator_obj.bind(py);
-                            for _ in 0..100 {
-                                match iter.call_method0("__next__") {
-                                    Ok(item) => {
-                                        chunk.push(extract_parameters(py, Some(ListOrTuple::extract_bound(&item)?))?);
-                                    }
-                                    Err(e) => {
-                                        if e.is_instance_of::<pyo3::exceptions::PyStopIteration>(py) {
-                                            break;
-                                        }
-                                        return Err(e);
-                                    }
+                        let result_set = if col_count > 0 {
+                            let rs = match retry_lost_stream(guard.conn(), remote, || stmt.query(params.clone())).await {
+                                Ok(r) => r,
+                                Err(e) => {
+                                    guard.rollback().await?;
+                                    return Err(sql_err(e, &sql));
+                                }
+                            };
+                            let mut rs = ResultSet::new(rs, limits, types.text_errors);
+                            if buffered {
+                                rs.read_ahead(None).await;
+                                if let Some(e) = rs.error.take() {
+                                    guard.rollback().await?;
+                                    return Err(e);
                                 }
                             }
-                            Ok(chunk)
-                        });
-                        
-                        let chunk = match chunk_res {
-                            Ok(c) => c,
-                            Err(e) => {
-                                txguard.rollback().await?;
-                                return Err(e);
-                            }
-                        };
-                        
-                        if chunk.is_empty() { break; }
-                        for p in chunk {
-                            if let Err(e) = stmt.execute(p).await {
-                                txguard.rollback().await?;
-                                return Err(to_py_err(e));
+                            Some(rs)
+                        } else {
+                            if let Err(e) = retry_lost_stream(guard.conn(), remote, || stmt.execute(params.clone())).await {
+                                guard.rollback().await?;
+                                return Err(sql_err(e, &sql));
                             }
-                            total_changes += txguard.conn().changes() as i64;
-                            last_id = txguard.conn().last_insert_rowid();
+                            None
+                        };
+                
+                        let changes = if col_count > 0 && !returning { -1 } else { guard.conn().changes() as i64 };
+                        let last_id = ((col_count == 0 || returning) && stmt_is_insert(&sql))
+                            .then(|| guard.conn().last_insert_rowid());
+                        guard.commit().await?;
+                        finish_write(&cache, &sql, seen);
+
+                        *cols.lock() = (col_count > 0)
+                            .then(|| stmt.columns().iter().map(|c| description_name(c.name(), &types)).collect());
+                        *decls.lock() = ColumnMeta::for_statement(&stmt);
+                        *kinds.lock() = ColumnKind::for_statement(&stmt, &types);
+                        *param_names.lock() = statement_parameters(&stmt);
+                        *last_sql.lock() = Some((sql.clone(), positional_values(&params)));
+                        dn.store(result_set.is_none(), Ordering::SeqCst);
+                        *slot = result_set;
+                        rc.store(changes, Ordering::SeqCst);
+                        *rid.lock() = last_id;
+                        drop(slot);
+                        if col_count > 0 && !buffered {
+                            prefetch_rows(&rows, prefetch);
                         }
+                    } else {
+                        return Err(PyValueError::new_err("closed"));
                     }
+                    Ok(())
                 }
-                rc.store(total_changes, Ordering::SeqCst);
-                rid.store(last_id, Ordering::SeqCst);
-                txguard.commit().await?;
+                .await;
+                if let Err(e) = &res
+                    && busy_retry.backoff(e, &mut attempt, in_tx, &conn, &metrics).await
+                {
+                    continue;
+                }
+                break res;
+            };
+            if let Some(failover) = &failover {
+                failover.observe(result.as_ref().err(), &metrics).await;
             }
+            metrics.track(result)?;
+            *write_index.lock() = replica
+                .as_ref()
+                .filter(|r| wrote && r.remote_writes)
+                .and_then(ReplicaSync::position);
             Ok(slf)
         })
     }
 
+    /// `progress(done, total)` is called after every `progress_every` parameter sets and once
+    /// more at the end; `total` is `None` when `parameters` has no length.
+    #[pyo3(signature = (sql, parameters=None, *, progress=None, progress_every=100))]
+    fn executemany<'py>(
+        slf: Py<Self>,
+        py: Python<'py>,
+        sql: String,
+        parameters: Option<Bound<'py, PyAny>>,
+        progress: Option<PyObject>,
+        progress_every: u64,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        Cursor::run_many(slf, py, sql, parameters, progress, progress_every, false)
+    }
+
+    #[pyo3(signature = (script, *, commit=true))]
     fn executescript<'py>(
         slf: Py<Self>,
         py: Python<'py>,
         script: String,
+        commit: bool,
     ) -> PyResult<Bound<'py, PyAny>> {
-        let conn_arc = slf.borrow(py).conn.clone();
+        slf.borrow(py).owner.check()?;
+        let (conn_arc, metrics, exec_lock, remote, last_sql) = {
+            let b = slf.borrow(py);
+            (b.conn.clone(), b.metrics.clone(), b.exec_lock.clone(), b.remote, b.last_sql.clone())
+        };
+        let cache = slf.borrow(py).cache.clone();
+        let ticket = QueryTicket::register(&slf.borrow(py).queries, &script)?;
+        slf.borrow_mut(py).query_id = Some(ticket.id);
+        let (replica, write_index) = {
+            let b = slf.borrow(py);
+            (b.replica.clone(), b.write_index.clone())
+        };
+        let failover = slf.borrow(py).failover.clone();
         future_into_py(py, async move {
-            let conn_opt = {
-                let guard = conn_arc.lock();
-                guard.as_ref().cloned()
-            };
-            if let Some(conn) = conn_opt {
-                conn.execute_batch(&script).await.map_err(to_py_err)?;
+            let result: PyResult<()> = async {
+                let _exec = exec_lock.lock().await;
+                ticket.start()?;
+                let conn_opt = {
+                    let guard = conn_arc.lock();
+                    guard.as_ref().cloned()
+                };
+                if let Some(conn) = conn_opt {
+                    if commit && !conn.is_autocommit() {
+                        conn.execute("COMMIT", ()).await.map_err(to_py_err)?;
+                    }
+                    metrics.add_statements(1);
+                    // Not replayed: a lost response may hide a batch that ran in part.
+                    let res = conn.execute_batch(&script).await;
+                    // A script's statements are not classified one by one, and it may have
+                    // written before failing.
+                    if let Some(cache) = &cache {
+                        cache.lock().entries.clear();
+                    }
+                    res.map_err(to_py_err)?;
+                    *last_sql.lock() = Some((script, Vec::new()));
+                } else {
+                    return Err(PyValueError::new_err("closed"));
+                }
+                Ok(())
             }
+            .await;
+            if let Some(failover) = &failover {
+                failover.observe(result.as_ref().err(), &metrics).await;
+            }
+            metrics.track(result)?;
+            *write_index.lock() = replica
+                .as_ref()
+                .filter(|r| r.remote_writes)
+                .and_then(ReplicaSync::position);
             Ok(slf)
         })
     }
 
+    #[getter]
+    fn parameter_count(&self) -> usize {
+        self.parameters.lock().len()
+    }
+
+    #[getter]
+    fn column_metadata<'py>(&self, py: Python<'py>) -> PyResult<Option<Vec<Bound<'py, PyDict>>>> {
+        if self.columns.lock().is_none() {
+            return Ok(None);
+        }
+        self.column_meta.lock().iter().map(|m| m.to_dict(py)).collect::<PyResult<_>>().map(Some)
+    }
+
+    #[getter]
+    fn parameter_names(&self) -> Vec<Option<String>> {
+        self.parameters.lock().clone()
+    }
+
+    #[getter]
+    fn last_sql(&self) -> Option<String> {
+        self.last_sql.lock().as_ref().map(|(sql, _)| sql.clone())
+    }
+
+    #[getter]
+    fn expanded_sql(&self) -> Option<String> {
+        self.last_sql.lock().as_ref().map(|(sql, values)| expand_sql(sql, values))
+    }
+
     #[getter]
     fn description(&self, py: Python<'_>) -> PyResult<Option<PyObject>> {
         let guard = self.columns.lock();
         if let Some(cols) = guard.as_ref() {
+            let decls = self.column_meta.lock();
             let mut elements = Vec::new();
-            for name in cols {
+            for (i, name) in cols.iter().enumerate() {
                 let e = (
                     name.clone(),
-                    py.None(),
+                    decls.get(i).and_then(|m| m.decl_type.clone()),
                     py.None(),
                     py.None(),
                     py.None(),
@@ -713,27 +6316,43 @@ impl Cursor {
     }
 
     fn fetchone<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
-        let rows_arc = self.rows.clone();
+        self.owner.check()?;
+        future_into_py(py, self.next_row())
+    }
+
+    #[pyo3(signature = (model, *, coerce=false))]
+    fn fetch_into<'py>(&self, py: Python<'py>, model: PyObject, coerce: bool) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
+        let row = self.next_row();
+        let columns = self.columns.clone();
         future_into_py(py, async move {
-            let mut guard = rows_arc.lock().await;
-            if let Some(rows) = guard.as_mut() {
-                if let Some(r) = rows.next().await.map_err(to_py_err)? {
-                    let cc = rows.column_count();
-                    let mut vals = Vec::with_capacity(cc as usize);
-                    for i in 0..cc {
-                        vals.push(r.get_value(i).map_err(to_py_err)?);
-                    }
-                    drop(guard);
-                    return Python::with_gil(|py| {
-                        let mut py_vals = Vec::with_capacity(cc as usize);
-                        for v in vals {
-                            py_vals.push(convert_value(py, v)?);
-                        }
-                        Ok(PyTuple::new(py, py_vals)?.unbind().into_any())
-                    });
+            let row = row.await?;
+            Python::with_gil(|py| {
+                if row.is_none(py) {
+                    return Ok(row);
                 }
-            }
-            Python::with_gil(|py| Ok(py.None()))
+                let mapper = ModelMapper::new(py, model, columns.lock().as_deref(), coerce)?;
+                mapper.build(py, row.bind(py))
+            })
+        })
+    }
+
+    #[pyo3(signature = (model, *, coerce=false))]
+    fn fetchall_into<'py>(&self, py: Python<'py>, model: PyObject, coerce: bool) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
+        let rows = self.remaining_rows();
+        let columns = self.columns.clone();
+        future_into_py(py, async move {
+            let rows = rows.await?;
+            Python::with_gil(|py| {
+                let mapper = ModelMapper::new(py, model, columns.lock().as_deref(), coerce)?;
+                let out = rows
+                    .bind(py)
+                    .try_iter()?
+                    .map(|row| mapper.build(py, &row?))
+                    .collect::<PyResult<Vec<_>>>()?;
+                Ok(PyList::new(py, out)?.unbind().into_any())
+            })
         })
     }
 
@@ -743,81 +6362,92 @@ impl Cursor {
         py: Python<'py>,
         size: Option<usize>,
     ) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
         let rows_arc = self.rows.clone();
+        let kinds_arc = self.column_kinds.clone();
+        let types = self.types.clone();
         let done_arc = self.done.clone();
+        let metrics = self.metrics.clone();
         let arraysize = self.arraysize;
+        let prefetch = self.prefetch;
         future_into_py(py, async move {
             let size = size.unwrap_or(arraysize);
             let mut guard = rows_arc.lock().await;
             let mut data = Vec::new();
-            if let Some(rows) = guard.as_mut() {
-                if !done_arc.load(Ordering::SeqCst) {
-                    let cc = rows.column_count();
-                    for _ in 0..size {
-                        match rows.next().await.map_err(to_py_err)? {
-                            Some(r) => {
-                                let mut row = Vec::with_capacity(cc as usize);
-                                for i in 0..cc {
-                                    row.push(r.get_value(i).map_err(to_py_err)?);
-                                }
-                                data.push(row);
-                            }
-                            None => {
-                                done_arc.store(true, Ordering::SeqCst);
-                                break;
-                            }
-                        }
-                    }
+            if let Some(rs) = guard.as_mut()
+                && !done_arc.load(Ordering::SeqCst)
+            {
+                data = metrics.track(rs.take_rows(Some(size)).await)?;
+                if rs.is_exhausted() {
+                    done_arc.store(true, Ordering::SeqCst);
                 }
             }
+            if done_arc.load(Ordering::SeqCst) {
+                *guard = None;
+            }
             drop(guard);
-            Python::with_gil(|py| {
-                let mut elements = Vec::with_capacity(data.len());
-                for row in data {
-                    let mut py_row = Vec::with_capacity(row.len());
-                    for v in row {
-                        py_row.push(convert_value(py, v)?);
-                    }
-                    elements.push(PyTuple::new(py, py_row)?.unbind().into_any());
-                }
-                Ok(PyList::new(py, elements)?.unbind().into_any())
-            })
+            prefetch_rows(&rows_arc, prefetch);
+            metrics.add_rows(data.len() as u64);
+            let kinds = kinds_arc.lock().clone();
+            rows_to_list(data, &kinds, &types).await
         })
     }
 
     fn fetchall<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
-        let rows_arc = self.rows.clone();
-        future_into_py(py, async move {
-            let mut guard = rows_arc.lock().await;
-            let mut data = Vec::new();
-            if let Some(rows) = guard.as_mut() {
-                let cc = rows.column_count();
-                while let Some(r) = rows.next().await.map_err(to_py_err)? {
-                    let mut row = Vec::with_capacity(cc as usize);
-                    for i in 0..cc {
-                        row.push(r.get_value(i).map_err(to_py_err)?);
-                    }
-                    data.push(row);
-                }
-            }
-            drop(guard);
-            Python::with_gil(|py| {
-                let mut elements = Vec::with_capacity(data.len());
-                for row in data {
-                    let mut py_row = Vec::with_capacity(row.len());
-                    for v in row {
-                        py_row.push(convert_value(py, v)?);
-                    }
-                    elements.push(PyTuple::new(py, py_row)?.unbind().into_any());
-                }
-                Ok(PyList::new(py, elements)?.unbind().into_any())
-            })
-        })
+        self.owner.check()?;
+        future_into_py(py, self.remaining_rows())
+    }
+
+    #[pyo3(signature = (target, *, header=true))]
+    fn to_csv<'py>(
+        &self,
+        py: Python<'py>,
+        target: Bound<'py, PyAny>,
+        header: bool,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
+        export_rows(py, self, &target, ExportFormat::Csv { header })
+    }
+
+    fn to_jsonl<'py>(
+        &self,
+        py: Python<'py>,
+        target: Bound<'py, PyAny>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
+        export_rows(py, self, &target, ExportFormat::Jsonl)
+    }
+
+    #[pyo3(signature = (path, *, compression="snappy".to_string()))]
+    fn to_parquet<'py>(
+        &self,
+        py: Python<'py>,
+        path: std::path::PathBuf,
+        compression: String,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
+        #[cfg(feature = "parquet")]
+        {
+            export_parquet(py, self, path, &compression)
+        }
+        #[cfg(not(feature = "parquet"))]
+        {
+            let _ = (py, path, compression);
+            Err(pyo3::exceptions::PyNotImplementedError::new_err(
+                "aiolibsql was built without parquet support",
+            ))
+        }
     }
 
     #[getter]
-    fn lastrowid(&self) -> i64 {
-        self.last_insert_rowid.load(Ordering::SeqCst)
+    fn lastrowid(&self) -> Option<i64> {
+        *self.last_insert_rowid.lock()
+    }
+
+    /// Replication index an embedded replica reached after this cursor's last write, or `None`.
+    #[getter]
+    fn replication_index(&self) -> Option<u64> {
+        *self.write_index.lock()
     }
 
     #[getter]
@@ -830,32 +6460,36 @@ impl Cursor {
     }
 
     fn __anext__<'py>(&self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyAny>>> {
+        self.owner.check()?;
         let rows_arc = self.rows.clone();
+        let kinds_arc = self.column_kinds.clone();
+        let types = self.types.clone();
+        let metrics = self.metrics.clone();
+        let prefetch = self.prefetch;
         future_into_py(py, async move {
             let mut guard = rows_arc.lock().await;
-            if let Some(rows) = guard.as_mut() {
-                if let Some(r) = rows.next().await.map_err(to_py_err)? {
-                    let cc = rows.column_count();
-                    let mut vals = Vec::with_capacity(cc as usize);
-                    for i in 0..cc {
-                        vals.push(r.get_value(i).map_err(to_py_err)?);
-                    }
+            if let Some(rs) = guard.as_mut() {
+                if let Some(vals) = metrics.track(rs.next_row().await)? {
+                    metrics.add_rows(1);
                     drop(guard);
+                    prefetch_rows(&rows_arc, prefetch);
                     return Python::with_gil(|py| {
-                        let mut py_vals = Vec::with_capacity(cc as usize);
-                        for v in vals {
-                            py_vals.push(convert_value(py, v)?);
+                        let kinds = kinds_arc.lock();
+                        let mut py_vals = Vec::with_capacity(vals.len());
+                        for (i, v) in vals.into_iter().enumerate() {
+                            py_vals.push(convert_column(py, v, kinds.get(i), &types)?);
                         }
                         PyTuple::new(py, py_vals).map(|t| t.into_any().unbind())
                     });
                 }
             }
+            *guard = None;
             Err(pyo3::exceptions::PyStopAsyncIteration::new_err("done"))
         }).map(Some)
     }
 }
 
-#[pyclass]
+#[pyclass(weakref)]
 pub struct ConnectionPool {
     #[allow(dead_code)]
     db: Arc<libsql_core::Database>,
@@ -865,9 +6499,46 @@ pub struct ConnectionPool {
     reader_sem: Arc<Semaphore>,
     writer_sem: Arc<Semaphore>,
     pool_size: usize,
+    owner: Owner,
 }
 
 impl ConnectionPool {
+    /// Closes the pooled connections. With `drain`, statements holding a connection are waited
+    /// for, until `deadline` if given, and later calls fail with `closed`; the writer's open
+    /// transaction, if any, is rolled back.
+    fn shutdown(&self, drain: bool, deadline: Option<Instant>) -> impl Future<Output = PyResult<()>> + Send + 'static {
+        let writer = self.writer.clone();
+        let readers = self.readers.clone();
+        let (writer_sem, reader_sem) = (self.writer_sem.clone(), self.reader_sem.clone());
+        let reader_permits = (readers.len() * 2) as u32;
+        async move {
+            if drain {
+                let drained = async {
+                    let _writer = writer_sem.acquire_many(1).await;
+                    let _readers = reader_sem.acquire_many(reader_permits).await;
+                };
+                match deadline {
+                    Some(deadline) => {
+                        let _ = tokio::time::timeout_at(deadline.into(), drained).await;
+                    }
+                    None => drained.await,
+                }
+                writer_sem.close();
+                reader_sem.close();
+            }
+            let conn = writer.lock().take();
+            for r in readers {
+                r.lock().take();
+            }
+            if let Some(conn) = conn
+                && !conn.is_autocommit()
+            {
+                conn.execute("ROLLBACK", ()).await.map_err(to_py_err)?;
+            }
+            Ok(())
+        }
+    }
+
     fn _get_reader(&self) -> Arc<SyncMutex<Option<libsql_core::Connection>>> {
         for r in &self.readers {
             if let Some(guard) = r.try_lock() {
@@ -900,14 +6571,18 @@ impl ConnectionPool {
         sql: String,
         parameters: Option<ListOrTuple>,
     ) -> PyResult<Bound<'py, PyAny>> {
-        let params = extract_parameters(py, parameters)?;
+        self.owner.check()?;
+        let owner = self.owner;
+        check_single_statement(&sql)?;
+        let (sql, parameters) = expand_sequence_parameters(py, sql, parameters)?;
+        let params = extract_parameters(py, parameters, &TypeConfig::default())?;
         let is_read = stmt_is_read(&sql);
 
         if is_read {
             let reader_arc = self._get_reader();
             let sem = self.reader_sem.clone();
             future_into_py(py, async move {
-                let _permit = sem.acquire().await.map_err(|e| PyValueError::new_err(e.to_string()))?;
+                let _permit = sem.acquire().await.map_err(|_| PyValueError::new_err("closed"))?;
                 let c_opt = { let guard = reader_arc.lock(); guard.as_ref().cloned() };
                 
                 if let Some(conn) = c_opt {
@@ -915,7 +6590,7 @@ impl ConnectionPool {
                     let col_count = stmt.column_count();
                     let mut desc_cols = Vec::new();
                     for c in stmt.columns() {
-                        desc_cols.push(c.name().to_string());
+                        desc_cols.push((c.name().to_string(), c.decl_type().map(str::to_string)));
                     }
                     if col_count > 0 {
                         let rows = stmt.query(params).await.map_err(to_py_err)?;
@@ -923,10 +6598,11 @@ impl ConnectionPool {
                         Ok(PoolCursor {
                             rows: Arc::new(AsyncMutex::new(Some(rows))),
                             columns: Arc::new(SyncMutex::new(desc)),
-                            rowcount: conn.changes() as i64,
-                            lastrowid: conn.last_insert_rowid(),
+                            rowcount: -1,
+                            lastrowid: None,
                             done: Arc::new(AtomicBool::new(false)),
                             arraysize: 1,
+                            owner,
                         })
                     } else {
                         stmt.execute(params).await.map_err(to_py_err)?;
@@ -934,9 +6610,10 @@ impl ConnectionPool {
                             rows: Arc::new(AsyncMutex::new(None)),
                             columns: Arc::new(SyncMutex::new(None)),
                             rowcount: conn.changes() as i64,
-                            lastrowid: conn.last_insert_rowid(),
+                            lastrowid: stmt_is_insert(&sql).then(|| conn.last_insert_rowid()),
                             done: Arc::new(AtomicBool::new(true)),
                             arraysize: 1,
+                            owner,
                         })
                     }
                 } else {
@@ -947,7 +6624,7 @@ impl ConnectionPool {
             let writer_arc = self.writer.clone();
             let sem = self.writer_sem.clone();
             future_into_py(py, async move {
-                let _permit = sem.acquire().await.map_err(|e| PyValueError::new_err(e.to_string()))?;
+                let _permit = sem.acquire().await.map_err(|_| PyValueError::new_err("closed"))?;
                 let c_opt = { let guard = writer_arc.lock(); guard.as_ref().cloned() };
                 
                 if let Some(conn) = c_opt {
@@ -955,7 +6632,7 @@ impl ConnectionPool {
                     let col_count = stmt.column_count();
                     let mut desc_cols = Vec::new();
                     for c in stmt.columns() {
-                        desc_cols.push(c.name().to_string());
+                        desc_cols.push((c.name().to_string(), c.decl_type().map(str::to_string)));
                     }
                     if col_count > 0 {
                         let rows = stmt.query(params).await.map_err(to_py_err)?;
@@ -963,10 +6640,11 @@ impl ConnectionPool {
                         Ok(PoolCursor {
                             rows: Arc::new(AsyncMutex::new(Some(rows))),
                             columns: Arc::new(SyncMutex::new(desc)),
-                            rowcount: conn.changes() as i64,
-                            lastrowid: conn.last_insert_rowid(),
+                            rowcount: -1,
+                            lastrowid: None,
                             done: Arc::new(AtomicBool::new(false)),
                             arraysize: 1,
+                            owner,
                         })
                     } else {
                         stmt.execute(params).await.map_err(to_py_err)?;
@@ -974,9 +6652,10 @@ impl ConnectionPool {
                             rows: Arc::new(AsyncMutex::new(None)),
                             columns: Arc::new(SyncMutex::new(None)),
                             rowcount: conn.changes() as i64,
-                            lastrowid: conn.last_insert_rowid(),
+                            lastrowid: stmt_is_insert(&sql).then(|| conn.last_insert_rowid()),
                             done: Arc::new(AtomicBool::new(true)),
                             arraysize: 1,
+                            owner,
                         })
                     }
                 } else {
@@ -993,6 +6672,9 @@ impl ConnectionPool {
         sql: String,
         parameters: Option<Bound<'py, PyAny>>,
     ) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
+        let owner = self.owner;
+        check_single_statement(&sql)?;
         let parameters_iter: Option<PyObject> = parameters.map(|p| {
             p.try_iter().unwrap().into_pyobject(p.py()).unwrap().into_any().unbind()
         });
@@ -1001,10 +6683,10 @@ impl ConnectionPool {
         let sem = self.writer_sem.clone();
         
         future_into_py(py, async move {
-            let _permit = sem.acquire().await.map_err(|e| PyValueError::new_err(e.to_string()))?;
+            let _permit = sem.acquire().await.map_err(|_| PyValueError::new_err("closed"))?;
             let conn_opt = { let guard = writer_arc.lock(); guard.as_ref().cloned() };
             if let Some(conn) = conn_opt {
-                let txguard = TxGuard::new(conn, true).await?;
+                let txguard = TxGuard::new(conn, true, None, None).await?;
                 
                 let stmt_res = txguard.conn().prepare(&sql).await;
                 if let Err(e) = stmt_res {
@@ -1012,8 +6694,9 @@ impl ConnectionPool {
                     return Err(to_py_err(e));
                 }
                 let stmt = stmt_res.unwrap();
+                let is_insert = stmt_is_insert(&sql);
                 let mut total_changes = 0;
-                let mut last_id = 0;
+                let mut last_id = None;
                 
                 if let Some(iterator_obj) = parameters_iter {
                     loop {
@@ -1023,7 +6706,7 @@ impl ConnectionPool {
                             for _ in 0..100 {
                                 match iter.call_method0("__next__") {
                                     Ok(item) => {
-                                        chunk.push(extract_parameters(py, Some(ListOrTuple::extract_bound(&item)?))?);
+                                        chunk.push(extract_parameters(py, Some(ListOrTuple::extract_bound(&item)?), &TypeConfig::default())?);
                                     }
                                     Err(e) => {
                                         if e.is_instance_of::<pyo3::exceptions::PyStopIteration>(py) {
@@ -1051,7 +6734,9 @@ impl ConnectionPool {
                                 return Err(to_py_err(e));
                             }
                             total_changes += txguard.conn().changes() as i64;
-                            last_id = txguard.conn().last_insert_rowid();
+                            if is_insert {
+                                last_id = Some(txguard.conn().last_insert_rowid());
+                            }
                         }
                     }
                 }
@@ -1060,9 +6745,10 @@ impl ConnectionPool {
                     rows: Arc::new(AsyncMutex::new(None)),
                     columns: Arc::new(SyncMutex::new(None)),
                     rowcount: total_changes as i64,
-                    lastrowid: last_id as i64,
+                    lastrowid: last_id,
                     done: Arc::new(AtomicBool::new(true)),
                     arraysize: 1,
+                    owner,
                 })
             } else {
                 Err(PyValueError::new_err("closed"))
@@ -1075,6 +6761,8 @@ impl ConnectionPool {
         py: Python<'py>,
         operations: Bound<'py, PyAny>,
     ) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
+        let owner = self.owner;
         let mut ops = Vec::new();
         let iter = operations.try_iter()?;
         for item in iter {
@@ -1085,7 +6773,7 @@ impl ConnectionPool {
             let params = if params_obj.is_none() {
                 libsql_core::params::Params::None
             } else {
-                extract_parameters(py, Some(ListOrTuple::extract_bound(&params_obj)?))?                
+                extract_parameters(py, Some(ListOrTuple::extract_bound(&params_obj)?), &TypeConfig::default())?                
             };
             ops.push((sql, params));
         }
@@ -1094,12 +6782,12 @@ impl ConnectionPool {
         let sem = self.writer_sem.clone();
         
         future_into_py(py, async move {
-            let _permit = sem.acquire().await.map_err(|e| PyValueError::new_err(e.to_string()))?;
+            let _permit = sem.acquire().await.map_err(|_| PyValueError::new_err("closed"))?;
             let conn_opt = { let guard = writer_arc.lock(); guard.as_ref().cloned() };
             if let Some(conn) = conn_opt {
-                let txguard = TxGuard::new(conn, true).await?;
+                let txguard = TxGuard::new(conn, true, None, None).await?;
                 let mut total_changes = 0;
-                let mut last_id = 0;
+                let mut last_id = None;
                 
                 for (sql, params) in ops {
                     let stmt_res = txguard.conn().prepare(&sql).await;
@@ -1113,16 +6801,19 @@ impl ConnectionPool {
                         return Err(to_py_err(e));
                     }
                     total_changes += txguard.conn().changes() as i64;
-                    last_id = txguard.conn().last_insert_rowid();
+                    if stmt_is_insert(&sql) {
+                        last_id = Some(txguard.conn().last_insert_rowid());
+                    }
                 }
                 txguard.commit().await?;
                 Ok(PoolCursor {
                     rows: Arc::new(AsyncMutex::new(None)),
                     columns: Arc::new(SyncMutex::new(None)),
                     rowcount: total_changes as i64,
-                    lastrowid: last_id as i64,
+                    lastrowid: last_id,
                     done: Arc::new(AtomicBool::new(true)),
                     arraysize: 1,
+                    owner,
                 })
             } else {
                 Err(PyValueError::new_err("closed"))
@@ -1130,16 +6821,11 @@ impl ConnectionPool {
         })
     }
 
-    fn close<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
-        let writer = self.writer.clone();
-        let readers = self.readers.clone();
-        future_into_py(py, async move {
-            writer.lock().take();
-            for r in readers {
-                r.lock().take();
-            }
-            Ok(())
-        })
+    #[pyo3(signature = (graceful_timeout=None))]
+    fn close<'py>(&self, py: Python<'py>, graceful_timeout: Option<f64>) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
+        let deadline = deadline_arg("graceful_timeout", graceful_timeout)?;
+        future_into_py(py, self.shutdown(graceful_timeout.is_some(), deadline))
     }
 
     fn __aenter__<'py>(slf: Py<Self>, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
@@ -1154,6 +6840,7 @@ impl ConnectionPool {
         _exc_val: Option<PyObject>,
         _exc_tb: Option<PyObject>,
     ) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
         let writer = self.writer.clone();
         let readers = self.readers.clone();
         future_into_py(py, async move {
@@ -1166,21 +6853,26 @@ impl ConnectionPool {
     }
 }
 
+/// Column name and declared type.
+type NamedColumn = (String, Option<String>);
+
 #[pyclass]
 #[derive(Clone)]
 pub struct PoolCursor {
     #[pyo3(get, set)]
     arraysize: usize,
     rows: Arc<AsyncMutex<Option<libsql_core::Rows>>>,
-    columns: Arc<SyncMutex<Option<Vec<String>>>>,
+    columns: Arc<SyncMutex<Option<Vec<NamedColumn>>>>,
     rowcount: i64,
-    lastrowid: i64,
+    lastrowid: Option<i64>,
     done: Arc<AtomicBool>,
+    owner: Owner,
 }
 
 #[pymethods]
 impl PoolCursor {
     fn fetchone<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
         let rows_arc = self.rows.clone();
         future_into_py(py, async move {
             let mut guard = rows_arc.lock().await;
@@ -1189,7 +6881,7 @@ impl PoolCursor {
                     let cc = rows.column_count();
                     let mut vals = Vec::with_capacity(cc as usize);
                     for i in 0..cc {
-                        vals.push(r.get_value(i).map_err(to_py_err)?);
+                        vals.push(row_value(&r, i, TextErrors::Strict)?);
                     }
                     drop(guard);
                     return Python::with_gil(|py| {
@@ -1206,6 +6898,7 @@ impl PoolCursor {
     }
 
     fn fetchall<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
         let rows_arc = self.rows.clone();
         future_into_py(py, async move {
             let mut guard = rows_arc.lock().await;
@@ -1215,23 +6908,13 @@ impl PoolCursor {
                 while let Some(r) = rows.next().await.map_err(to_py_err)? {
                     let mut row = Vec::with_capacity(cc as usize);
                     for i in 0..cc {
-                        row.push(r.get_value(i).map_err(to_py_err)?);
+                        row.push(row_value(&r, i, TextErrors::Strict)?);
                     }
                     data.push(row);
                 }
             }
             drop(guard);
-            Python::with_gil(|py| {
-                let mut elements = Vec::with_capacity(data.len());
-                for row in data {
-                    let mut py_row = Vec::with_capacity(row.len());
-                    for v in row {
-                        py_row.push(convert_value(py, v)?);
-                    }
-                    elements.push(PyTuple::new(py, py_row)?.unbind().into_any());
-                }
-                Ok(PyList::new(py, elements)?.unbind().into_any())
-            })
+            rows_to_list(data, &[], &TypeConfig::default()).await
         })
     }
 
@@ -1241,6 +6924,7 @@ impl PoolCursor {
         py: Python<'py>,
         size: Option<usize>,
     ) -> PyResult<Bound<'py, PyAny>> {
+        self.owner.check()?;
         let rows_arc = self.rows.clone();
         let done_arc = self.done.clone();
         let arraysize = self.arraysize;
@@ -1256,7 +6940,7 @@ impl PoolCursor {
                             Some(r) => {
                                 let mut row = Vec::with_capacity(cc as usize);
                                 for i in 0..cc {
-                                    row.push(r.get_value(i).map_err(to_py_err)?);
+                                    row.push(row_value(&r, i, TextErrors::Strict)?);
                                 }
                                 data.push(row);
                             }
@@ -1269,17 +6953,7 @@ impl PoolCursor {
                 }
             }
             drop(guard);
-            Python::with_gil(|py| {
-                let mut elements = Vec::with_capacity(data.len());
-                for row in data {
-                    let mut py_row = Vec::with_capacity(row.len());
-                    for v in row {
-                        py_row.push(convert_value(py, v)?);
-                    }
-                    elements.push(PyTuple::new(py, py_row)?.unbind().into_any());
-                }
-                Ok(PyList::new(py, elements)?.unbind().into_any())
-            })
+            rows_to_list(data, &[], &TypeConfig::default()).await
         })
     }
 
@@ -1288,10 +6962,10 @@ impl PoolCursor {
         let guard = self.columns.lock();
         if let Some(cols) = guard.as_ref() {
             let mut elements = Vec::new();
-            for name in cols {
+            for (name, decl_type) in cols {
                 let e = (
                     name.clone(),
-                    py.None(),
+                    decl_type.clone(),
                     py.None(),
                     py.None(),
                     py.None(),
@@ -1310,7 +6984,7 @@ impl PoolCursor {
     }
 
     #[getter]
-    fn lastrowid(&self) -> i64 {
+    fn lastrowid(&self) -> Option<i64> {
         self.lastrowid
     }
 
@@ -1324,6 +6998,7 @@ impl PoolCursor {
     }
 
     fn __anext__<'py>(&self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyAny>>> {
+        self.owner.check()?;
         let rows_arc = self.rows.clone();
         future_into_py(py, async move {
             let mut guard = rows_arc.lock().await;
@@ -1332,7 +7007,7 @@ impl PoolCursor {
                     let cc = rows.column_count();
                     let mut vals = Vec::with_capacity(cc as usize);
                     for i in 0..cc {
-                        vals.push(r.get_value(i).map_err(to_py_err)?);
+                        vals.push(row_value(&r, i, TextErrors::Strict)?);
                     }
                     drop(guard);
                     return Python::with_gil(|py| {
@@ -1365,9 +7040,11 @@ fn create_pool<'py>(
     timeout: f64,
     encryption_key: Option<String>,
 ) -> PyResult<Bound<'py, PyAny>> {
+    check_runtime_pid()?;
     if size < 2 {
         return Err(PyValueError::new_err("pool size must be at least 2"));
     }
+    let owner = Owner::current(false);
     future_into_py(py, async move {
         let encryption_config = match encryption_key {
             Some(key) => {
@@ -1405,7 +7082,7 @@ fn create_pool<'py>(
             readers.push(Arc::new(SyncMutex::new(Some(reader))));
         }
 
-        Ok(ConnectionPool {
+        let pool = ConnectionPool {
             db: Arc::new(db),
             writer: Arc::new(SyncMutex::new(Some(writer_conn))),
             readers,
@@ -1413,27 +7090,265 @@ fn create_pool<'py>(
             reader_sem: Arc::new(Semaphore::new(reader_count * 2)),
             writer_sem: Arc::new(Semaphore::new(1)),
             pool_size: size,
+            owner,
+        };
+        Python::with_gil(|py| {
+            let pool = Py::new(py, pool)?;
+            track_open_handle(pool.bind(py).as_any())?;
+            Ok(pool)
         })
     })
 }
 
+/// `timeout` seconds from now, for the `close()` and `close_all()` timeouts. A deadline too far
+/// away to represent is no deadline.
+fn deadline_arg(name: &str, timeout: Option<f64>) -> PyResult<Option<Instant>> {
+    Ok(match timeout {
+        Some(t) => Instant::now().checked_add(seconds_arg(name, t)?),
+        None => None,
+    })
+}
+
+/// Weak references to the connections and pools opened so far, for `close_all()`.
+static OPEN_HANDLES: SyncMutex<Vec<Py<PyWeakrefReference>>> = SyncMutex::new(Vec::new());
+
+fn track_open_handle(handle: &Bound<'_, PyAny>) -> PyResult<()> {
+    let weak = PyWeakrefReference::new(handle)?.unbind();
+    let mut handles = OPEN_HANDLES.lock();
+    handles.retain(|h| h.bind(handle.py()).upgrade().is_some());
+    handles.push(weak);
+    Ok(())
+}
+
+/// Closes every connection and pool that is still open, e.g. on ASGI lifespan shutdown: each
+/// waits for its running and queued statements, up to `timeout` seconds overall, then rolls
+/// back an open transaction and closes.
+#[pyfunction]
+#[pyo3(signature = (timeout=None))]
+fn close_all(py: Python<'_>, timeout: Option<f64>) -> PyResult<Bound<'_, PyAny>> {
+    check_runtime_pid()?;
+    let deadline = deadline_arg("timeout", timeout)?;
+    let runtime = pyo3_async_runtimes::tokio::get_runtime();
+    let handles: Vec<Py<PyWeakrefReference>> = OPEN_HANDLES.lock().iter().map(|h| h.clone_ref(py)).collect();
+    let mut tasks = Vec::new();
+    for weak in handles {
+        let Some(handle) = weak.bind(py).upgrade() else {
+            continue;
+        };
+        let task = if let Ok(conn) = handle.downcast::<Connection>() {
+            runtime.spawn(conn.borrow().graceful_shutdown(deadline))
+        } else if let Ok(pool) = handle.downcast::<ConnectionPool>() {
+            runtime.spawn(pool.borrow().shutdown(true, deadline))
+        } else {
+            continue;
+        };
+        tasks.push((weak, task));
+    }
+    future_into_py(py, async move {
+        let mut first_error = None;
+        let mut closed = Vec::new();
+        for (weak, task) in tasks {
+            match task.await.map_err(|e| PyValueError::new_err(e.to_string())).and_then(|r| r) {
+                Ok(()) => closed.push(weak),
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        // Handles that failed to close stay registered, so a later close_all() retries them.
+        Python::with_gil(move |py| {
+            OPEN_HANDLES.lock().retain(|h| {
+                h.bind(py).upgrade().is_some() && !closed.iter().any(|c| c.as_ptr() == h.as_ptr())
+            });
+        });
+        first_error.map_or(Ok(()), Err)
+    })
+}
+
+static LOG_FILTER: OnceLock<reload::Handle<Targets, Registry>> = OnceLock::new();
+
+/// A tracing event waiting to be handed to Python `logging`.
+struct LogRecord {
+    logger: String,
+    level: u8,
+    message: String,
+}
+
+/// Queues tracing events for `LogDrain`, so threads that emit them never wait for the GIL.
+struct PyLoggingLayer {
+    sender: mpsc::UnboundedSender<LogRecord>,
+}
+
+/// Logs queued tracing events from a Python daemon thread started by `init_logging`.
+#[pyclass]
+struct LogDrain {
+    receiver: SyncMutex<Option<mpsc::UnboundedReceiver<LogRecord>>>,
+}
+
+#[pymethods]
+impl LogDrain {
+    fn run(&self, py: Python<'_>) -> PyResult<()> {
+        let Some(mut receiver) = self.receiver.lock().take() else {
+            return Ok(());
+        };
+        let logging = PyModule::import(py, "logging")?;
+        while let Some(record) = py.allow_threads(|| receiver.blocking_recv()) {
+            let res = logging
+                .call_method1("getLogger", (record.logger,))
+                .and_then(|logger| logger.call_method1("log", (record.level, record.message)));
+            if let Err(e) = res {
+                e.write_unraisable(py, None);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct LogVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for LogVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            let _ = write!(self.fields, " {}={}", field.name(), value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+        } else {
+            let _ = write!(self.fields, " {}={:?}", field.name(), value);
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for PyLoggingLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let meta = event.metadata();
+        let mut visitor = LogVisitor::default();
+        event.record(&mut visitor);
+        let level = match *meta.level() {
+            Level::TRACE => 5,
+            Level::DEBUG => 10,
+            Level::INFO => 20,
+            Level::WARN => 30,
+            Level::ERROR => 40,
+        };
+        let _ = self.sender.send(LogRecord {
+            logger: meta.target().replace("::", "."),
+            level,
+            message: visitor.message + &visitor.fields,
+        });
+    }
+}
+
+fn parse_log_level(level: &Bound<'_, PyAny>) -> PyResult<LevelFilter> {
+    if let Ok(n) = level.extract::<i64>() {
+        return Ok(match n {
+            i64::MIN..=5 => LevelFilter::TRACE,
+            6..=10 => LevelFilter::DEBUG,
+            11..=20 => LevelFilter::INFO,
+            21..=30 => LevelFilter::WARN,
+            31..=50 => LevelFilter::ERROR,
+            _ => LevelFilter::OFF,
+        });
+    }
+    let name: String = level.extract()?;
+    match name.to_uppercase().as_str() {
+        "TRACE" => Ok(LevelFilter::TRACE),
+        "DEBUG" => Ok(LevelFilter::DEBUG),
+        "INFO" => Ok(LevelFilter::INFO),
+        "WARN" | "WARNING" => Ok(LevelFilter::WARN),
+        "ERROR" | "CRITICAL" => Ok(LevelFilter::ERROR),
+        "OFF" | "NONE" => Ok(LevelFilter::OFF),
+        _ => Err(PyValueError::new_err(format!("unknown log level: {}", name))),
+    }
+}
+
+#[pyfunction]
+#[pyo3(signature = (level=None, targets=None))]
+fn init_logging(
+    py: Python<'_>,
+    level: Option<Bound<'_, PyAny>>,
+    targets: Option<Bound<'_, PyDict>>,
+) -> PyResult<()> {
+    let default_level = match level {
+        Some(l) => parse_log_level(&l)?,
+        None => LevelFilter::WARN,
+    };
+    let mut filter = Targets::new().with_default(default_level);
+    if let Some(targets) = targets {
+        for (target, target_level) in targets.iter() {
+            let target: String = target.extract()?;
+            filter = filter.with_target(target.replace('.', "::"), parse_log_level(&target_level)?);
+        }
+    }
+    if let Some(handle) = LOG_FILTER.get() {
+        return handle.reload(filter).map_err(|e| PyValueError::new_err(e.to_string()));
+    }
+    let (filter_layer, handle) = reload::Layer::new(filter);
+    let (sender, receiver) = mpsc::unbounded_channel();
+    tracing_subscriber::registry()
+        .with(filter_layer)
+        .with(PyLoggingLayer { sender })
+        .try_init()
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let _ = LOG_FILTER.set(handle);
+    let drain = Py::new(py, LogDrain { receiver: SyncMutex::new(Some(receiver)) })?;
+    let kwargs = PyDict::new(py);
+    kwargs.set_item("target", drain.getattr(py, "run")?)?;
+    kwargs.set_item("name", "aiolibsql-logging")?;
+    kwargs.set_item("daemon", true)?;
+    PyModule::import(py, "threading")?
+        .getattr("Thread")?
+        .call((), Some(&kwargs))?
+        .call_method0("start")?;
+    Ok(())
+}
+
 #[pymodule]
+#[pyo3(name = "_aiolibsql")]
 fn aiolibsql(py: Python<'_>, m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add("VERSION", VERSION)?;
     m.add("LEGACY_TRANSACTION_CONTROL", LEGACY_TRANSACTION_CONTROL)?;
+    m.add("PARSE_DECLTYPES", PARSE_DECLTYPES)?;
+    m.add("PARSE_COLNAMES", PARSE_COLNAMES)?;
+    m.add("apilevel", "2.0")?;
+    m.add("threadsafety", 1)?;
     m.add("paramstyle", "qmark")?;
-    m.add("sqlite_version_info", (3, 42, 0))?;
+    for (name, value) in SQLITE_CONSTANTS {
+        m.add(*name, *value)?;
+    }
+    let version = libsql_core::version_number();
+    m.add("sqlite_version", libsql_core::version())?;
+    m.add("sqlite_version_info", (version / 1_000_000, version / 1000 % 1000, version % 1000))?;
     
-    m.add("Error", py.get_type::<DatabaseError>())?;
+    m.add("Error", py.get_type::<Error>())?;
     m.add("DatabaseError", py.get_type::<DatabaseError>())?;
     m.add("OperationalError", py.get_type::<OperationalError>())?;
     m.add("IntegrityError", py.get_type::<IntegrityError>())?;
     m.add("TimeoutError", py.get_type::<TimeoutError>())?;
+    m.add("ConflictError", py.get_type::<ConflictError>())?;
+    m.add("ProgrammingError", py.get_type::<ProgrammingError>())?;
+    m.add("DataError", py.get_type::<DataError>())?;
+    m.add("InterfaceError", py.get_type::<InterfaceError>())?;
+    m.add("Warning", py.get_type::<Warning>())?;
 
     m.add_function(wrap_pyfunction!(connect, m)?)?;
     m.add_function(wrap_pyfunction!(create_pool, m)?)?;
+    m.add_function(wrap_pyfunction!(close_all, m)?)?;
+    m.add_function(wrap_pyfunction!(init_logging, m)?)?;
     m.add_class::<Connection>()?;
     m.add_class::<Cursor>()?;
+    m.add_class::<Statement>()?;
+    m.add_class::<Transaction>()?;
+    m.add_class::<ChangeStream>()?;
     m.add_class::<ConnectionPool>()?;
     m.add_class::<PoolCursor>()?;
     Ok(())
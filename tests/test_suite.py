@@ -6,7 +6,14 @@ Tests every exposed function and property of the aiolibsql library.
 Run with: pytest tests/test_suite.py -v
 """
 
+import asyncio
+import json
+import math
+import os
+import socket
 import sys
+import threading
+from http.server import BaseHTTPRequestHandler, ThreadingHTTPServer
 import pytest
 import pytest_asyncio
 import aiolibsql
@@ -336,7 +343,8 @@ async def test_in_transaction():
 def test_module_constants():
     assert aiolibsql.LEGACY_TRANSACTION_CONTROL == -1
     assert aiolibsql.paramstyle == "qmark"
-    assert aiolibsql.sqlite_version_info == (3, 42, 0)
+    assert aiolibsql.sqlite_version_info >= (3, 45, 1)
+    assert aiolibsql.sqlite_version == ".".join(map(str, aiolibsql.sqlite_version_info))
     assert aiolibsql.Error is not None
     assert hasattr(aiolibsql, "VERSION")
     assert isinstance(aiolibsql.VERSION, str)
@@ -382,3 +390,2480 @@ async def test_connection_pool(tmp_path):
     assert row[0] == 4
     
     await pool.close()
+
+
+@pytest.mark.asyncio
+async def test_init_logging(fake_hrana, caplog):
+    import logging
+
+    caplog.set_level(1, logger="libsql")
+    aiolibsql.init_logging("WARNING", targets={"libsql": "TRACE", "hyper": "WARNING"})
+    try:
+        conn = await aiolibsql.connect(fake_hrana.url, autocommit=1)
+        await conn.execute("SELECT 1")
+        await conn.close()
+        for _ in range(100):
+            if any(r.name.startswith("libsql.hrana") for r in caplog.records):
+                break
+            await asyncio.sleep(0.05)
+    finally:
+        aiolibsql.init_logging("WARNING")
+    record = next(r for r in caplog.records if r.name.startswith("libsql.hrana"))
+    assert record.levelno < logging.DEBUG
+    assert record.getMessage()
+
+
+def test_init_logging_invalid_level():
+    with pytest.raises(ValueError):
+        aiolibsql.init_logging("LOUD")
+
+
+@pytest.mark.asyncio
+async def test_connection_metrics(conn):
+    await conn.execute("CREATE TABLE t (x INTEGER)")
+    await conn.executemany("INSERT INTO t VALUES (?)", [(1,), (2,), (3,)])
+    cursor = await conn.execute("SELECT * FROM t")
+    await cursor.fetchall()
+    with pytest.raises(aiolibsql.Error):
+        await conn.execute("SELECT * FROM missing")
+    metrics = conn.metrics()
+    assert metrics["statements_executed"] >= 5
+    assert metrics["rows_fetched"] == 3
+    assert metrics["errors"] == 1
+    assert metrics["busy_errors"] == 0
+    assert metrics["busy_retries"] == 0
+    assert "bytes_synced" not in metrics
+    assert metrics["failovers"] == 0
+
+
+@pytest.mark.asyncio
+async def test_replication_info_local(conn):
+    info = await conn.replication_info()
+    assert info["is_replica"] is False
+    assert info["connected"] is False
+    assert info["last_sync"] is None
+    assert info["replication_index"] is None
+
+
+@pytest.mark.asyncio
+async def test_connect_url_options():
+    conn = await aiolibsql.connect("libsql://localhost:8080?authToken=abc%2Ddef&tls=0")
+    await conn.close()
+    with pytest.raises(ValueError):
+        aiolibsql.connect("libsql://localhost:8080?authToken=abc&bogus=1")
+    with pytest.raises(ValueError):
+        aiolibsql.connect("libsql://localhost:8080?tls=maybe")
+
+
+@pytest.mark.asyncio
+async def test_connect_from_env(tmp_path, monkeypatch):
+    monkeypatch.setenv("LIBSQL_URL", "libsql://localhost:1?bogus=1")
+    db = str(tmp_path / "env.db")
+    conn = await aiolibsql.connect(db)
+    await conn.close()
+    with pytest.raises(ValueError):
+        aiolibsql.connect(db, from_env=True)
+
+
+@pytest.mark.asyncio
+async def test_migrations_apply(conn, tmp_path):
+    from aiolibsql import migrations
+
+    (tmp_path / "0001_users.sql").write_text("CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT);")
+    (tmp_path / "0002_seed.sql").write_text("INSERT INTO users (name) VALUES ('Alice');")
+
+    async def add_bob(c):
+        await c.execute("INSERT INTO users (name) VALUES (?)", ("Bob",))
+
+    assert await migrations.apply(conn, str(tmp_path)) == ["0001", "0002"]
+    assert await migrations.apply(conn, [*sorted(map(str, tmp_path.glob("*.sql"))), ("0003", add_bob)]) == ["0003"]
+    assert await migrations.applied(conn) == ["0001", "0002", "0003"]
+    cursor = await conn.execute("SELECT COUNT(*) FROM users")
+    assert (await cursor.fetchone())[0] == 2
+
+
+@pytest.mark.asyncio
+async def test_migrations_failure_rolls_back(conn):
+    from aiolibsql import migrations
+
+    with pytest.raises(migrations.MigrationError) as excinfo:
+        await migrations.apply(conn, [
+            ("1", "CREATE TABLE a (x INTEGER)"),
+            ("2", "CREATE TABLE b (x INTEGER); INSERT INTO missing VALUES (1);"),
+        ])
+    assert excinfo.value.version == "2"
+    assert await migrations.applied(conn) == ["1"]
+    cursor = await conn.execute("SELECT name FROM sqlite_master WHERE name = 'b'")
+    assert await cursor.fetchone() is None
+
+
+@pytest.mark.asyncio
+async def test_migrations_recheck_inside_transaction(conn, monkeypatch):
+    from aiolibsql import migrations
+
+    table = 'odd"name'
+    assert await migrations.apply(conn, [("1", "CREATE TABLE a (x INTEGER)")], table=table) == ["1"]
+    assert await migrations.applied(conn, table=table) == ["1"]
+
+    # A stale list of applied versions, as another process would see it just before
+    # this one committed: the version is re-checked under the write lock and skipped.
+    async def stale(conn, *, table=migrations.DEFAULT_TABLE):
+        return []
+
+    monkeypatch.setattr(migrations, "applied", stale)
+    migrations_list = [("1", "CREATE TABLE a (x INTEGER)"), ("2", "CREATE TABLE b (x INTEGER)")]
+    assert await migrations.apply(conn, migrations_list, table=table) == ["2"]
+    assert not conn.in_transaction
+
+
+@pytest.mark.asyncio
+async def test_testing_helpers(tmp_path):
+    from aiolibsql import testing
+
+    (tmp_path / "schema").mkdir()
+    (tmp_path / "schema" / "01_users.sql").write_text("CREATE TABLE users (name TEXT);")
+    (tmp_path / "schema" / "02_seed.sql").write_text("INSERT INTO users VALUES ('alice');")
+
+    async with testing.memory_database(schema=tmp_path / "schema") as conn:
+        async with testing.rollback_after(conn):
+            await conn.execute("INSERT INTO users VALUES ('bob')")
+        cursor = await conn.execute("SELECT name FROM users")
+        assert await cursor.fetchall() == [("alice",)]
+
+    async with testing.temp_database(schema=[tmp_path / "schema" / "01_users.sql"]) as conn:
+        cursor = await conn.execute("SELECT COUNT(*) FROM users")
+        assert await cursor.fetchone() == (0,)
+
+
+@pytest.mark.asyncio
+async def test_aiosqlite_shim():
+    from aiolibsql import aiosqlite
+
+    async with aiosqlite.connect(":memory:", autocommit=1) as db:
+        db.row_factory = aiosqlite.Row
+        await db.execute("CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT)")
+        await db.execute("INSERT INTO users (name) VALUES (?)", ("Alice",))
+        await db.execute("INSERT INTO users (name) VALUES (?)", ["Bob"])
+        row = await db.execute_insert("INSERT INTO users (name) VALUES (?)", ("Carol",))
+        assert row == (3,)
+        assert db.total_changes == 3
+        async with db.execute("SELECT id, name FROM users ORDER BY id") as cursor:
+            names = [r["name"] async for r in cursor]
+        assert names == ["Alice", "Bob", "Carol"]
+        rows = await db.execute_fetchall("SELECT name FROM users WHERE id = ?", (1,))
+        assert rows[0].keys() == ["name"]
+        dump = [line async for line in db.iterdump()]
+        assert dump[0] == "BEGIN TRANSACTION;"
+        assert 'INSERT INTO "users" VALUES(1,\'Alice\');' in dump
+        assert dump[-1] == "COMMIT;"
+
+
+@pytest.mark.asyncio
+async def test_aiosqlite_dict_parameters():
+    from aiolibsql import aiosqlite
+
+    async with aiosqlite.connect(":memory:", autocommit=1) as db:
+        await db.execute("CREATE TABLE users (name TEXT)")
+        with pytest.raises(aiosqlite.ProgrammingError, match="named parameters"):
+            await db.execute("INSERT INTO users VALUES (:name)", {"name": "Alice"})
+        with pytest.raises(aiosqlite.ProgrammingError, match="named parameters"):
+            await db.executemany("INSERT INTO users VALUES (:name)", [{"name": "Bob"}])
+        assert await db.execute_fetchall("SELECT name FROM users") == []
+
+
+def test_sync_api(tmp_path):
+    from aiolibsql import sync
+
+    with sync.connect(str(tmp_path / "sync.db"), autocommit=1) as conn:
+        conn.execute("CREATE TABLE t (x INTEGER)")
+        cursor = conn.execute("INSERT INTO t VALUES (?)", (1,))
+        assert cursor.lastrowid == 1
+        conn.execute("INSERT INTO t VALUES (?)", (2,))
+        assert conn.execute("SELECT * FROM t ORDER BY x").fetchall() == [(1,), (2,)]
+        assert [row for row in conn.execute("SELECT x FROM t ORDER BY x")] == [(1,), (2,)]
+        assert conn.cursor().execute("SELECT COUNT(*) FROM t").fetchone() == (2,)
+        assert conn.metrics()["statements_executed"] >= 4
+        assert conn.replication_info()["is_replica"] is False
+    conn.close()
+
+
+@pytest.mark.asyncio
+async def test_sync_api_inside_running_loop():
+    from aiolibsql import sync
+
+    conn = sync.connect(":memory:")
+    assert conn.execute("SELECT 1").fetchone() == (1,)
+    conn.close()
+
+
+def test_anyio_under_trio(tmp_path):
+    trio = pytest.importorskip("trio")
+    from aiolibsql import anyio as aiolibsql_anyio
+
+    async def main():
+        async with await aiolibsql_anyio.connect(str(tmp_path / "trio.db"), autocommit=1) as conn:
+            await conn.execute("CREATE TABLE t (x INTEGER)")
+            await conn.execute("INSERT INTO t VALUES (?)", (1,))
+            await conn.execute("INSERT INTO t VALUES (?)", (2,))
+            cursor = await conn.execute("SELECT x FROM t ORDER BY x")
+            return [row async for row in cursor]
+
+    assert trio.run(main) == [(1,), (2,)]
+
+
+@pytest.mark.asyncio
+async def test_anyio_under_asyncio():
+    from aiolibsql import anyio as aiolibsql_anyio
+
+    conn = await aiolibsql_anyio.connect(":memory:")
+    cursor = await conn.execute("SELECT 1")
+    assert await cursor.fetchone() == (1,)
+    assert conn.metrics()["statements_executed"] >= 1
+    assert (await conn.replication_info())["is_replica"] is False
+    await conn.close()
+
+    async with await aiolibsql_anyio.connect(":memory:", close_on_exit=True) as conn:
+        await conn.execute("SELECT 1")
+    with pytest.raises(ValueError, match="closed"):
+        await conn.db_stats()
+
+
+def test_connection_used_across_event_loops(tmp_path):
+    import threading
+
+    path = str(tmp_path / "loops.db")
+    results = {}
+
+    def in_new_loop(key, coro_fn):
+        def target():
+            try:
+                results[key] = asyncio.run(coro_fn())
+            except BaseException as e:
+                results[key] = e
+
+        thread = threading.Thread(target=target)
+        thread.start()
+        thread.join()
+        if isinstance(results[key], BaseException):
+            raise results[key]
+        return results[key]
+
+    async def open_conn():
+        conn = await aiolibsql.connect(path, autocommit=1)
+        await conn.execute("CREATE TABLE t (x INTEGER)")
+        return conn
+
+    conn = in_new_loop("open", open_conn)
+
+    async def write():
+        await conn.execute("INSERT INTO t VALUES (1)")
+        cursor = await conn.execute("SELECT x FROM t")
+        return cursor
+
+    cursor = in_new_loop("write", write)
+
+    async def read():
+        rows = await cursor.fetchall()
+        await conn.close()
+        return rows
+
+    assert in_new_loop("read", read) == [(1,)]
+
+
+@pytest.mark.asyncio
+async def test_datetime_adaptation():
+    import datetime
+
+    conn = await aiolibsql.connect(":memory:", autocommit=1, detect_types=aiolibsql.PARSE_DECLTYPES)
+    await conn.execute("CREATE TABLE t (d DATE, ts TIMESTAMP, raw TEXT)")
+    day = datetime.date(2024, 2, 29)
+    moment = datetime.datetime(2024, 2, 29, 13, 45, 30, 123456)
+    await conn.execute("INSERT INTO t VALUES (?, ?, ?)", (day, moment, moment))
+    cursor = await conn.execute("SELECT d, ts, raw FROM t")
+    assert await cursor.fetchone() == (day, moment, "2024-02-29 13:45:30.123456")
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_datetime_epoch_formats():
+    import datetime
+
+    moment = datetime.datetime(2024, 1, 1, 12, 0, 0)
+    aware = datetime.datetime(2024, 1, 1, 14, 0, 0, tzinfo=datetime.timezone(datetime.timedelta(hours=2)))
+    unix = await aiolibsql.connect(":memory:", datetime_format="unix", detect_types=aiolibsql.PARSE_DECLTYPES)
+    await unix.execute("CREATE TABLE t (ts TIMESTAMP)")
+    await unix.execute("INSERT INTO t VALUES (?)", (moment,))
+    await unix.execute("INSERT INTO t VALUES (?)", (aware,))
+    cursor = await unix.execute("SELECT ts, CAST(ts AS INTEGER) FROM t")
+    assert await cursor.fetchall() == [(moment, 1704110400), (moment, 1704110400)]
+    await unix.close()
+
+    julian = await aiolibsql.connect(":memory:", datetime_format="julian")
+    cursor = await julian.execute("SELECT ?", (datetime.date(2000, 1, 1),))
+    assert (await cursor.fetchone())[0] == pytest.approx(2451544.5)
+    await julian.close()
+
+    with pytest.raises(ValueError):
+        await aiolibsql.connect(":memory:", datetime_format="rfc2822")
+
+
+@pytest.mark.asyncio
+async def test_timezone_aware_timestamps():
+    import datetime
+
+    utc = datetime.timezone.utc
+    aware = datetime.datetime(2024, 1, 1, 14, 0, 0, tzinfo=datetime.timezone(datetime.timedelta(hours=2)))
+    conn = await aiolibsql.connect(":memory:", timezone="utc", detect_types=aiolibsql.PARSE_DECLTYPES)
+    await conn.execute("CREATE TABLE t (ts TIMESTAMP)")
+    await conn.execute("INSERT INTO t VALUES (?)", (aware,))
+    await conn.execute("INSERT INTO t VALUES (?)", (datetime.datetime(2024, 1, 1, 12, 0, 0),))
+    await conn.execute("INSERT INTO t VALUES ('2024-01-01 13:00:00+01:00')")
+    cursor = await conn.execute("SELECT ts, CAST(ts AS TEXT) FROM t")
+    rows = await cursor.fetchall()
+    assert [r[1] for r in rows[:2]] == ["2024-01-01 12:00:00"] * 2
+    assert [r[0] for r in rows] == [datetime.datetime(2024, 1, 1, 12, 0, 0, tzinfo=utc)] * 3
+    assert all(r[0].tzinfo is utc for r in rows)
+    await conn.close()
+
+    with pytest.raises(ValueError, match="timezone"):
+        await aiolibsql.connect(":memory:", timezone="Nowhere/Special")
+
+
+@pytest.mark.asyncio
+async def test_timezone_named_zone():
+    import datetime
+    import zoneinfo
+
+    try:
+        berlin = zoneinfo.ZoneInfo("Europe/Berlin")
+    except zoneinfo.ZoneInfoNotFoundError:
+        pytest.skip("no tz database")
+    conn = await aiolibsql.connect(":memory:", timezone="Europe/Berlin", detect_types=aiolibsql.PARSE_DECLTYPES)
+    await conn.execute("CREATE TABLE t (ts TIMESTAMP)")
+    # Naive parameters are local time in the zone, so summer and winter get different offsets.
+    await conn.execute("INSERT INTO t VALUES (?)", (datetime.datetime(2024, 7, 1, 14, 0, 0),))
+    await conn.execute("INSERT INTO t VALUES (?)", (datetime.datetime(2024, 1, 1, 14, 0, 0),))
+    cursor = await conn.execute("SELECT ts, CAST(ts AS TEXT) FROM t")
+    rows = await cursor.fetchall()
+    assert [r[1] for r in rows] == ["2024-07-01 12:00:00", "2024-01-01 13:00:00"]
+    assert [r[0] for r in rows] == [
+        datetime.datetime(2024, 7, 1, 14, 0, 0, tzinfo=berlin),
+        datetime.datetime(2024, 1, 1, 14, 0, 0, tzinfo=berlin),
+    ]
+    assert [r[0].utcoffset() for r in rows] == [datetime.timedelta(hours=2), datetime.timedelta(hours=1)]
+    assert all(r[0].tzinfo is berlin for r in rows)
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_decimal_adaptation():
+    from decimal import Decimal
+
+    conn = await aiolibsql.connect(":memory:", detect_types=aiolibsql.PARSE_DECLTYPES)
+    await conn.execute("CREATE TABLE t (amount DECIMAL(10, 2), note TEXT)")
+    await conn.execute("INSERT INTO t VALUES (?, ?)", (Decimal("12.5"), Decimal("0.10")))
+    cursor = await conn.execute("SELECT amount, note FROM t")
+    assert await cursor.fetchone() == (Decimal("12.5"), "0.10")
+    await conn.close()
+
+    cents = await aiolibsql.connect(":memory:", decimal_format="cents", detect_types=aiolibsql.PARSE_DECLTYPES)
+    await cents.execute("CREATE TABLE t (amount NUMERIC, raw INTEGER)")
+    await cents.execute("INSERT INTO t VALUES (?, ?)", (Decimal("19.99"), Decimal("19.99")))
+    cursor = await cents.execute("SELECT amount, raw FROM t")
+    assert await cursor.fetchone() == (Decimal("19.99"), 1999)
+    with pytest.raises(ValueError):
+        await cents.execute("INSERT INTO t VALUES (?, NULL)", (Decimal("0.001"),))
+    await cents.close()
+
+
+@pytest.mark.asyncio
+async def test_uuid_adaptation():
+    import uuid
+
+    value = uuid.UUID("12345678-1234-5678-1234-567812345678")
+    for uuid_format, stored in (("text", str(value)), ("blob", value.bytes)):
+        conn = await aiolibsql.connect(":memory:", uuid_format=uuid_format, detect_types=aiolibsql.PARSE_DECLTYPES)
+        await conn.execute("CREATE TABLE t (id UUID, raw)")
+        await conn.execute("INSERT INTO t VALUES (?, ?)", (value, value))
+        cursor = await conn.execute("SELECT id, raw FROM t")
+        assert await cursor.fetchone() == (value, stored)
+        await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_strict_parameters():
+    loose = await aiolibsql.connect(":memory:")
+    cursor = await loose.execute("SELECT ?", ({1, 2},))
+    assert await cursor.fetchone() == (None,)
+    await loose.close()
+
+    conn = await aiolibsql.connect(":memory:", strict_parameters=True)
+    with pytest.raises(aiolibsql.ProgrammingError, match="parameter 2: type 'set'"):
+        await conn.execute("SELECT ?, ?", (1, {1, 2}))
+    assert issubclass(aiolibsql.ProgrammingError, aiolibsql.DatabaseError)
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_int_overflow_policy():
+    big = 2**70
+    conn = await aiolibsql.connect(":memory:")
+    cursor = await conn.execute("SELECT ?, ?", (2**63 - 1, -(2**63)))
+    assert await cursor.fetchone() == (2**63 - 1, -(2**63))
+    with pytest.raises(OverflowError, match="parameter 2"):
+        await conn.execute("SELECT ?, ?", (1, big))
+    await conn.close()
+
+    conn = await aiolibsql.connect(":memory:", int_overflow="text")
+    cursor = await conn.execute("SELECT ?", (big,))
+    assert await cursor.fetchone() == (str(big),)
+    await conn.close()
+
+    conn = await aiolibsql.connect(":memory:", int_overflow="blob")
+    cursor = await conn.execute("SELECT ?, ?", (big, -big))
+    row = await cursor.fetchone()
+    assert [int.from_bytes(v, "big", signed=True) for v in row] == [big, -big]
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_nonfinite_policy():
+    inf = float("inf")
+    conn = await aiolibsql.connect(":memory:")
+    cursor = await conn.execute("SELECT ?, ?", (inf, float("nan")))
+    assert await cursor.fetchone() == (inf, None)
+    await conn.close()
+
+    conn = await aiolibsql.connect(":memory:", nonfinite="null")
+    cursor = await conn.execute("SELECT ?, 1e999", (-inf,))
+    assert await cursor.fetchone() == (None, None)
+    await conn.close()
+
+    conn = await aiolibsql.connect(":memory:", nonfinite="raise")
+    with pytest.raises(aiolibsql.DataError, match="parameter 2"):
+        await conn.execute("SELECT ?, ?", (1.0, float("nan")))
+    with pytest.raises(aiolibsql.DataError, match="not finite"):
+        cursor = await conn.execute("SELECT 1e999")
+        await cursor.fetchone()
+    await conn.close()
+
+    conn = await aiolibsql.connect(":memory:", nonfinite="text")
+    await conn.execute("CREATE TABLE t (x REAL)")
+    await conn.executemany("INSERT INTO t VALUES (?)", [(float("nan"),), (-inf,), (1.5,)])
+    cursor = await conn.execute("SELECT x, typeof(x) FROM t")
+    rows = await cursor.fetchall()
+    assert math.isnan(rows[0][0]) and rows[0][1] == "text"
+    assert rows[1:] == [(-inf, "text"), (1.5, "real")]
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_import_csv(tmp_path):
+    import io
+
+    path = tmp_path / "users.csv"
+    path.write_text("id,name\n1,Alice\n2,\"Bob, Jr.\"\n3,Carol\n")
+    conn = await aiolibsql.connect(":memory:", autocommit=1)
+    await conn.execute("CREATE TABLE users (id INTEGER PRIMARY KEY, name TEXT)")
+    seen = []
+    assert await conn.import_csv("users", path, batch_size=2, progress=seen.append) == 3
+    assert seen == [2, 3]
+
+    extra = io.StringIO("3,Dup\n4,Dan\n")
+    count = await conn.import_csv("users", extra, header=False, columns=["id", "name"], on_conflict="ignore")
+    assert count == 2
+    cursor = await conn.execute("SELECT id, name FROM users ORDER BY id")
+    assert await cursor.fetchall() == [(1, "Alice"), (2, "Bob, Jr."), (3, "Carol"), (4, "Dan")]
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_import_csv_rolls_back_on_bad_row():
+    import io
+
+    conn = await aiolibsql.connect(":memory:", autocommit=1)
+    await conn.execute("CREATE TABLE t (a, b)")
+    with pytest.raises(ValueError, match="csv line 3"):
+        await conn.import_csv("t", io.StringIO("a,b\n1,2\n3\n"))
+    cursor = await conn.execute("SELECT COUNT(*) FROM t")
+    assert await cursor.fetchone() == (0,)
+    assert not conn.in_transaction
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_cursor_to_csv_and_jsonl(tmp_path):
+    import csv
+    import io
+    import json
+
+    conn = await aiolibsql.connect(":memory:", autocommit=1)
+    await conn.execute("CREATE TABLE t (id INTEGER, name TEXT, score REAL, data BLOB)")
+    await conn.execute("INSERT INTO t VALUES (1, 'plain', 1.5, x'00ff')")
+    await conn.execute("INSERT INTO t VALUES (2, 'has, \"quotes\"\nand newline', NULL, NULL)")
+
+    path = tmp_path / "out.csv"
+    cursor = await conn.execute("SELECT * FROM t ORDER BY id")
+    assert await cursor.to_csv(path) == 2
+    assert await cursor.fetchone() is None
+    with open(path, newline="") as f:
+        assert list(csv.reader(f)) == [
+            ["id", "name", "score", "data"],
+            ["1", "plain", "1.5", "00ff"],
+            ["2", 'has, "quotes"\nand newline', "", ""],
+        ]
+
+    out = io.StringIO()
+    cursor = await conn.execute("SELECT * FROM t ORDER BY id")
+    assert await cursor.to_jsonl(out) == 2
+    assert [json.loads(line) for line in out.getvalue().splitlines()] == [
+        {"id": 1, "name": "plain", "score": 1.5, "data": "00ff"},
+        {"id": 2, "name": 'has, "quotes"\nand newline', "score": None, "data": None},
+    ]
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_cursor_to_parquet(tmp_path):
+    conn = await aiolibsql.connect(":memory:", autocommit=1)
+    await conn.execute("CREATE TABLE t (id INTEGER, name TEXT, score REAL, data BLOB)")
+    await conn.execute("INSERT INTO t VALUES (1, 'a', 1.5, x'00ff')")
+    await conn.execute("INSERT INTO t VALUES (2, NULL, 2, NULL)")
+    path = tmp_path / "out.parquet"
+    cursor = await conn.execute("SELECT * FROM t ORDER BY id")
+    try:
+        assert await cursor.to_parquet(path) == 2
+    except NotImplementedError:
+        await conn.close()
+        pytest.skip("built without the parquet feature")
+    data = path.read_bytes()
+    assert data[:4] == b"PAR1" and data[-4:] == b"PAR1"
+    with pytest.raises(ValueError):
+        await (await conn.execute("SELECT 1")).to_parquet(tmp_path / "x.parquet", compression="brotli")
+    # Values in the second batch that don't fit the types chosen from the first one widen
+    # the columns: all NULL to STRING, BLOB and TEXT to STRING, INTEGER and REAL to DOUBLE.
+    rows = (
+        "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i <= 65536) "
+        "SELECT i, CASE WHEN i <= 65536 THEN NULL ELSE 'late' END AS late, "
+        "CASE WHEN i <= 65536 THEN x'00' ELSE 'text' END AS data, "
+        "CASE WHEN i <= 65536 THEN i ELSE 0.5 END AS num FROM n"
+    )
+    mixed = tmp_path / "mixed.parquet"
+    assert await (await conn.execute(rows)).to_parquet(mixed) == 65537
+    assert sorted(p.name for p in tmp_path.iterdir()) == ["mixed.parquet", "out.parquet"]
+    # A failed export leaves an existing file as it was.
+    failing = (
+        "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i <= 70000) "
+        "SELECT CASE WHEN i > 70000 THEN abs(70000 - i - 9223372036854775807) ELSE i END FROM n"
+    )
+    with pytest.raises(aiolibsql.Error):
+        await (await conn.execute(failing)).to_parquet(path)
+    assert path.read_bytes() == data
+    assert sorted(p.name for p in tmp_path.iterdir()) == ["mixed.parquet", "out.parquet"]
+    await conn.close()
+
+    pq = pytest.importorskip("pyarrow.parquet")
+    table = pq.read_table(path)
+    assert table.to_pylist() == [
+        {"id": 1, "name": "a", "score": 1.5, "data": b"\x00\xff"},
+        {"id": 2, "name": None, "score": 2.0, "data": None},
+    ]
+    table = pq.read_table(mixed)
+    assert [str(t) for t in table.schema.types] == ["int64", "string", "string", "double"]
+    assert table.slice(0, 1).to_pylist() == [{"i": 1, "late": None, "data": "00", "num": 1.0}]
+    assert table.slice(65536).to_pylist() == [{"i": 65537, "late": "late", "data": "text", "num": 0.5}]
+
+
+@pytest.mark.asyncio
+async def test_attach_detach(tmp_path):
+    other = await aiolibsql.connect(str(tmp_path / "other.db"), autocommit=1)
+    await other.execute("CREATE TABLE items (name TEXT)")
+    await other.execute("INSERT INTO items VALUES ('from other')")
+    await other.close()
+
+    conn = await aiolibsql.connect(":memory:", autocommit=1)
+    await conn.attach(tmp_path / "other.db", 'my "other" db')
+    cursor = await conn.execute('SELECT name FROM "my ""other"" db".items')
+    assert await cursor.fetchall() == [("from other",)]
+    await conn.detach('my "other" db')
+    with pytest.raises(aiolibsql.OperationalError):
+        await conn.execute('SELECT name FROM "my ""other"" db".items')
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_attach_encrypted(tmp_path):
+    path = str(tmp_path / "archive.db")
+    archive = await aiolibsql.connect(path, autocommit=1, encryption_key="archive-key")
+    await archive.execute("CREATE TABLE items (name TEXT)")
+    await archive.execute("INSERT INTO items VALUES ('sealed')")
+    await archive.close()
+    with open(path, "rb") as f:
+        assert not f.read(16).startswith(b"SQLite format 3")
+
+    conn = await aiolibsql.connect(":memory:", autocommit=1)
+    await conn.attach(path, "archive", encryption_key="archive-key")
+    cursor = await conn.execute("SELECT name FROM archive.items")
+    assert await cursor.fetchall() == [("sealed",)]
+    await conn.detach("archive")
+
+    with pytest.raises(aiolibsql.Error):
+        await conn.attach(path, "wrong", encryption_key="not-the-key")
+        await conn.execute("SELECT name FROM wrong.items")
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_implicit_behavior_warnings():
+    import warnings
+
+    assert issubclass(aiolibsql.Warning, UserWarning)
+    conn = await aiolibsql.connect(":memory:", autocommit=1)
+    with pytest.warns(aiolibsql.Warning, match="parameter 1: type 'set'"):
+        await conn.execute("SELECT ?", ({1},))
+
+    with warnings.catch_warnings():
+        warnings.simplefilter("error")
+        await conn.execute("SELECT ';' -- trailing; comment\n;  ")
+        await conn.execute("CREATE TABLE t (x)")
+        await conn.execute(
+            "CREATE TRIGGER tr AFTER INSERT ON t BEGIN "
+            "SELECT CASE WHEN new.x > 0 THEN 1 END; SELECT 2; END;"
+        )
+    await conn.close()
+
+    conn = await aiolibsql.connect(":memory:", autocommit=1)
+    await conn.execute("CREATE TABLE t (x)")
+    await conn.execute("BEGIN")
+    await conn.execute("INSERT INTO t VALUES (1)")
+    with pytest.warns(aiolibsql.Warning, match="committed by __aexit__"):
+        async with conn:
+            pass
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_close_rolls_back_open_transaction(tmp_path):
+    path = str(tmp_path / "close.db")
+    conn = await aiolibsql.connect(path, autocommit=1)
+    await conn.execute("CREATE TABLE t (x)")
+    await conn.execute("BEGIN")
+    await conn.execute("INSERT INTO t VALUES (1)")
+    with pytest.warns(aiolibsql.Warning, match="rolled back by close"):
+        await conn.close()
+
+    conn = await aiolibsql.connect(path, autocommit=1)
+    cursor = await conn.execute("SELECT COUNT(*) FROM t")
+    assert await cursor.fetchone() == (0,)
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_resource_warnings_for_unclosed_handles():
+    import gc
+
+    conn = await aiolibsql.connect(":memory:")
+    cursor = await conn.execute("SELECT 1 UNION ALL SELECT 2")
+    await cursor.fetchone()
+    with pytest.warns(ResourceWarning, match="Cursor"):
+        del cursor
+        gc.collect()
+
+    cursor = await conn.execute("SELECT 1")
+    await cursor.fetchall()
+    del cursor
+    gc.collect()
+
+    with pytest.warns(ResourceWarning, match="Connection"):
+        del conn
+        gc.collect()
+
+
+@pytest.mark.asyncio
+async def test_close_releases_open_cursors():
+    conn = await aiolibsql.connect(":memory:")
+    cursor = await conn.execute("SELECT 1 UNION ALL SELECT 2")
+    assert await cursor.fetchone() == (1,)
+    await conn.close()
+    assert await cursor.fetchone() is None
+
+
+@pytest.mark.asyncio
+async def test_cursor_close_keeps_connection_open():
+    conn = await aiolibsql.connect(":memory:", autocommit=1)
+    await conn.execute("CREATE TABLE t (x)")
+    for i in range(3):
+        await conn.execute("INSERT INTO t VALUES (?)", (i,))
+
+    first = await conn.cursor().execute("SELECT x FROM t ORDER BY x")
+    second = await conn.cursor().execute("SELECT x FROM t ORDER BY x DESC")
+    assert await first.fetchone() == (0,)
+    await first.close()
+    assert await first.fetchone() is None
+
+    assert await second.fetchall() == [(2,), (1,), (0,)]
+    third = await conn.execute("SELECT COUNT(*) FROM t")
+    assert await third.fetchone() == (3,)
+    await conn.execute("INSERT INTO t VALUES (3)")
+    await third.close()
+    await second.close()
+    cursor = await conn.execute("SELECT COUNT(*) FROM t")
+    assert await cursor.fetchone() == (4,)
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_independent_cursors_interleaved():
+    conn = await aiolibsql.connect(":memory:", autocommit=1)
+    await conn.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, x)")
+    for i in range(20):
+        await conn.execute("INSERT INTO t (x) VALUES (?)", (i,))
+
+    asc = await conn.execute("SELECT x FROM t ORDER BY x")
+    desc = await conn.execute("SELECT x FROM t ORDER BY x DESC")
+    pairs = [(await asc.fetchone(), await desc.fetchone()) for _ in range(20)]
+    assert pairs == [((i,), (19 - i,)) for i in range(20)]
+
+    async def insert(value):
+        cursor = await conn.execute("INSERT INTO t (x) VALUES (?)", (value,))
+        return cursor.lastrowid, cursor.rowcount
+
+    results = await asyncio.gather(*(insert(100 + i) for i in range(10)))
+    assert sorted(r[0] for r in results) == list(range(21, 31))
+    assert all(r[1] == 1 for r in results)
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_execute_rejects_multiple_statements():
+    conn = await aiolibsql.connect(":memory:", autocommit=1)
+    await conn.execute("CREATE TABLE t (x)")
+    with pytest.raises(aiolibsql.ProgrammingError, match="executescript"):
+        await conn.execute("INSERT INTO t VALUES (1); DROP TABLE t")
+    with pytest.raises(aiolibsql.ProgrammingError, match="executescript"):
+        await conn.executemany("INSERT INTO t VALUES (?); DROP TABLE t", [(1,), (2,)])
+    await conn.executemany("INSERT INTO t VALUES (?) /* ; */ ;", [])
+    cursor = await conn.execute("SELECT COUNT(*) FROM t;  -- done")
+    assert await cursor.fetchone() == (0,)
+    await conn.close()
+
+    pool = await aiolibsql.create_pool(":memory:", size=2)
+    with pytest.raises(aiolibsql.ProgrammingError):
+        await pool.execute("SELECT 1; SELECT 2")
+    with pytest.raises(aiolibsql.ProgrammingError):
+        await pool.executemany("CREATE TABLE p (x); DROP TABLE p", [])
+    await pool.close()
+
+
+@pytest.mark.asyncio
+async def test_executescript_commits_pending_transaction():
+    conn = await aiolibsql.connect(":memory:", autocommit=0)
+    await conn.execute("CREATE TABLE t (x)")
+    await conn.execute("INSERT INTO t VALUES (1)")
+    assert conn.in_transaction
+    await conn.executescript("INSERT INTO t VALUES (2);")
+    await conn.rollback()
+    cursor = await conn.execute("SELECT x FROM t ORDER BY x")
+    assert await cursor.fetchall() == [(1,), (2,)]
+
+    await conn.execute("BEGIN")
+    await conn.executescript("INSERT INTO t VALUES (3); INSERT INTO t VALUES (4);", commit=False)
+    assert conn.in_transaction
+    await conn.rollback()
+    cursor = await conn.execute("SELECT COUNT(*) FROM t")
+    assert await cursor.fetchone() == (2,)
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_connect_pragma_kwargs(tmp_path):
+    conn = await aiolibsql.connect(
+        str(tmp_path / "p.db"),
+        journal_mode="wal",
+        synchronous="normal",
+        foreign_keys=True,
+        cache_size=-4000,
+        mmap_size=0,
+        wal_autocheckpoint=5000,
+    )
+    for pragma, expected in [
+        ("journal_mode", "wal"),
+        ("synchronous", 1),
+        ("foreign_keys", 1),
+        ("cache_size", -4000),
+        ("mmap_size", 0),
+        ("wal_autocheckpoint", 5000),
+    ]:
+        cursor = await conn.execute(f"PRAGMA {pragma}")
+        assert await cursor.fetchone() == (expected,)
+    await conn.set_wal_autocheckpoint(0)
+    cursor = await conn.execute("PRAGMA wal_autocheckpoint")
+    assert await cursor.fetchone() == (0,)
+    await conn.close()
+
+    with pytest.raises(ValueError, match="journal_mode"):
+        await aiolibsql.connect(":memory:", journal_mode="wal; DROP TABLE t")
+
+
+@pytest.mark.asyncio
+async def test_rowcount_is_minus_one_for_select():
+    conn = await aiolibsql.connect(":memory:", autocommit=1)
+    await conn.execute("CREATE TABLE t (x)")
+    cursor = await conn.execute("INSERT INTO t VALUES (1), (2)")
+    assert cursor.rowcount == 2
+    await cursor.execute("SELECT * FROM t")
+    assert cursor.rowcount == -1
+    await cursor.execute("UPDATE t SET x = x + 1 WHERE x = 1")
+    assert cursor.rowcount == 1
+    await conn.close()
+
+    pool = await aiolibsql.create_pool(":memory:", size=2)
+    cursor = await pool.execute("SELECT 1")
+    assert cursor.rowcount == -1
+    await pool.close()
+
+
+@pytest.mark.asyncio
+async def test_lastrowid_none_after_non_insert():
+    conn = await aiolibsql.connect(":memory:", autocommit=1)
+    cursor = conn.cursor()
+    assert cursor.lastrowid is None
+    await cursor.execute("CREATE TABLE t (x)")
+    assert cursor.lastrowid is None
+    await cursor.execute("INSERT INTO t VALUES (1)")
+    assert cursor.lastrowid == 1
+    await cursor.execute("SELECT * FROM t")
+    assert cursor.lastrowid is None
+    await cursor.execute("REPLACE INTO t (rowid, x) VALUES (5, 2)")
+    assert cursor.lastrowid == 5
+    with pytest.raises(aiolibsql.Error):
+        await cursor.execute("INSERT INTO missing VALUES (1)")
+    assert cursor.lastrowid is None
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_transaction_context_manager():
+    conn = await aiolibsql.connect(":memory:", autocommit=1)
+    await conn.execute("CREATE TABLE t (x)")
+    async with conn.transaction():
+        await conn.execute("INSERT INTO t VALUES (1)")
+        assert conn.in_transaction
+    assert not conn.in_transaction
+
+    with pytest.raises(RuntimeError):
+        async with conn.transaction("immediate"):
+            await conn.execute("INSERT INTO t VALUES (2)")
+            raise RuntimeError("boom")
+    assert not conn.in_transaction
+    cursor = await conn.execute("SELECT x FROM t")
+    assert await cursor.fetchall() == [(1,)]
+
+    async with conn.transaction():
+        with pytest.raises(aiolibsql.ProgrammingError):
+            async with conn.transaction():
+                pass
+    with pytest.raises(ValueError):
+        conn.transaction("sometimes")
+    await conn.close()
+
+
+def test_sync_transaction():
+    from aiolibsql import sync
+
+    conn = sync.connect(":memory:", autocommit=1)
+    conn.execute("CREATE TABLE t (x)")
+    with pytest.raises(RuntimeError):
+        with conn.transaction():
+            conn.execute("INSERT INTO t VALUES (1)")
+            raise RuntimeError("boom")
+    with conn.transaction():
+        conn.execute("INSERT INTO t VALUES (2)")
+    assert conn.execute("SELECT x FROM t").fetchall() == [(2,)]
+    conn.close()
+
+
+@pytest.mark.asyncio
+async def test_batch():
+    conn = await aiolibsql.connect(":memory:", autocommit=1)
+    results = await conn.batch([
+        "CREATE TABLE t (x)",
+        "INSERT INTO t VALUES (1), (2);",
+        "SELECT x FROM t ORDER BY x",
+    ])
+    assert results == [[], [], [(1,), (2,)]]
+
+    with pytest.raises(aiolibsql.Error):
+        await conn.batch(["INSERT INTO t VALUES (3)", "INSERT INTO missing VALUES (1)"])
+    cursor = await conn.execute("SELECT COUNT(*) FROM t")
+    assert await cursor.fetchone() == (2,)
+
+    with pytest.raises(aiolibsql.ProgrammingError):
+        await conn.batch(["SELECT 1; SELECT 2"])
+    assert await conn.batch([]) == []
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_connection_reset():
+    conn = await aiolibsql.connect(":memory:", autocommit=1)
+    await conn.execute("CREATE TABLE t (x)")
+    await conn.execute("INSERT INTO t VALUES (1), (2)")
+    pending = await conn.execute("SELECT x FROM t")
+    await conn.execute("BEGIN")
+    await conn.execute("INSERT INTO t VALUES (3)")
+    await conn.reset()
+    assert not conn.in_transaction
+    assert await pending.fetchall() == []
+    cursor = await conn.execute("SELECT COUNT(*) FROM t")
+    assert await cursor.fetchone() == (2,)
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_connection_ping():
+    conn = await aiolibsql.connect(":memory:")
+    latency = await conn.ping()
+    assert isinstance(latency, float) and latency >= 0
+    await conn.close()
+    with pytest.raises(ValueError):
+        await conn.ping()
+
+
+class FakeHrana:
+    """A minimal Hrana-over-HTTP server for remote connection tests.
+
+    Every statement succeeds and a `SELECT` returns the single row `(1,)`. Statements the
+    server ran are recorded in `executed`; `expire()` makes every baton handed out so far
+    invalid, and a statement listed in `drop_response` runs but its connection is closed
+    before the response is sent.
+    """
+
+    def __init__(self):
+        self.executed = []
+        self.drop_response = set()
+        self.issued = 0
+        self.expired = set()
+        self.server = ThreadingHTTPServer(("127.0.0.1", 0), self._handler())
+        threading.Thread(target=self.server.serve_forever, daemon=True).start()
+        self.url = "http://127.0.0.1:%d" % self.server.server_address[1]
+
+    def expire(self):
+        self.expired.update("b%d" % i for i in range(1, self.issued + 1))
+
+    def close(self):
+        self.server.shutdown()
+        self.server.server_close()
+
+    def _baton(self):
+        self.issued += 1
+        return "b%d" % self.issued
+
+    def _run(self, stmts):
+        self.executed.extend(s["sql"] for s in stmts)
+        return any(s["sql"] in self.drop_response for s in stmts)
+
+    def _handler(self):
+        fake = self
+
+        class Handler(BaseHTTPRequestHandler):
+            def log_message(self, *args):
+                pass
+
+            def reply(self, status, body):
+                data = body.encode()
+                self.send_response(status)
+                self.send_header("Content-Type", "application/json")
+                self.send_header("Content-Length", str(len(data)))
+                self.end_headers()
+                self.wfile.write(data)
+
+            def do_POST(self):
+                body = json.loads(self.rfile.read(int(self.headers["Content-Length"])))
+                if body.get("baton") in fake.expired:
+                    error = {"message": "The stream has expired due to inactivity", "code": "STREAM_EXPIRED"}
+                    return self.reply(400, json.dumps(error))
+                if self.path.startswith("/v3/cursor"):
+                    return self.cursor(body["batch"]["steps"])
+                results, close = [], False
+                for req in body["requests"]:
+                    kind = req["type"]
+                    if kind == "describe":
+                        reads = req["sql"].lstrip().upper().startswith("SELECT")
+                        cols = [{"name": "x", "decltype": None}] if reads else []
+                        response = {"params": [], "cols": cols, "is_explain": False, "is_readonly": reads}
+                        response = {"type": "describe", "result": response}
+                    elif kind in ("execute", "batch"):
+                        stmts = [req["stmt"]] if kind == "execute" else [s["stmt"] for s in req["batch"]["steps"]]
+                        if fake._run(stmts):
+                            self.close_connection = True
+                            self.connection.shutdown(socket.SHUT_RDWR)
+                            return
+                        result = {"cols": [], "rows": [], "affected_row_count": 1, "last_insert_rowid": None}
+                        if kind == "execute":
+                            response = {"type": "execute", "result": result}
+                        else:
+                            batch = {"step_results": [result] * len(stmts), "step_errors": [None] * len(stmts)}
+                            response = {"type": "batch", "result": batch}
+                    elif kind == "get_autocommit":
+                        response = {"type": "get_autocommit", "is_autocommit": True}
+                    else:
+                        close = close or kind == "close"
+                        response = {"type": kind}
+                    results.append({"type": "ok", "response": response})
+                baton = None if close else fake._baton()
+                self.reply(200, json.dumps({"baton": baton, "base_url": None, "results": results}))
+
+            def cursor(self, steps):
+                fake._run([s["stmt"] for s in steps])
+                lines = [{"baton": fake._baton(), "base_url": None}]
+                for i, _ in enumerate(steps):
+                    lines.append({"type": "step_begin", "step": i, "cols": [{"name": "x", "decltype": None}]})
+                    lines.append({"type": "row", "row": [{"type": "integer", "value": "1"}]})
+                    lines.append({"type": "step_end", "affected_row_count": 0, "last_insert_rowid": None})
+                self.reply(200, "".join(json.dumps(line) + "\n" for line in lines))
+
+        return Handler
+
+
+@pytest.fixture
+def fake_hrana():
+    server = FakeHrana()
+    yield server
+    server.close()
+
+
+@pytest.mark.asyncio
+async def test_remote_expired_stream_is_replayed(fake_hrana):
+    conn = await aiolibsql.connect(fake_hrana.url, autocommit=1)
+    cursor = await conn.execute("SELECT 1")
+    assert await cursor.fetchall() == [(1,)]
+    # The cursor left its stream open; the server drops it before the next statement.
+    fake_hrana.expire()
+    await conn.execute("INSERT INTO t VALUES (1)")
+    assert fake_hrana.executed == ["SELECT 1", "INSERT INTO t VALUES (1)"]
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_remote_lost_response_is_not_replayed(fake_hrana):
+    conn = await aiolibsql.connect(fake_hrana.url, autocommit=1)
+    fake_hrana.drop_response.add("INSERT INTO t VALUES (2)")
+    with pytest.raises(aiolibsql.Error):
+        await conn.execute("INSERT INTO t VALUES (2)")
+    assert fake_hrana.executed == ["INSERT INTO t VALUES (2)"]
+
+    # A script on an expired stream fails as a whole instead of being sent again.
+    cursor = await conn.execute("SELECT 1")
+    await cursor.fetchall()
+    fake_hrana.expire()
+    with pytest.raises(aiolibsql.Error, match="STREAM_EXPIRED"):
+        await conn.executescript("INSERT INTO t VALUES (3); INSERT INTO t VALUES (4);")
+    assert fake_hrana.executed == ["INSERT INTO t VALUES (2)", "SELECT 1"]
+    # The connection recovers on the next statement.
+    cursor = await conn.execute("SELECT 1")
+    assert await cursor.fetchall() == [(1,)]
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_remote_http_options():
+    conn = await aiolibsql.connect(
+        "http://127.0.0.1:9",
+        http_keepalive=30.0,
+        http_connect_timeout=1.0,
+        http2=True,
+    )
+    with pytest.raises(aiolibsql.Error, match="connect"):
+        await conn.ping()
+    await conn.close()
+    for option in ("http_keepalive", "http_connect_timeout"):
+        for value in (-1.0, float("nan")):
+            with pytest.raises(ValueError, match=option):
+                await aiolibsql.connect("http://127.0.0.1:9", **{option: value})
+
+
+@pytest.mark.asyncio
+async def test_websocket_url_falls_back_to_http():
+    with pytest.warns(aiolibsql.Warning, match="over HTTP: http://127.0.0.1:9"):
+        conn = await aiolibsql.connect("ws://127.0.0.1:9", http_connect_timeout=1.0)
+    with pytest.raises(aiolibsql.Error, match="connect"):
+        await conn.ping()
+    await conn.close()
+
+    with pytest.warns(aiolibsql.Warning) as record:
+        conn = await aiolibsql.connect("wss://127.0.0.1:9?authToken=secret", http_connect_timeout=1.0)
+    assert all("secret" not in str(w.message) for w in record)
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_update_hook():
+    conn = await aiolibsql.connect(":memory:", autocommit=1)
+    await conn.execute("CREATE TABLE t (x)")
+    events = []
+    conn.set_update_hook(lambda *args: events.append(args))
+    await conn.execute("INSERT INTO t VALUES (1)")
+    await conn.execute("UPDATE t SET x = 2")
+    await conn.execute("DELETE FROM t WHERE x = 2")
+    assert events == [
+        ("INSERT", "main", "t", 1),
+        ("UPDATE", "main", "t", 1),
+        ("DELETE", "main", "t", 1),
+    ]
+    conn.set_update_hook(None)
+    await conn.execute("INSERT INTO t VALUES (3)")
+    assert len(events) == 3
+    with pytest.raises(TypeError):
+        conn.set_update_hook(42)
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_changes_stream():
+    conn = await aiolibsql.connect(":memory:", autocommit=1)
+    await conn.execute("CREATE TABLE users (name TEXT)")
+    await conn.execute("CREATE TABLE other (x)")
+    seen = []
+    conn.set_update_hook(lambda *args: seen.append(args[0]))
+    stream = conn.changes_stream(tables=["users"])
+    await conn.execute("INSERT INTO users VALUES ('ann')")
+    await conn.execute("INSERT INTO other VALUES (1)")
+    await conn.execute("UPDATE users SET name = 'bob'")
+    await conn.execute("DELETE FROM users WHERE rowid = 1")
+
+    events = []
+    async for event in stream:
+        events.append(event)
+        if len(events) == 3:
+            break
+    assert [(e["op"], e["table"], e["rowid"]) for e in events] == [
+        ("INSERT", "users", 1),
+        ("UPDATE", "users", 1),
+        ("DELETE", "users", 1),
+    ]
+    assert events[0]["db"] == "main"
+    assert all(e["row"] is None for e in events)
+    assert seen == ["INSERT", "INSERT", "UPDATE", "DELETE"]
+
+    await conn.execute("INSERT INTO users VALUES ('cy')")
+    event = await stream.__anext__()
+    assert event["row"] == {"name": "cy"}
+    stream.close()
+    await conn.execute("INSERT INTO users VALUES ('dee')")
+    with pytest.raises(StopAsyncIteration):
+        await stream.__anext__()
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_in_clause_sequence_expansion(conn):
+    await conn.execute("CREATE TABLE t (x INTEGER, y TEXT)")
+    await conn.executescript("INSERT INTO t VALUES (0, '0'), (1, '1'), (2, '2'), (3, '3'), (4, '4')")
+    cur = await conn.execute("SELECT x FROM t WHERE x IN (?) AND y != ? ORDER BY x", ([1, 3, 4], "4"))
+    assert await cur.fetchall() == [(1,), (3,)]
+    cur = await conn.execute("SELECT x FROM t WHERE x IN (?)", ((),))
+    assert await cur.fetchall() == []
+    cur = await conn.execute("SELECT '?' FROM t WHERE x IN (?)", ([2],))
+    assert await cur.fetchall() == [("?",)]
+    with pytest.raises(aiolibsql.ProgrammingError):
+        await conn.execute("SELECT x FROM t WHERE x IN (?1)", ([1, 2],))
+    with pytest.raises(aiolibsql.ProgrammingError):
+        await conn.execute("SELECT x FROM t WHERE x IN (?)", (list(range(40000)),))
+
+
+@pytest.mark.asyncio
+async def test_in_clause_delete_is_split(conn):
+    await conn.execute("CREATE TABLE t (x INTEGER PRIMARY KEY)")
+    await conn.executemany("INSERT INTO t VALUES (?)", [(i,) for i in range(50000)])
+    ids = list(range(0, 80000, 2)) + [0, 2]
+    cur = await conn.execute("DELETE FROM t WHERE x IN (?) OR x = ?", (ids, 1))
+    assert cur.rowcount == 25001
+    cur = await conn.execute("SELECT COUNT(*) FROM t")
+    assert await cur.fetchone() == (24999,)
+    with pytest.raises(aiolibsql.ProgrammingError):
+        await conn.execute("DELETE FROM t WHERE x NOT IN (?)", (ids,))
+    with pytest.raises(aiolibsql.ProgrammingError):
+        await conn.execute("DELETE FROM t WHERE NOT (x > 0 AND x IN (?))", (ids,))
+    # Only a top-level `column IN (?)` next to conditions that don't read the table is split.
+    await conn.execute("CREATE TABLE keep (id INTEGER)")
+    for sql in [
+        "DELETE FROM t WHERE NOT EXISTS (SELECT 1 FROM keep WHERE keep.id = t.x AND keep.id IN (?))",
+        "DELETE FROM t WHERE (x IN (?)) = 0",
+        "DELETE FROM t WHERE x IN (?) IS FALSE",
+        "DELETE FROM t WHERE CASE WHEN x IN (?) THEN 0 ELSE 1 END",
+        "DELETE FROM t WHERE x IN (?) AND (SELECT count(*) FROM t) > 1",
+    ]:
+        with pytest.raises(aiolibsql.ProgrammingError):
+            await conn.execute(sql, (ids,))
+    cur = await conn.execute("SELECT COUNT(*) FROM t")
+    assert await cur.fetchone() == (24999,)
+    # a NOT elsewhere in the statement does not stop the split
+    odd = list(range(1, 80000, 2))
+    cur = await conn.execute("DELETE FROM t WHERE x IN (?) AND NOT x = ?", (odd, 3))
+    assert cur.rowcount == 24998
+    cur = await conn.execute("SELECT x FROM t")
+    assert await cur.fetchall() == [(3,)]
+
+    # bytearray values can't be de-duplicated but are still bound
+    await conn.execute("CREATE TABLE b (v BLOB)")
+    await conn.executemany("INSERT INTO b VALUES (?)", [(bytes([i]),) for i in range(10)])
+    blobs = [bytearray(i.to_bytes(3, "big")) for i in range(40000)] + [bytearray([1]), bytearray([2])]
+    cur = await conn.execute("DELETE FROM b WHERE v IN (?)", (blobs,))
+    assert cur.rowcount == 2
+
+
+@pytest.mark.asyncio
+async def test_invalid_utf8_text(conn):
+    await conn.execute("CREATE TABLE t (name TEXT)")
+    await conn.execute("INSERT INTO t VALUES ('ok'), (CAST(x'ff61' AS TEXT))")
+    cur = await conn.execute("SELECT name FROM t ORDER BY rowid")
+    assert await cur.fetchone() == ("ok",)
+    with pytest.raises(aiolibsql.DataError, match=r"column 1 \(name\).*UTF-8"):
+        await cur.fetchone()
+    cur = await conn.execute("SELECT CAST(name AS BLOB) FROM t ORDER BY rowid")
+    assert [r[0] for r in await cur.fetchall()] == [b"ok", b"\xffa"]
+
+
+@pytest.mark.asyncio
+@pytest.mark.parametrize("policy, expected", [("bytes", b"\xffa"), ("replace", "\ufffda")])
+async def test_text_errors_policy(tmp_path, policy, expected):
+    conn = await aiolibsql.connect(str(tmp_path / "t.db"), text_errors=policy)
+    try:
+        await conn.execute("CREATE TABLE t (id INTEGER, name TEXT)")
+        await conn.execute("INSERT INTO t VALUES (1, 'ok'), (2, CAST(x'ff61' AS TEXT))")
+        cur = await conn.execute("SELECT name, id FROM t ORDER BY rowid")
+        assert await cur.fetchall() == [("ok", 1), (expected, 2)]
+    finally:
+        await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_text_errors_surrogateescape_declined(tmp_path):
+    with pytest.raises(ValueError, match="surrogateescape"):
+        await aiolibsql.connect(str(tmp_path / "t.db"), text_errors="surrogateescape")
+
+
+@pytest.mark.asyncio
+async def test_dbapi_type_objects(conn):
+    import datetime
+
+    await conn.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, name VARCHAR(20), data BLOB, price REAL, at TIMESTAMP)")
+    cur = await conn.execute("SELECT id, name, data, price, at, 1 + 1 FROM t")
+    codes = [d[1] for d in cur.description]
+    assert codes[:5] == ["INTEGER", "VARCHAR(20)", "BLOB", "REAL", "TIMESTAMP"]
+    assert codes[0] == aiolibsql.NUMBER and codes[0] == aiolibsql.ROWID
+    assert codes[1] == aiolibsql.STRING and codes[1] != aiolibsql.NUMBER
+    assert codes[2] == aiolibsql.BINARY
+    assert codes[3] == aiolibsql.NUMBER
+    assert codes[4] == aiolibsql.DATETIME and codes[4] != aiolibsql.NUMBER
+    assert codes[5] is None and codes[5] != aiolibsql.NUMBER
+    await cur.close()
+
+    assert aiolibsql.Date(2024, 2, 29) == datetime.date(2024, 2, 29)
+    assert aiolibsql.Binary(b"\x00") == b"\x00"
+    assert isinstance(aiolibsql.TimestampFromTicks(0), datetime.datetime)
+
+
+@pytest.mark.asyncio
+async def test_sqlite3_constants():
+    import datetime
+
+    assert aiolibsql.apilevel == "2.0"
+    assert aiolibsql.threadsafety == 1
+    assert (aiolibsql.PARSE_DECLTYPES, aiolibsql.PARSE_COLNAMES) == (1, 2)
+    assert (aiolibsql.SQLITE_OK, aiolibsql.SQLITE_BUSY, aiolibsql.SQLITE_DONE) == (0, 5, 101)
+    assert (aiolibsql.SQLITE_DENY, aiolibsql.SQLITE_READ, aiolibsql.SQLITE_RECURSIVE) == (1, 20, 33)
+
+    conn = await aiolibsql.connect(":memory:", detect_types=aiolibsql.PARSE_COLNAMES)
+    cur = await conn.execute("SELECT '2024-02-29' AS \"d [date]\", 'x' AS \"s [text]\"")
+    assert [d[0] for d in cur.description] == ["d", "s"]
+    assert await cur.fetchone() == (datetime.date(2024, 2, 29), "x")
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_cursor_factory(conn):
+    class LoggingCursor(aiolibsql.Cursor):
+        def __init__(self, connection):
+            self.statements = []
+
+        async def execute(self, sql, parameters=None):
+            self.statements.append(sql)
+            return await super().execute(sql, parameters)
+
+    cur = conn.cursor(factory=LoggingCursor)
+    assert isinstance(cur, LoggingCursor)
+    assert await cur.execute("SELECT 1") is cur
+    assert await cur.fetchall() == [(1,)]
+    assert cur.statements == ["SELECT 1"]
+    assert type(conn.cursor()) is aiolibsql.Cursor
+    with pytest.raises(TypeError):
+        conn.cursor(factory=lambda c: object())
+
+
+@pytest.mark.asyncio
+async def test_connection_factory():
+    class AppConnection(aiolibsql.Connection):
+        def __init__(self, connection):
+            self.queries = 0
+
+        async def count(self, table):
+            self.queries += 1
+            cur = await self.execute(f"SELECT count(*) FROM {table}")
+            return (await cur.fetchone())[0]
+
+    conn = await aiolibsql.connect(":memory:", factory=AppConnection)
+    assert isinstance(conn, AppConnection)
+    await conn.execute("CREATE TABLE t (x)")
+    await conn.execute("INSERT INTO t VALUES (1)")
+    assert await conn.count("t") == 1
+    assert conn.queries == 1
+    await conn.close()
+
+    with pytest.raises(TypeError):
+        await aiolibsql.connect(":memory:", factory=lambda c: object())
+
+
+@pytest.mark.asyncio
+async def test_prefetch_rows():
+    conn = await aiolibsql.connect(":memory:", prefetch=3)
+    await conn.execute("CREATE TABLE t (x INTEGER)")
+    await conn.executescript("WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 10) INSERT INTO t SELECT i FROM n")
+    cur = await conn.execute("SELECT x FROM t ORDER BY x")
+    assert cur.prefetch == 3
+    await asyncio.sleep(0.01)
+    assert await cur.fetchone() == (1,)
+    assert await cur.fetchmany(4) == [(2,), (3,), (4,), (5,)]
+    assert [row async for row in cur] == [(i,) for i in range(6, 11)]
+
+    cur = conn.cursor()
+    cur.prefetch = 0
+    await cur.execute("SELECT x FROM t ORDER BY x")
+    assert len(await cur.fetchall()) == 10
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_buffered_execute(conn):
+    await conn.execute("CREATE TABLE t (x INTEGER)")
+    await conn.executescript("INSERT INTO t VALUES (1), (2), (3)")
+    cur = await conn.execute("SELECT x FROM t ORDER BY x", buffered=True)
+    # the statement is already finished, so the table can be dropped
+    await conn.execute("DROP TABLE t")
+    assert await cur.fetchone() == (1,)
+    assert await cur.fetchall() == [(2,), (3,)]
+
+    with pytest.raises(aiolibsql.Error):
+        await conn.execute("SELECT CAST(abs(-9223372036854775807 - 1) AS INTEGER)", buffered=True)
+
+
+@pytest.mark.asyncio
+async def test_result_limits():
+    conn = await aiolibsql.connect(":memory:", max_rows=3)
+    await conn.execute("CREATE TABLE t (x TEXT)")
+    await conn.executescript("INSERT INTO t VALUES ('aaaa'), ('bbbb'), ('cccc'), ('dddd')")
+    cur = await conn.execute("SELECT x FROM t")
+    assert cur.max_rows == 3
+    assert len(await cur.fetchmany(3)) == 3
+    with pytest.raises(aiolibsql.DataError):
+        await cur.fetchone()
+
+    with pytest.raises(aiolibsql.DataError):
+        await conn.execute("SELECT x FROM t", buffered=True)
+
+    cur = conn.cursor()
+    cur.max_rows = None
+    cur.max_result_bytes = 10
+    await cur.execute("SELECT x FROM t")
+    assert await cur.fetchmany(2) == [("aaaa",), ("bbbb",)]
+    with pytest.raises(aiolibsql.DataError, match="max_result_bytes"):
+        await cur.fetchall()
+    assert issubclass(aiolibsql.DataError, aiolibsql.DatabaseError)
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_heap_limits():
+    # the limits are process-wide and the hard one can only be lowered, so keep it generous
+    conn = await aiolibsql.connect(":memory:", soft_heap_limit=8 * 1024 * 1024, hard_heap_limit=1 << 32)
+    cur = await conn.execute("PRAGMA soft_heap_limit")
+    assert await cur.fetchone() == (8 * 1024 * 1024,)
+    cur = await conn.execute("PRAGMA hard_heap_limit")
+    assert await cur.fetchone() == (1 << 32,)
+    await conn.execute("PRAGMA soft_heap_limit=0")
+    await conn.close()
+
+    with pytest.raises(ValueError):
+        await aiolibsql.connect("libsql://example.turso.io", hard_heap_limit=1024)
+
+
+@pytest.mark.asyncio
+async def test_initial_sync_options(tmp_path):
+    path = str(tmp_path / "replica.db")
+    with pytest.raises(aiolibsql.OperationalError, match="not been bootstrapped"):
+        await aiolibsql.connect(path, sync_url="http://127.0.0.1:1", initial_sync="skip")
+    with pytest.raises(ValueError):
+        await aiolibsql.connect(path, sync_url="http://127.0.0.1:1", initial_sync="later")
+    for option in ("sync_timeout", "sync_interval"):
+        for value in (-1.0, float("nan")):
+            with pytest.raises(ValueError, match=option):
+                await aiolibsql.connect(path, sync_url="http://127.0.0.1:1", **{option: value})
+
+    conn = await aiolibsql.connect(":memory:")
+    info = await conn.replication_info()
+    assert info["bootstrap_seconds"] is None
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_optimize_and_vacuum(tmp_path):
+    path = str(tmp_path / "main.db")
+    conn = await aiolibsql.connect(path, optimize_on_close=True)
+    await conn.execute("CREATE TABLE t (x)")
+    await conn.executescript("INSERT INTO t VALUES (randomblob(10000)); DELETE FROM t")
+    await conn.optimize()
+    await conn.vacuum()
+    copy = tmp_path / "copy.db"
+    await conn.vacuum(into=copy)
+    assert copy.exists()
+
+    await conn.execute("BEGIN")
+    with pytest.raises(aiolibsql.ProgrammingError):
+        await conn.vacuum()
+    await conn.rollback()
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_integrity_check(tmp_path):
+    conn = await aiolibsql.connect(str(tmp_path / "check.db"))
+    await conn.execute("CREATE TABLE t (x INTEGER)")
+    await conn.execute("CREATE INDEX t_x ON t (x)")
+    await conn.executescript("INSERT INTO t VALUES (1), (2), (3)")
+    assert await conn.integrity_check() == {"ok": True, "problems": []}
+    assert await conn.quick_check() == {"ok": True, "problems": []}
+
+    # corrupt the index by editing the schema behind SQLite's back
+    await conn.executescript(
+        "PRAGMA writable_schema=ON;"
+        "UPDATE sqlite_schema SET sql = 'CREATE INDEX t_x ON t (x DESC)' WHERE name = 't_x';"
+        "PRAGMA writable_schema=OFF;"
+    )
+    await conn.close()
+    conn = await aiolibsql.connect(str(tmp_path / "check.db"))
+    result = await conn.integrity_check(max_errors=1)
+    assert result["ok"] is False
+    assert len(result["problems"]) == 1
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_db_stats(tmp_path):
+    conn = await aiolibsql.connect(str(tmp_path / "stats.db"))
+    await conn.executescript("PRAGMA journal_mode=WAL; CREATE TABLE t (x BLOB)")
+    await conn.executescript("INSERT INTO t VALUES (zeroblob(10000)), (zeroblob(10000))")
+    stats = await conn.db_stats()
+    assert stats["journal_mode"] == "wal"
+    assert stats["page_size"] > 0
+    assert stats["page_count"] > 1
+    assert stats["freelist_count"] == 0
+    assert stats["file"].endswith("stats.db")
+    assert stats["wal_size"] > 0
+    assert stats["wal_frames"] > 0
+    assert stats["checkpoint_seq"] is not None
+
+    await conn.execute("DELETE FROM t")
+    stats = await conn.db_stats()
+    assert stats["freelist_count"] > 0
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_returning_rowcount_and_lastrowid():
+    conn = await aiolibsql.connect(":memory:")
+    await conn.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT)")
+
+    cursor = await conn.execute("INSERT INTO t (name) VALUES ('a'), ('b') RETURNING id, name")
+    assert cursor.rowcount == 2
+    assert cursor.lastrowid == 2
+    assert await cursor.fetchall() == [(1, "a"), (2, "b")]
+    assert cursor.description[0][0] == "id"
+
+    cursor = await conn.execute("UPDATE t SET name = upper(name) RETURNING name")
+    assert cursor.rowcount == 2
+    assert cursor.lastrowid is None
+    assert sorted(await cursor.fetchall()) == [("A",), ("B",)]
+
+    cursor = await conn.execute("DELETE FROM t WHERE id = ? RETURNING id", (1,))
+    assert cursor.rowcount == 1
+    assert await cursor.fetchone() == (1,)
+    await conn.commit()
+    assert await (await conn.execute("SELECT count(*) FROM t")).fetchone() == (1,)
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_executemany_returning():
+    conn = await aiolibsql.connect(":memory:")
+    await conn.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT)")
+    cursor = await conn.executemany(
+        "INSERT INTO t (name) VALUES (?) RETURNING id, name", [("a",), ("b",), ("c",)]
+    )
+    assert cursor.rowcount == 3
+    assert cursor.lastrowid == 3
+    assert cursor.description[1][0] == "name"
+    assert await cursor.fetchall() == [(1, "a"), (2, "b"), (3, "c")]
+
+    cursor = await conn.executemany("INSERT INTO t (name) VALUES (?)", [("d",), ("e",)])
+    assert cursor.rowcount == 2
+    assert cursor.description is None
+    assert await (await conn.execute("SELECT count(*) FROM t")).fetchone() == (5,)
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_insert_many():
+    conn = await aiolibsql.connect(":memory:")
+    await conn.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT)")
+
+    inserted = await conn.insert_many("t", ((i, f"n{i}") for i in range(1, 1001)), batch_size=300)
+    assert inserted == 1000
+    assert await (await conn.execute("SELECT count(*), max(id) FROM t")).fetchone() == (1000, 1000)
+
+    rows = [{"id": 1, "name": "dup"}, {"id": 1001, "name": "new"}]
+    assert await conn.insert_many("t", rows, on_conflict="ignore") == 1
+    assert await conn.insert_many("t", [("x",)], columns=["name"]) == 1
+    assert await conn.insert_many("t", []) == 0
+
+    with pytest.raises(ValueError):
+        await conn.insert_many("t", [(2000, "a"), (2001,)])
+    assert await (await conn.execute("SELECT count(*) FROM t")).fetchone() == (1002,)
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_prepared_statement():
+    conn = await aiolibsql.connect(":memory:")
+    await conn.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT)")
+
+    insert = await conn.prepare("INSERT INTO t (id, name) VALUES (?, ?)")
+    assert isinstance(insert, aiolibsql.Statement)
+    for i in range(5):
+        assert await insert.execute((i, f"n{i}")) == 1
+
+    select = await conn.prepare("SELECT id, name FROM t WHERE id >= ? ORDER BY id")
+    assert select.columns == ["id", "name"]
+    assert await select.query((3,)) == [(3, "n3"), (4, "n4")]
+    assert await select.query([4]) == [(4, "n4")]
+    select.reset()
+
+    update = await conn.prepare("UPDATE t SET name = upper(name) WHERE id < ?")
+    assert await update.execute((2,)) == 2
+
+    insert.finalize()
+    with pytest.raises(aiolibsql.ProgrammingError):
+        await insert.execute((9, "x"))
+    with pytest.raises(aiolibsql.ProgrammingError):
+        await conn.prepare("SELECT 1; SELECT 2")
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_parameter_introspection():
+    conn = await aiolibsql.connect(":memory:")
+    stmt = await conn.prepare("SELECT :name, @age, $city, ?")
+    assert stmt.parameter_count == 4
+    assert stmt.parameter_names == ["name", "age", "city", None]
+
+    stmt = await conn.prepare("SELECT 1")
+    assert stmt.parameter_count == 0
+    assert stmt.parameter_names == []
+
+    cursor = await conn.execute("SELECT ?, ?", (1, 2))
+    assert cursor.parameter_count == 2
+    assert cursor.parameter_names == [None, None]
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_column_metadata():
+    conn = await aiolibsql.connect(":memory:")
+    await conn.execute("CREATE TABLE users (id INTEGER PRIMARY KEY, name VARCHAR(20))")
+    cursor = await conn.execute("SELECT id AS user_id, name, 1 + 1 AS two FROM users")
+    meta = cursor.column_metadata
+    assert meta[0] == {
+        "name": "user_id",
+        "decltype": "INTEGER",
+        "table": "users",
+        "column": "id",
+        "database": "main",
+    }
+    assert meta[1]["decltype"] == "VARCHAR(20)"
+    assert meta[2] == {"name": "two", "decltype": None, "table": None, "column": None, "database": None}
+
+    stmt = await conn.prepare("SELECT name FROM users")
+    assert stmt.column_metadata[0]["column"] == "name"
+
+    cursor = await conn.execute("INSERT INTO users (name) VALUES ('a')")
+    assert cursor.column_metadata is None
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_close_on_exit():
+    async with await aiolibsql.connect(":memory:", close_on_exit=True) as conn:
+        await conn.execute("CREATE TABLE t (x INTEGER)")
+        cursor = await conn.execute("SELECT 1")
+    with pytest.raises(ValueError, match="closed"):
+        await conn.db_stats()
+    assert await cursor.fetchall() == []
+
+    async with await aiolibsql.connect(":memory:") as conn:
+        pass
+    await conn.execute("SELECT 1")
+    await conn.close()
+
+
+async def _cancel_after(coro, steps):
+    task = asyncio.ensure_future(coro)
+    for _ in range(steps):
+        await asyncio.sleep(0)
+    task.cancel()
+    try:
+        return await task
+    except asyncio.CancelledError:
+        return None
+
+
+@pytest.mark.asyncio
+async def test_cancellation_leaves_consistent_state():
+    conn = await aiolibsql.connect(":memory:", prefetch=2)
+    await conn.executescript("CREATE TABLE t (x INTEGER); " + "".join(
+        f"INSERT INTO t VALUES ({i});" for i in range(50)
+    ))
+
+    # cancelled writes either commit or roll back, and never leave a transaction behind
+    cursor = conn.cursor()
+    for steps in range(10):
+        await _cancel_after(cursor.execute("INSERT INTO t VALUES (?)", (100 + steps,)), steps)
+        check = await asyncio.wait_for(conn.execute("SELECT count(*) FROM t"), 5)
+        assert (await check.fetchone())[0] >= 50
+        await conn.execute("BEGIN")  # fails if a transaction was left open
+        await conn.rollback()
+
+    # a cancelled fetch keeps the rows it was still reading; a batch it had already taken is
+    # lost whole, and the rest are never repeated or reordered
+    cursor = await conn.execute("SELECT x FROM t WHERE x < 50 ORDER BY x")
+    seen = []
+    for steps in range(10):
+        rows = await _cancel_after(cursor.fetchmany(3), steps % 4)
+        if rows is not None:
+            assert len(rows) == 3 and rows[0][0] % 3 == 0
+            seen.extend(r[0] for r in rows)
+    rest = [r[0] for r in await asyncio.wait_for(cursor.fetchall(), 5)]
+    assert rest == list(range(rest[0], 50)) and rest[0] % 3 == 0
+    for start in range(0, rest[0], 3):
+        batch = [start, start + 1, start + 2]
+        assert all(x in seen for x in batch) or not any(x in seen for x in batch)
+    assert await cursor.fetchone() is None
+
+    # a cancelled execute leaves the previous result in place, with its own description
+    cursor = await conn.execute("SELECT x FROM t WHERE x < 3 ORDER BY x")
+    for steps in range(3):
+        await _cancel_after(cursor.execute("SELECT 'other'"), steps)
+    expected = {"x": [(0,), (1,), (2,)], "'other'": [("other",)]}
+    rows = await asyncio.wait_for(cursor.fetchall(), 5)
+    assert rows == expected[cursor.description[0][0]]
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_cursor_metadata_does_not_block():
+    conn = await aiolibsql.connect(":memory:", prefetch=1)
+    await conn.executescript("CREATE TABLE t (x INTEGER); INSERT INTO t VALUES (1), (2)")
+    cursor = await conn.execute("SELECT x FROM t")
+    # a statement running on another task holds the connection lock, not the cursor's metadata
+    task = asyncio.ensure_future(conn.execute(
+        "WITH RECURSIVE c(n) AS (SELECT 1 UNION ALL SELECT n + 1 FROM c WHERE n < 200000) SELECT count(*) FROM c"
+    ))
+    await asyncio.sleep(0)
+    assert cursor.description[0][0] == "x"
+    assert cursor.rowcount == -1
+    assert cursor.lastrowid is None
+    await task
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_fetchall_converts_in_chunks():
+    conn = await aiolibsql.connect(":memory:")
+    cursor = await conn.execute(
+        "WITH RECURSIVE c(n) AS (SELECT 1 UNION ALL SELECT n + 1 FROM c WHERE n < 25000) SELECT n, 'row' || n FROM c"
+    )
+    ticks = 0
+
+    async def ticker():
+        nonlocal ticks
+        while True:
+            ticks += 1
+            await asyncio.sleep(0)
+
+    task = asyncio.ensure_future(ticker())
+    rows = await cursor.fetchall()
+    task.cancel()
+    assert len(rows) == 25000
+    assert rows[0] == (1, "row1") and rows[-1] == (25000, "row25000")
+    assert ticks > 1
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_buffer_protocol_blobs():
+    import array
+
+    conn = await aiolibsql.connect(":memory:")
+    await conn.execute("CREATE TABLE t (b BLOB)")
+    data = b"\x00\x01\x02\x03\xff"
+    values = [
+        bytearray(data),
+        memoryview(data),
+        memoryview(b"xx" + data)[2:],
+        array.array("B", data),
+    ]
+    for value in values:
+        await conn.execute("INSERT INTO t VALUES (?)", (value,))
+    rows = await (await conn.execute("SELECT b, typeof(b) FROM t")).fetchall()
+    assert rows == [(data, "blob")] * len(values)
+
+    cursor = await conn.execute("SELECT ?", (array.array("H", [1, 2]),))
+    assert await cursor.fetchone() == (array.array("H", [1, 2]).tobytes(),)
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_write_detection_with_cte_and_comments():
+    conn = await aiolibsql.connect(":memory:")
+    await conn.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, n INTEGER)")
+    await conn.commit()
+
+    cursor = await conn.execute(
+        "WITH src(n) AS (SELECT 7) INSERT INTO t (n) SELECT n FROM src"
+    )
+    assert cursor.rowcount == 1
+    assert cursor.lastrowid == 1
+
+    cursor = await conn.execute(
+        "-- upsert\n/* keep the newest */ INSERT INTO t (id, n) VALUES (1, 8) "
+        "ON CONFLICT (id) DO UPDATE SET n = excluded.n"
+    )
+    assert cursor.rowcount == 1
+    assert cursor.lastrowid == 1
+
+    cursor = await conn.execute("WITH x(v) AS (SELECT 'insert into') SELECT v FROM x")
+    assert cursor.rowcount == -1
+    assert await cursor.fetchall() == [("insert into",)]
+    await conn.rollback()
+    assert await (await conn.execute("SELECT n FROM t")).fetchall() == [(8,)]
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_pragma_assignment_is_a_write():
+    conn = await aiolibsql.connect(":memory:", autocommit=1, result_cache_size=8)
+    await conn.execute("CREATE TABLE t (x INTEGER)")
+    for sql in ["PRAGMA user_version = 3", "/* bump */ PRAGMA main.user_version(3)"]:
+        with pytest.raises(aiolibsql.ProgrammingError, match="read-only"):
+            await conn.query_cached(sql)
+    assert await conn.query_cached("PRAGMA user_version") == [(0,)]
+    assert [r[1] for r in await conn.query_cached("PRAGMA table_info(t)")] == ["x"]
+    await conn.execute("PRAGMA user_version = 3")
+    assert await (await conn.execute("PRAGMA user_version")).fetchall() == [(3,)]
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_ddl_participates_in_transactions(tmp_path):
+    conn = await aiolibsql.connect(str(tmp_path / "ddl.db"), autocommit=0)
+    await conn.execute("CREATE TABLE t (x INTEGER)")
+    with pytest.raises(ZeroDivisionError):
+        async with conn.transaction():
+            await conn.execute("ALTER TABLE t ADD COLUMN y TEXT")
+            await conn.execute("CREATE TABLE u (x INTEGER)")
+            await conn.execute("INSERT INTO t VALUES (1, 'a')")
+            1 / 0
+    cursor = await conn.execute("SELECT * FROM t")
+    assert [d[0] for d in cursor.description] == ["x"]
+    assert await cursor.fetchall() == []
+    tables = await (await conn.execute("SELECT name FROM sqlite_master WHERE name = 'u'")).fetchall()
+    assert tables == []
+
+    await conn.execute("BEGIN")
+    await conn.execute("DROP TABLE t")
+    await conn.rollback()
+    tables = await (await conn.execute("SELECT name FROM sqlite_master WHERE name = 't'")).fetchall()
+    assert tables == [("t",)]
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_isolation_level_setter(tmp_path):
+    path = str(tmp_path / "iso.db")
+    conn = await aiolibsql.connect(path)
+    other = await aiolibsql.connect(path, autocommit=1, timeout=0)
+    await conn.execute("CREATE TABLE t (x INTEGER)")
+
+    conn.isolation_level = "immediate"
+    assert conn.isolation_level == "IMMEDIATE"
+    with pytest.raises(ValueError):
+        conn.isolation_level = "SERIALIZABLE"
+    assert conn.isolation_level == "IMMEDIATE"
+
+    await conn.execute("BEGIN")
+    await conn.execute("INSERT INTO t VALUES (1)")
+    with pytest.raises(aiolibsql.ProgrammingError, match="commit or roll back"):
+        conn.isolation_level = None
+    assert conn.isolation_level == "IMMEDIATE"
+    assert conn.in_transaction
+    await conn.commit()
+    conn.isolation_level = None
+    assert conn.isolation_level is None
+    assert conn.autocommit == 1
+    await conn.execute("BEGIN")
+    await conn.rollback()
+    assert await (await other.execute("SELECT COUNT(*) FROM t")).fetchall() == [(1,)]
+
+    conn.isolation_level = "DEFERRED"
+    assert conn.autocommit == 0
+    await conn.execute("INSERT INTO t VALUES (2)")
+    assert await (await other.execute("SELECT COUNT(*) FROM t")).fetchall() == [(2,)]
+    await other.close()
+    await conn.close()
+
+    with pytest.raises(ValueError):
+        await aiolibsql.connect(":memory:", isolation_level="SNAPSHOT")
+
+
+@pytest.mark.asyncio
+async def test_error_context():
+    conn = await aiolibsql.connect(":memory:", autocommit=1)
+    await conn.execute("CREATE TABLE t (id INTEGER PRIMARY KEY)")
+
+    with pytest.raises(aiolibsql.OperationalError) as info:
+        await conn.execute("SELECT id\nFROM t\nWHERE id = 1 ORDR BY id")
+    message = str(info.value)
+    assert "line 3, column 14" in message
+    assert "SQL: SELECT id FROM t WHERE id = 1 ORDR BY id" in message
+
+    await conn.execute("INSERT INTO t VALUES (1)")
+    with pytest.raises(aiolibsql.IntegrityError) as info:
+        await conn.execute("INSERT INTO t VALUES (1) -- " + "x" * 300)
+    message = str(info.value)
+    assert "SQL: INSERT INTO t VALUES (1) -- xxx" in message
+    assert message.endswith("...")
+
+    with pytest.raises(OverflowError, match="error binding parameter 2"):
+        await conn.execute("SELECT ?, ?", (1, 2**70))
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_last_and_expanded_sql():
+    conn = await aiolibsql.connect(":memory:", autocommit=1)
+    cursor = conn.cursor()
+    assert cursor.last_sql is None and cursor.expanded_sql is None
+
+    await cursor.execute("CREATE TABLE t (a, b, c, d)")
+    await cursor.execute(
+        "INSERT INTO t VALUES (?, ?, ?, ?) -- '?'", (1, "it's", 2.5, b"\x01\xff")
+    )
+    assert cursor.last_sql == "INSERT INTO t VALUES (?, ?, ?, ?) -- '?'"
+    assert cursor.expanded_sql == "INSERT INTO t VALUES (1, 'it''s', 2.5, x'01ff') -- '?'"
+
+    await cursor.execute("SELECT * FROM t WHERE a IN (?) AND b = ?", ([1, 2], "x"))
+    assert cursor.last_sql == "SELECT * FROM t WHERE a IN (?, ?) AND b = ?"
+    assert cursor.expanded_sql == "SELECT * FROM t WHERE a IN (1, 2) AND b = 'x'"
+
+    await cursor.execute("SELECT :x, ?5, :x, ?", (1, None, None, None, 5, 6))
+    assert cursor.expanded_sql == "SELECT 1, 5, 1, 6"
+
+    await cursor.executemany("INSERT INTO t (a) VALUES (?)", [(1,), (2,)])
+    assert cursor.expanded_sql == "INSERT INTO t (a) VALUES (2)"
+
+    with pytest.raises(aiolibsql.OperationalError):
+        await cursor.execute("SELEC 1")
+    assert cursor.last_sql == "INSERT INTO t (a) VALUES (?)"
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_cursor_connection_and_open_cursors():
+    import gc
+
+    conn = await aiolibsql.connect(":memory:", autocommit=1)
+    cursor = conn.cursor()
+    assert cursor.connection is conn
+    assert (await conn.execute("SELECT 1")).connection is conn
+
+    await cursor.execute("WITH RECURSIVE n(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM n LIMIT 10) SELECT x FROM n")
+    idle = conn.cursor()
+    gc.collect()
+    entries = conn.open_cursors()
+    assert {"sql": cursor.last_sql, "active": True} in entries
+    assert {"sql": None, "active": False} in entries
+    await cursor.fetchall()
+    assert {"sql": cursor.last_sql, "active": False} in conn.open_cursors()
+    del idle
+    gc.collect()
+    assert {"sql": None, "active": False} not in conn.open_cursors()
+
+    await conn.close()
+    with pytest.raises(ValueError, match="closed"):
+        await cursor.execute("SELECT 1")
+    with pytest.raises(ValueError, match="closed"):
+        await cursor.executemany("SELECT ?", [(1,)])
+    assert conn.open_cursors() == []
+
+
+@pytest.mark.asyncio
+async def test_aiosqlite_namedtuple_rows():
+    from aiolibsql import aiosqlite
+
+    async with aiosqlite.connect(":memory:", autocommit=1) as db:
+        db.row_factory = "namedtuple"
+        await db.execute("CREATE TABLE users (id INTEGER, name TEXT)")
+        await db.executemany("INSERT INTO users VALUES (?, ?)", [(1, "ann"), (2, "bob")])
+        async with db.execute("SELECT id, name, id + 1, name FROM users ORDER BY id") as cursor:
+            rows = await cursor.fetchall()
+        assert rows[0].id == 1 and rows[1].name == "bob"
+        assert rows[0]._fields == ("id", "name", "_2", "_3")
+        assert type(rows[0]) is type(rows[1])
+        assert tuple(rows[0]) == (1, "ann", 2, "ann")
+
+        async with db.execute("SELECT name AS who FROM users WHERE id = 2") as cursor:
+            row = await cursor.fetchone()
+        assert row.who == "bob"
+
+
+@pytest.mark.asyncio
+async def test_fetch_into_models():
+    import dataclasses
+    import datetime
+    from decimal import Decimal
+    from typing import List, Optional
+
+    @dataclasses.dataclass
+    class Item:
+        id: int
+        price: Decimal
+        added: Optional[datetime.date]
+        tags: List[str]
+
+    conn = await aiolibsql.connect(":memory:", autocommit=1)
+    await conn.execute("CREATE TABLE items (id INTEGER, price TEXT, added TEXT)")
+    await conn.executemany(
+        "INSERT INTO items VALUES (?, ?, ?)", [(1, "9.50", "2024-02-29"), (2, "3", None)]
+    )
+    sql = "SELECT id, price, added, '' AS tags FROM items ORDER BY id"
+
+    cursor = await conn.execute(sql)
+    first = await cursor.fetch_into(Item)
+    assert first == Item(1, "9.50", "2024-02-29", "")
+    rest = await cursor.fetchall_into(Item, coerce=True)
+    assert rest == [Item(2, Decimal("3"), None, "")]
+    assert await cursor.fetch_into(Item) is None
+
+    items = await (await conn.execute(sql)).fetchall_into(Item, coerce=True)
+    assert items[0] == Item(1, Decimal("9.50"), datetime.date(2024, 2, 29), "")
+
+    with pytest.raises(TypeError):
+        await (await conn.execute("SELECT 1 AS unknown")).fetch_into(Item)
+    with pytest.raises(aiolibsql.ProgrammingError):
+        await (await conn.execute("UPDATE items SET id = id")).fetchall_into(Item)
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_numbered_placeholders():
+    conn = await aiolibsql.connect(":memory:", autocommit=1)
+    cursor = await conn.execute("SELECT ?2, ?1, ?2 || ?1", ("a", "b"))
+    assert await cursor.fetchall() == [("b", "a", "ba")]
+
+    with pytest.raises(aiolibsql.ProgrammingError, match="uses 3, and there are 2"):
+        await conn.execute("SELECT ?1, ?3", (1, 2))
+    with pytest.raises(aiolibsql.ProgrammingError, match="uses 1, and there are 2"):
+        await conn.execute("SELECT ?", (1, 2))
+    with pytest.raises(aiolibsql.ProgrammingError, match="uses 2, and there are 0"):
+        await conn.execute("SELECT ?1, ?2")
+
+    await conn.execute("CREATE TABLE t (a, b)")
+    await conn.executemany("INSERT INTO t VALUES (?1, ?1 * ?2)", [(2, 3), (4, 5)])
+    with pytest.raises(aiolibsql.ProgrammingError):
+        await conn.executemany("INSERT INTO t VALUES (?1, ?2)", [(6, 7), (8,)])
+    assert await (await conn.execute("SELECT a, b FROM t ORDER BY a")).fetchall() == [(2, 6), (4, 20), (6, 7)]
+
+    stmt = await conn.prepare("SELECT ?2 - ?1")
+    assert await stmt.query((1, 10)) == [(9,)]
+    with pytest.raises(aiolibsql.ProgrammingError):
+        await stmt.query((1,))
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_result_cache():
+    conn = await aiolibsql.connect(":memory:", autocommit=1, result_cache_size=2)
+    await conn.executescript("CREATE TABLE a (x); CREATE TABLE b (y); INSERT INTO a VALUES (1); INSERT INTO b VALUES (2);")
+
+    assert await conn.query_cached("SELECT x FROM a") == [(1,)]
+    assert await conn.query_cached("SELECT y FROM b WHERE y = ?", (2,)) == [(2,)]
+    assert await conn.query_cached("SELECT x FROM a") == [(1,)]
+    assert conn.metrics()["cache_hits"] == 1
+    assert conn.metrics()["cache_misses"] == 2
+
+    # Only results reading the written table are dropped.
+    await conn.execute("INSERT INTO a VALUES (3)")
+    assert await conn.query_cached("SELECT y FROM b WHERE y = ?", (2,)) == [(2,)]
+    assert await conn.query_cached("SELECT x FROM a") == [(1,), (3,)]
+    assert conn.metrics()["cache_hits"] == 2
+
+    # Writes the update hook doesn't report clear everything.
+    await conn.execute("DELETE FROM b")
+    assert await conn.query_cached("SELECT y FROM b WHERE y = ?", (2,)) == []
+    await conn.execute("CREATE INDEX a_x ON a (x)")
+    assert await conn.query_cached("SELECT y FROM b WHERE y = ?", (2,)) == []
+    assert conn.metrics()["cache_hits"] == 2
+
+    # Least recently used entries are evicted.
+    await conn.query_cached("SELECT x FROM a")
+    await conn.query_cached("SELECT 1")
+    await conn.query_cached("SELECT x FROM a")
+    assert conn.metrics()["cache_hits"] == 3
+    await conn.query_cached("SELECT y FROM b WHERE y = ?", (2,))
+    assert conn.metrics()["cache_hits"] == 3
+
+    conn.clear_result_cache()
+    await conn.query_cached("SELECT x FROM a")
+    assert conn.metrics()["cache_hits"] == 3
+
+    with pytest.raises(aiolibsql.ProgrammingError):
+        await conn.query_cached("DELETE FROM a")
+    await conn.close()
+
+    conn = await aiolibsql.connect(":memory:", autocommit=1, result_cache_size=8, result_cache_ttl=0.05)
+    await conn.query_cached("SELECT 1")
+    await conn.query_cached("SELECT 1")
+    await asyncio.sleep(0.1)
+    await conn.query_cached("SELECT 1")
+    assert conn.metrics()["cache_hits"] == 1
+    await conn.close()
+
+    for ttl in (-1, float("nan")):
+        with pytest.raises(ValueError, match="result_cache_ttl"):
+            await aiolibsql.connect(":memory:", result_cache_size=8, result_cache_ttl=ttl)
+
+
+@pytest.mark.asyncio
+async def test_busy_retries(tmp_path):
+    path = str(tmp_path / "busy.db")
+    holder = await aiolibsql.connect(path, autocommit=1)
+    await holder.execute("CREATE TABLE t (x)")
+    impatient = await aiolibsql.connect(path, autocommit=1, timeout=0)
+    patient = await aiolibsql.connect(path, autocommit=1, timeout=0, busy_retries=8, busy_retry_delay=0.02)
+
+    await holder.execute("BEGIN IMMEDIATE")
+    with pytest.raises(aiolibsql.TimeoutError):
+        await impatient.execute("INSERT INTO t VALUES (1)")
+
+    async def release():
+        await asyncio.sleep(0.1)
+        await holder.execute("COMMIT")
+
+    task = asyncio.create_task(release())
+    await patient.execute("INSERT INTO t VALUES (2)")
+    await task
+    assert patient.metrics()["busy_retries"] > 0
+    assert patient.metrics()["busy_errors"] == 0
+
+    await holder.execute("BEGIN IMMEDIATE")
+    task = asyncio.create_task(release())
+    async with patient.transaction("IMMEDIATE"):
+        await patient.execute("INSERT INTO t VALUES (3)")
+    await task
+
+    # Nothing is retried inside an explicit transaction.
+    await patient.execute("BEGIN")
+    await holder.execute("BEGIN IMMEDIATE")
+    retries = patient.metrics()["busy_retries"]
+    with pytest.raises(aiolibsql.TimeoutError):
+        await patient.execute("INSERT INTO t VALUES (4)")
+    assert patient.metrics()["busy_retries"] == retries
+    await patient.rollback()
+    await holder.rollback()
+
+    cursor = await holder.execute("SELECT x FROM t ORDER BY x")
+    assert await cursor.fetchall() == [(2,), (3,)]
+    with pytest.raises(ValueError):
+        await aiolibsql.connect(":memory:", busy_retry_delay=-1)
+    for conn in (holder, impatient, patient):
+        await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_busy_retry_after_automatic_rollback():
+    # A busy error that also rolled the explicit transaction back must not be replayed in
+    # autocommit mode: the write before it is gone, so the statement has to fail too.
+    conn = await aiolibsql.connect(":memory:", autocommit=1, busy_retries=3, busy_retry_delay=0.01)
+    await conn.executescript(
+        """
+        CREATE TABLE log (x);
+        CREATE TABLE t (x);
+        CREATE TRIGGER busy BEFORE INSERT ON t WHEN (SELECT COUNT(*) FROM log) > 0
+        BEGIN SELECT RAISE(ROLLBACK, 'database is locked'); END;
+        """
+    )
+    await conn.execute("BEGIN")
+    await conn.execute("INSERT INTO log VALUES (1)")
+    with pytest.raises(aiolibsql.TimeoutError):
+        await conn.execute("INSERT INTO t VALUES (2)")
+    assert not conn.in_transaction
+    assert conn.metrics()["busy_retries"] == 0
+    cursor = await conn.execute("SELECT (SELECT COUNT(*) FROM log), (SELECT COUNT(*) FROM t)")
+    assert await cursor.fetchone() == (0, 0)
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_concurrent_transaction():
+    assert issubclass(aiolibsql.ConflictError, aiolibsql.TimeoutError)
+    conn = await aiolibsql.connect(":memory:", autocommit=1)
+    with pytest.raises(ValueError):
+        conn.transaction("IMMEDIATE", concurrent=True)
+    # The bundled SQLite has no BEGIN CONCURRENT; the error says so instead of "syntax error".
+    with pytest.raises(aiolibsql.OperationalError, match="not supported"):
+        async with conn.transaction(concurrent=True):
+            pass
+    assert not conn.in_transaction
+    # Names that merely contain "conflict" are ordinary errors, not conflicts to retry.
+    await conn.execute("CREATE TABLE t (x)")
+    for sql in ("SELECT * FROM conflicts", "SELECT conflict_id FROM t"):
+        with pytest.raises(aiolibsql.OperationalError) as info:
+            await conn.execute(sql)
+        assert not isinstance(info.value, aiolibsql.ConflictError)
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_cancel_query():
+    conn = await aiolibsql.connect(":memory:", autocommit=1)
+    slow = (
+        "WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c) "
+        "SELECT count(*) FROM (SELECT x FROM c LIMIT 1000000000)"
+    )
+    runaway, queued = conn.cursor(), conn.cursor()
+    first = asyncio.ensure_future(runaway.execute(slow, buffered=True))
+    second = asyncio.ensure_future(queued.execute("SELECT 1"))
+    await asyncio.sleep(0.1)
+
+    queries = conn.queries()
+    assert [q["id"] for q in queries] == [runaway.query_id, queued.query_id]
+    assert queries[0]["sql"] == slow
+    assert queries[0]["running"] and not queries[1]["running"]
+
+    assert conn.cancel(queued.query_id)
+    assert conn.cancel(runaway.query_id)
+    with pytest.raises(aiolibsql.OperationalError, match="interrupted"):
+        await first
+    with pytest.raises(aiolibsql.OperationalError, match="interrupted"):
+        await second
+
+    assert conn.queries() == []
+    assert not conn.cancel(runaway.query_id)
+    cursor = await conn.execute("SELECT 2")
+    assert await cursor.fetchall() == [(2,)]
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_statement_queue():
+    conn = await aiolibsql.connect(":memory:", autocommit=1, max_queue=2)
+    slow = (
+        "WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c) "
+        "SELECT count(*) FROM (SELECT x FROM c LIMIT 1000000000)"
+    )
+    assert conn.queue_length == 0
+    runaway = conn.cursor()
+    first = asyncio.ensure_future(runaway.execute(slow, buffered=True))
+    await asyncio.sleep(0.05)
+    waiting = [asyncio.ensure_future(conn.execute("SELECT ?", (i,))) for i in range(2)]
+    await asyncio.sleep(0.05)
+    assert conn.queue_length == 2
+    with pytest.raises(aiolibsql.OperationalError, match="queued"):
+        await conn.execute("SELECT 3")
+
+    conn.cancel(runaway.query_id)
+    with pytest.raises(aiolibsql.OperationalError):
+        await first
+    # The queued statements run once the connection is free.
+    cursors = await asyncio.gather(*waiting)
+    assert [await c.fetchone() for c in cursors] == [(0,), (1,)]
+    assert conn.queue_length == 0
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_close_all(tmp_path):
+    import gc
+
+    await aiolibsql.close_all()
+    gc.collect()
+    path = str(tmp_path / "close_all.db")
+    done = await aiolibsql.connect(path)
+    await done.close()
+    writer = await aiolibsql.connect(path, autocommit=1)
+    await writer.execute("CREATE TABLE u (x)")
+    conn = await aiolibsql.connect(str(tmp_path / "tx.db"), autocommit=1)
+    await conn.execute("CREATE TABLE t (x)")
+    await conn.execute("BEGIN")
+    await conn.execute("INSERT INTO t VALUES (1)")
+    pool = await aiolibsql.create_pool(str(tmp_path / "pool.db"), size=2)
+    await pool.execute("CREATE TABLE t (x)")
+
+    running = asyncio.ensure_future(writer.execute(
+        "INSERT INTO u WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c WHERE x < 200000) "
+        "SELECT x FROM c"
+    ))
+    await asyncio.sleep(0)
+    await aiolibsql.close_all(timeout=5)
+    # The statement that was running finished before its connection closed, and the open
+    # transaction was rolled back.
+    assert (await running).rowcount == 200000
+    assert not conn.in_transaction
+    with pytest.raises(ValueError):
+        await conn.execute("SELECT 1")
+    with pytest.raises(ValueError):
+        await pool.execute("SELECT 1")
+    for db, table, count in (("close_all.db", "u", 200000), ("tx.db", "t", 0)):
+        check = await aiolibsql.connect(str(tmp_path / db))
+        cursor = await check.execute(f"SELECT count(*) FROM {table}")
+        assert await cursor.fetchone() == (count,)
+        await check.close()
+
+    # Past the timeout, running statements are interrupted.
+    conn = await aiolibsql.connect(":memory:")
+    runaway = asyncio.ensure_future(conn.execute(
+        "WITH RECURSIVE c(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM c) SELECT count(*) FROM c",
+        buffered=True,
+    ))
+    await asyncio.sleep(0.05)
+    await aiolibsql.close_all(timeout=0.05)
+    with pytest.raises(aiolibsql.OperationalError, match="interrupted"):
+        await runaway
+
+    for value in (-1, float("nan")):
+        with pytest.raises(ValueError, match="timeout"):
+            await aiolibsql.close_all(timeout=value)
+    pool = await aiolibsql.create_pool(str(tmp_path / "pool.db"), size=2)
+    with pytest.raises(ValueError, match="graceful_timeout"):
+        await pool.close(graceful_timeout=float("nan"))
+    await pool.close(graceful_timeout=1)
+    with pytest.raises(ValueError, match="closed"):
+        await pool.execute("INSERT INTO t VALUES (1)")
+
+
+@pytest.mark.asyncio
+async def test_user_version(tmp_path):
+    path = str(tmp_path / "version.db")
+    conn = await aiolibsql.connect(path)
+    assert await conn.user_version == 0
+    await conn.set_user_version(7)
+    assert await conn.user_version == 7
+    with pytest.raises(OverflowError):
+        await conn.set_user_version(2**31)
+    await conn.close()
+
+    from aiolibsql import sync
+
+    with sync.connect(path) as conn:
+        assert conn.user_version == 7
+        conn.user_version = 8
+        assert conn.user_version == 8
+
+
+@pytest.mark.asyncio
+async def test_bulk_progress():
+    conn = await aiolibsql.connect(":memory:")
+    await conn.execute("CREATE TABLE t (x)")
+    calls = []
+    await conn.executemany("INSERT INTO t VALUES (?)", [(i,) for i in range(250)],
+                           progress=lambda done, total: calls.append((done, total)))
+    assert calls == [(100, 250), (200, 250), (250, 250)]
+
+    calls.clear()
+    await conn.executemany("INSERT INTO t VALUES (?)", ((i,) for i in range(20)), progress_every=10,
+                           progress=lambda done, total: calls.append((done, total)))
+    assert calls == [(10, None), (20, None)]
+
+    calls.clear()
+    await conn.insert_many("t", [(i,) for i in range(5)], batch_size=2,
+                           progress=lambda done, total: calls.append((done, total)))
+    assert calls == [(2, 5), (4, 5), (5, 5)]
+
+    def abort(done, total):
+        raise RuntimeError("stop")
+
+    with pytest.raises(RuntimeError, match="stop"):
+        await conn.executemany("INSERT INTO t VALUES (?)", [(1,)] * 10, progress=abort, progress_every=5)
+    cursor = await conn.execute("SELECT count(*) FROM t")
+    assert await cursor.fetchone() == (275,)
+    await conn.close()
+
+
+@pytest.mark.asyncio
+async def test_backup(tmp_path):
+    source = await aiolibsql.connect(str(tmp_path / "source.db"))
+    await source.executescript("""
+        CREATE TABLE users (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT UNIQUE);
+        CREATE TABLE posts (user_id INTEGER REFERENCES users(id), body BLOB);
+        CREATE INDEX posts_user ON posts (user_id);
+        CREATE VIEW names AS SELECT name FROM users;
+        INSERT INTO users (name) VALUES ('ada'), ('alan');
+        INSERT INTO posts VALUES (1, x'00ff'), (2, NULL);
+        DELETE FROM users WHERE name = 'alan';
+    """)
+    await source.set_user_version(3)
+
+    target = await aiolibsql.connect(":memory:")
+    await target.execute("CREATE TABLE stale (x)")
+    assert await source.backup(target) == 3
+    cursor = await target.execute("SELECT * FROM posts ORDER BY user_id")
+    assert await cursor.fetchall() == [(1, b"\x00\xff"), (2, None)]
+    cursor = await target.execute("SELECT * FROM names")
+    assert await cursor.fetchall() == [("ada",)]
+    cursor = await target.execute("SELECT name FROM sqlite_schema WHERE name = 'stale' OR name = 'posts_user'")
+    assert await cursor.fetchall() == [("posts_user",)]
+    cursor = await target.execute("INSERT INTO users (name) VALUES ('grace') RETURNING id")
+    assert await cursor.fetchone() == (3,)
+    assert await target.user_version == 3
+
+    with pytest.raises(aiolibsql.ProgrammingError):
+        await source.backup(source)
+    await source.close()
+    await target.close()
+
+
+@pytest.mark.asyncio
+async def test_connect_without_create(tmp_path):
+    path = tmp_path / "missing.db"
+    with pytest.raises(aiolibsql.OperationalError):
+        conn = await aiolibsql.connect(str(path), create=False)
+        await conn.execute("SELECT 1")
+    assert not path.exists()
+
+    conn = await aiolibsql.connect(str(path))
+    await conn.close()
+    conn = await aiolibsql.connect(str(path), create=False)
+    await conn.execute("SELECT 1")
+    await conn.close()
+
+    with pytest.raises(ValueError, match="create"):
+        await aiolibsql.connect("libsql://example.turso.io", create=False)
+
+
+@pytest.mark.asyncio
+async def test_connect_fallback_urls():
+    with pytest.raises(ValueError, match="fallback_urls"):
+        await aiolibsql.connect(":memory:", fallback_urls=["http://127.0.0.1:1"])
+    with pytest.raises(ValueError, match="not remote"):
+        await aiolibsql.connect("http://127.0.0.1:1", fallback_urls=["backup.db"])
+    with pytest.raises(aiolibsql.Error):
+        await aiolibsql.connect(
+            "http://127.0.0.1:1", fallback_urls=["http://127.0.0.1:2"], http_connect_timeout=1
+        )
+
+
+@pytest.mark.asyncio
+async def test_execute_primary_needs_primary(conn):
+    with pytest.raises(ValueError, match="primary"):
+        await conn.execute("SELECT 1", primary=True)
+    cursor = await conn.execute("SELECT 1", primary=False)
+    assert await cursor.fetchone() == (1,)
+
+
+@pytest.mark.asyncio
+async def test_replication_index_local(conn):
+    cursor = await conn.execute("CREATE TABLE t (x)")
+    assert cursor.replication_index is None
+    with pytest.raises(ValueError, match="min_replication_index"):
+        await conn.execute("SELECT 1", min_replication_index=1)
+
+
+@pytest.mark.asyncio
+async def test_check_same_thread():
+    strict = await aiolibsql.connect(":memory:", check_same_thread=True)
+    shared = await aiolibsql.connect(":memory:")
+    cursor = await strict.execute("SELECT 1")
+
+    def use_from_thread(conn):
+        return asyncio.run(conn.execute("SELECT 2"))
+
+    loop = asyncio.get_running_loop()
+    with pytest.raises(aiolibsql.ProgrammingError, match="same thread"):
+        await loop.run_in_executor(None, use_from_thread, strict)
+    with pytest.raises(aiolibsql.ProgrammingError, match="same thread"):
+        await loop.run_in_executor(None, lambda: cursor.fetchone())
+    other = await loop.run_in_executor(None, use_from_thread, shared)
+    assert await other.fetchone() == (2,)
+    assert await cursor.fetchone() == (1,)
+    await strict.close()
+    await shared.close()
+
+
+@pytest.mark.asyncio
+async def test_check_same_thread_deprecated_alias():
+    with pytest.warns(DeprecationWarning, match="check_same_thread"):
+        conn = await aiolibsql.connect(":memory:", _check_same_thread=True)
+
+    def use_from_thread():
+        return asyncio.run(conn.execute("SELECT 1"))
+
+    loop = asyncio.get_running_loop()
+    with pytest.raises(aiolibsql.ProgrammingError, match="same thread"):
+        await loop.run_in_executor(None, use_from_thread)
+    await conn.close()
+
+
+@pytest.mark.skipif(not hasattr(os, "fork"), reason="needs os.fork()")
+@pytest.mark.asyncio
+async def test_fork_detection():
+    assert issubclass(aiolibsql.InterfaceError, aiolibsql.Error)
+    assert not issubclass(aiolibsql.InterfaceError, aiolibsql.DatabaseError)
+    assert issubclass(aiolibsql.DatabaseError, aiolibsql.Error)
+    conn = await aiolibsql.connect(":memory:")
+    cursor = await conn.execute("SELECT 1")
+    stmt = await conn.prepare("SELECT 2")
+    pid = os.fork()
+    if pid == 0:
+        calls = [
+            lambda: conn.execute("SELECT 1"),
+            cursor.fetchall,
+            cursor.fetchone,
+            lambda: stmt.query(),
+            conn.optimize,
+            conn.ping,
+            lambda: conn.user_version,
+            lambda: conn.in_transaction,
+            lambda: aiolibsql.connect(":memory:"),
+        ]
+        code = 0
+        for call in calls:
+            try:
+                call()
+                code = 1
+            except aiolibsql.InterfaceError:
+                pass
+        os._exit(code)
+    _, status = os.waitpid(pid, 0)
+    assert os.waitstatus_to_exitcode(status) == 0
+    assert await cursor.fetchone() == (1,)
+    assert await stmt.query() == [(2,)]
+    await conn.close()